@@ -1,28 +1,69 @@
 #![allow(unused_variables)]
 #![allow(unused)]
 
+use crate::cli::Args;
+use crate::config::{config_path, Config};
+use crate::debugger_window::DebuggerWindow;
 use crate::imgui_wgpu::Renderer;
+use crate::input::Input;
+use crate::layout::{Layout, UiMode};
+use crate::memory_window::MemoryWindow;
+use crate::nametable_window::NametableWindow;
 use crate::nes;
+use crate::oam_window::OamWindow;
+use crate::palette_window::PaletteWindow;
+use crate::rom_browser::{self, RomBrowser, MAX_RECENT_ROMS};
+use crate::video::cpu_filters;
+use crate::video::pipeline::ScreenPipeline;
+use crate::video::VideoOptions;
 use futures::executor::block_on;
 use glob::glob;
 use imgui::*;
 use imgui_winit_support;
+use nes::controller::{InputSetup, PortDevice};
+use nes::multitap::Multitap;
+use nes::ntsc::{NtscFilter, NTSC_WIDTH};
+use nes::palette::{NtscParams, Palette, PaletteSource};
+use nes::ppu_view::{pattern_table, PATTERN_TABLE_SIZE};
+use nes::region::Region;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::rc::Rc;
-use std::time::Instant;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use wgpu::{Device, Queue};
 use winit::{
-    dpi::LogicalSize,
+    dpi::{LogicalSize, PhysicalPosition},
     event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::Window,
+    window::{Fullscreen, Window},
 };
 
-fn find_roms() -> glob::Paths {
-    let exe_path = std::env::current_exe();
-    let rom_path = exe_path.unwrap().parent().unwrap().join("../../roms");
+fn find_roms(rom_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut roms = vec![];
+    for rom_dir in rom_dirs {
+        let pattern = rom_dir.join("**/*.nes");
+        match glob(&pattern.to_string_lossy()) {
+            Ok(paths) => roms.extend(paths.filter_map(Result::ok)),
+            Err(e) => println!("Bad ROM directory: '{0}'. Error: {1}", rom_dir.display(), e),
+        }
+    }
+    roms
+}
+
+// Palette files are put by the user next to the config
+fn palette_dir() -> Option<PathBuf> {
+    config_path("palettes")
+}
 
-    glob(rom_path.join("**/*.nes").to_str().unwrap()).unwrap()
+fn find_palettes() -> Vec<PathBuf> {
+    let pattern = match palette_dir() {
+        Some(dir) => dir.join("**/*.pal"),
+        None => return vec![],
+    };
+    match glob(&pattern.to_string_lossy()) {
+        Ok(paths) => paths.filter_map(Result::ok).collect(),
+        Err(_) => vec![],
+    }
 }
 
 fn to_rgb01(color: [i32; 4]) -> [f32; 4] {
@@ -34,14 +75,14 @@ fn to_rgb01(color: [i32; 4]) -> [f32; 4] {
     ]
 }
 
-struct UiTexture {
+pub struct UiTexture {
     size: (usize, usize),
     data: Vec<u8>,
-    texture_id: TextureId,
+    pub texture_id: TextureId,
 }
 
 impl UiTexture {
-    fn new(renderer: &mut Renderer, device: &Device, size: (usize, usize)) -> Self {
+    pub fn new(renderer: &mut Renderer, device: &Device, size: (usize, usize)) -> Self {
         let texture_id = renderer.create_texture(&device, size.0 as u32, size.1 as u32);
         UiTexture {
             size: size,
@@ -78,50 +119,203 @@ impl UiTexture {
     }
 }
 
+const SPRITE_BOX_COLOR: [f32; 4] = [0.2, 1.0, 0.2, 1.0];
+const TILE_HIGHLIGHT_COLOR: [f32; 4] = [1.0, 1.0, 0.2, 1.0];
+
 // Screen is used to store and update screen buffer and draw it as window with a texture
 struct ScreenBuffer {
-    ui_scale: f32,
-    texture: UiTexture,
+    video: VideoOptions,
+    pipeline: ScreenPipeline,
+    // Last frame shown, kept for screenshots
+    frame: cpu_filters::Image,
+    // Output has to be rendered again even without a new frame
+    dirty: bool,
+    // Composite video filter, its output is shown instead of the raw screen when enabled
+    ntsc_filter: Option<NtscFilter>,
+    // Area the picture is scaled to fit in player mode
+    fit_area: Option<[f32; 2]>,
 }
 
 impl ScreenBuffer {
-    fn new(renderer: &mut Renderer, device: &Device) -> Self {
+    fn new(device: &Device, video: VideoOptions) -> Self {
+        let (width, height) = nes::ppu::SCREEN_SIZE;
         ScreenBuffer {
-            ui_scale: 3.0_f32,
-            texture: UiTexture::new(renderer, device, nes::ppu::SCREEN_SIZE),
+            video,
+            pipeline: ScreenPipeline::new(device),
+            frame: cpu_filters::Image::new(width, height, vec![0; width * height]),
+            dirty: true,
+            ntsc_filter: None,
+            fit_area: None,
+        }
+    }
+
+    // Video options with the scale used for the current mode
+    fn display_options(&self) -> VideoOptions {
+        match self.fit_area {
+            Some(area) => self.video.fit(area),
+            None => self.video,
         }
     }
 
-    fn draw_ui(&mut self, ui: &imgui::Ui) {
+    fn set_fit_area(&mut self, area: Option<[f32; 2]>) {
+        if self.fit_area != area {
+            self.fit_area = area;
+            self.dirty = true;
+        }
+    }
+
+    // `sprite_boxes` are drawn over the picture, in NES pixels
+    fn draw_ui(&mut self, ui: &imgui::Ui, emulator: &mut nes::Emulator, sprite_boxes: &[[f32; 4]]) {
+        self.set_fit_area(None);
+
         // Screen window
         let window = imgui::Window::new(im_str!("Screen")).always_auto_resize(true);
         window
-            .position([370.0, 5.0], Condition::Once)
+            .position([370.0, 5.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                if let Some(texture_id) = self.pipeline.output() {
+                    let image_pos = ui.cursor_screen_pos();
+                    let (width, height) = self.video.output_size();
+                    Image::new(texture_id, [width as f32, height as f32]).build(&ui);
+                    self.update_zapper(
+                        ui,
+                        &self.video,
+                        image_pos,
+                        &mut emulator.zapper.borrow_mut(),
+                    );
+
+                    let draw_list = ui.get_window_draw_list();
+                    let to_screen = |x, y| {
+                        let pos = self.video.from_nes_pixel(x, y);
+                        [
+                            image_pos[0] + pos[0].max(0.0).min(width as f32),
+                            image_pos[1] + pos[1].max(0.0).min(height as f32),
+                        ]
+                    };
+                    for rect in sprite_boxes {
+                        draw_list
+                            .add_rect(
+                                to_screen(rect[0], rect[1]),
+                                to_screen(rect[2], rect[3]),
+                                SPRITE_BOX_COLOR,
+                            )
+                            .build();
+                    }
+                }
+
+                if self.video.draw_ui(&ui) {
+                    self.dirty = true;
+                }
+                let mut ntsc = self.ntsc_filter.is_some();
+                if ui.checkbox(im_str!("NTSC filter"), &mut ntsc) {
+                    self.ntsc_filter = if ntsc {
+                        Some(NtscFilter::new(NtscParams::default()))
+                    } else {
+                        None
+                    };
+                    self.dirty = true;
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Screenshot")) {
+                    self.save_screenshot();
+                }
+            });
+    }
+
+    // Game screen filling the whole window, without decorations
+    fn draw_player_ui(&mut self, ui: &imgui::Ui, emulator: &mut nes::Emulator) {
+        let display_size = ui.io().display_size;
+        self.set_fit_area(Some(display_size));
+        let options = self.display_options();
+
+        let window = imgui::Window::new(im_str!("Player"));
+        window
+            .position([0.0, 0.0], Condition::Always)
+            .size(display_size, Condition::Always)
+            .title_bar(false)
+            .resizable(false)
+            .movable(false)
+            .scroll_bar(false)
+            .scrollable(false)
+            .draw_background(false)
+            .save_settings(false)
+            .bring_to_front_on_focus(false)
             .build(&ui, || {
-                Image::new(
-                    self.texture.texture_id,
-                    self.texture.get_size(self.ui_scale),
-                )
-                .build(&ui);
-                ui.drag_float(im_str!("Scale"), &mut self.ui_scale).build();
+                if let Some(texture_id) = self.pipeline.output() {
+                    let (width, height) = options.output_size();
+                    ui.set_cursor_pos([
+                        ((display_size[0] - width as f32) / 2.0).floor(),
+                        ((display_size[1] - height as f32) / 2.0).floor(),
+                    ]);
+                    let image_pos = ui.cursor_screen_pos();
+                    Image::new(texture_id, [width as f32, height as f32]).build(&ui);
+                    self.update_zapper(ui, &options, image_pos, &mut emulator.zapper.borrow_mut());
+                }
             });
     }
 
+    // Aim with the mouse over the screen image, left click pulls the trigger,
+    // right click in the screen window shoots away from the screen (reload in most games)
+    fn update_zapper(
+        &self,
+        ui: &imgui::Ui,
+        options: &VideoOptions,
+        image_pos: [f32; 2],
+        zapper: &mut nes::zapper::Zapper,
+    ) {
+        let hovered = ui.is_item_hovered();
+        let mouse_pos = ui.io().mouse_pos;
+        let shoot_away = ui.is_window_hovered() && ui.is_mouse_down(MouseButton::Right);
+
+        zapper.aim = if hovered && !shoot_away {
+            Some(options.to_nes_pixel([mouse_pos[0] - image_pos[0], mouse_pos[1] - image_pos[1]]))
+        } else {
+            None
+        };
+        zapper.trigger = (hovered && ui.is_mouse_down(MouseButton::Left)) || shoot_away;
+    }
+
+    // Saves the last frame with the CPU version of the upscaler
+    fn save_screenshot(&self) {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let path = PathBuf::from(format!("screenshot-{}.png", time.as_secs()));
+        cpu_filters::apply(&self.video, &self.frame).save_png(&path);
+    }
+
     fn update(
         &mut self,
         ppu: &nes::ppu::Ppu,
+        frame_ready: bool,
         renderer: &mut Renderer,
         device: &Device,
         queue: &mut Queue,
     ) {
-        // Update pixels in screen buffer from ppu's screen
-        for x in 0..self.texture.size.0 {
-            for y in 0..self.texture.size.1 {
-                self.texture.set_pixel(x, y, ppu.screen.get_pixel(x, y));
-            }
+        if !frame_ready && !self.dirty {
+            return;
         }
 
-        self.texture.update(renderer, device, queue);
+        self.frame = if let Some(filter) = &mut self.ntsc_filter {
+            filter.apply(&ppu.screen);
+            cpu_filters::Image::new(
+                NTSC_WIDTH,
+                nes::ppu::SCREEN_SIZE.1,
+                filter.output().to_vec(),
+            )
+        } else {
+            let (width, height) = nes::ppu::SCREEN_SIZE;
+            cpu_filters::Image::new(width, height, ppu.screen.rgb().to_vec())
+        };
+
+        self.pipeline.upload(
+            device,
+            queue,
+            &self.frame.pixels,
+            self.frame.width,
+            self.frame.height,
+        );
+        self.pipeline
+            .render(renderer, device, queue, &self.display_options());
+        self.dirty = false;
     }
 }
 
@@ -139,20 +333,63 @@ impl StateWindow {
         }
     }
 
-    fn draw_ui(&mut self, ui: &imgui::Ui, emulator: &mut nes::Emulator) {
+    // `highlighted_tiles` are pattern table addresses of tiles to outline
+    fn draw_ui(&mut self, ui: &imgui::Ui, emulator: &mut nes::Emulator, highlighted_tiles: &[u16]) {
         // Window with CPU state
         let window = imgui::Window::new(im_str!("State"));
         window
-            .size([425.0, 600.0], Condition::Once)
-            .position([1170.0, 5.0], Condition::Once)
+            .size([425.0, 600.0], Condition::FirstUseEver)
+            .position([1170.0, 5.0], Condition::FirstUseEver)
             .build(&ui, || {
                 ui.text(format!("FPS: {:.0}", emulator.frame_time.fps));
                 ui.text(format!("Total Clocks: {}", emulator.clock));
                 ui.text(format!("PC: {:#X}", emulator.cpu.PC));
 
+                // Region selection, "Auto" takes it from the ROM
+                let mut region_override = emulator.region_override;
+                ui.radio_button(im_str!("Auto"), &mut region_override, None);
+                for region in Region::ALL.iter() {
+                    ui.same_line(0.0);
+                    ui.radio_button(
+                        &im_str!("{}", region.name()),
+                        &mut region_override,
+                        Some(*region),
+                    );
+                }
+                if region_override != emulator.region_override {
+                    emulator.set_region_override(region_override);
+                }
+                ui.text(format!(
+                    "Region: {} ({:.2} Hz)",
+                    emulator.region.name(),
+                    emulator.region.frame_rate()
+                ));
+
+                let scale = 1.5;
                 for i in 0..2 {
-                    Image::new(self.textures[i].texture_id, self.textures[i].get_size(1.5))
-                        .build(&ui);
+                    let image_pos = ui.cursor_screen_pos();
+                    Image::new(
+                        self.textures[i].texture_id,
+                        self.textures[i].get_size(scale),
+                    )
+                    .build(&ui);
+
+                    let draw_list = ui.get_window_draw_list();
+                    for &addr in highlighted_tiles {
+                        if (addr / 0x1000) as usize != i {
+                            continue;
+                        }
+                        let tile = (addr & 0x0FFF) / 16;
+                        let x = image_pos[0] + (tile % 16) as f32 * 8.0 * scale;
+                        let y = image_pos[1] + (tile / 16) as f32 * 8.0 * scale;
+                        draw_list
+                            .add_rect(
+                                [x, y],
+                                [x + 8.0 * scale, y + 8.0 * scale],
+                                TILE_HIGHLIGHT_COLOR,
+                            )
+                            .build();
+                    }
                     ui.same_line(0.0);
                 }
             });
@@ -160,38 +397,18 @@ impl StateWindow {
 
     fn update(
         &mut self,
-        ppu: &mut nes::ppu::Ppu,
+        ppu: &nes::ppu::Ppu,
+        palette: u8,
         renderer: &mut Renderer,
         device: &Device,
         queue: &mut Queue,
     ) {
-        let palette = 0;
-
-        // Get and draw CHR ROM
+        // Get and draw CHR ROM with the palette chosen in the palette window
+        let width = PATTERN_TABLE_SIZE.0;
         for i in 0..2 as usize {
-            for tile_y in 0..16 {
-                for tile_x in 0..16 {
-                    let offset = tile_y * 256 + tile_x * 16;
-                    for row in 0..8 {
-                        let mut tile_lsb =
-                            ppu.ppu_read((i as u16) * 0x1000 + offset + row + 0x0000);
-                        let mut tile_msb =
-                            ppu.ppu_read((i as u16) * 0x1000 + offset + row + 0x0008);
-
-                        for col in 0..8 {
-                            let pixel = (tile_msb & 0x01) << 1 | (tile_lsb & 0x01);
-
-                            tile_lsb >>= 1;
-                            tile_msb >>= 1;
-
-                            self.textures[i].set_pixel(
-                                (tile_x * 8 + (7 - col)) as usize,
-                                (tile_y * 8 + row) as usize,
-                                ppu.get_color_from_pal(palette, pixel),
-                            );
-                        }
-                    }
-                }
+            let pixels = pattern_table(ppu, i as u16 * 0x1000, palette);
+            for (j, &color) in pixels.iter().enumerate() {
+                self.textures[i].set_pixel(j % width, j / width, ppu.palette.get_color(color));
             }
             self.textures[i].update(renderer, device, queue);
         }
@@ -199,31 +416,290 @@ impl StateWindow {
 }
 
 pub struct NESApp {
+    // Config file with command line overrides applied
+    config: Config,
+    args: Args,
+    // Directory typed in settings to add to ROM directories
+    new_rom_dir: ImString,
     rom_files: Vec<PathBuf>,
+    current_rom: Option<PathBuf>,
+    input: Input,
+    palette_files: Vec<PathBuf>,
+    palette_source: PaletteSource,
     emulator: nes::Emulator,
+    layout: Layout,
+    // Overlay menu shown over the game in player mode
+    show_menu: bool,
+    quit: bool,
 }
 
 impl NESApp {
-    pub fn new() -> Self {
-        let roms = find_roms().map(|res| res.unwrap()).collect();
+    pub fn new(config: Config, args: Args) -> Self {
+        let mut emulator = nes::Emulator::new();
+        emulator.set_region_override(config.region);
+        if let Some(palette) = Palette::from_source(&config.palette) {
+            emulator.ppu.borrow_mut().palette = palette;
+        }
 
         NESApp {
-            rom_files: roms,
-            emulator: nes::Emulator::new(),
+            rom_files: find_roms(&config.rom_dirs),
+            new_rom_dir: ImString::with_capacity(256),
+            current_rom: None,
+            input: Input::new(config.input.clone()),
+            palette_files: find_palettes(),
+            palette_source: config.palette.clone(),
+            config,
+            args,
+            emulator,
+            layout: Layout::load(),
+            show_menu: false,
+            quit: false,
         }
     }
 
-    fn draw_ui(&mut self, ui: &imgui::Ui) {
-        // Window with list of ROMs
-        let window = imgui::Window::new(im_str!("ROMs"));
+    // Overlay menu of the player mode, the game is paused while it's shown
+    fn draw_menu(&mut self, ui: &imgui::Ui, screen: &mut ScreenBuffer) {
+        let display_size = ui.io().display_size;
+        let size = [420.0, 480.0];
+        let window = imgui::Window::new(im_str!("Menu"));
         window
-            .size([350.0, 600.0], Condition::Once)
-            .position([5.0, 5.0], Condition::Once)
+            .size(size, Condition::Always)
+            .position(
+                [
+                    ((display_size[0] - size[0]) / 2.0).max(0.0),
+                    ((display_size[1] - size[1]) / 2.0).max(0.0),
+                ],
+                Condition::Always,
+            )
+            .collapsible(false)
+            .resizable(false)
+            .movable(false)
+            .save_settings(false)
             .build(&ui, || {
+                if ui.button(im_str!("Resume"), [0.0, 0.0]) {
+                    self.show_menu = false;
+                }
+                ui.same_line(0.0);
+                if ui.button(im_str!("Debug mode"), [0.0, 0.0]) {
+                    self.layout.mode = UiMode::Debug;
+                    self.show_menu = false;
+                }
+                ui.same_line(0.0);
+                if ui.button(im_str!("Quit"), [0.0, 0.0]) {
+                    self.quit = true;
+                }
+                ui.checkbox(im_str!("Fullscreen"), &mut self.layout.fullscreen);
+                ui.text(im_str!("F1: menu, F2: debug mode, F11: fullscreen"));
+
+                ui.separator();
+                if screen.video.draw_ui(&ui) {
+                    screen.dirty = true;
+                }
+
+                ui.separator();
+                let mut selected = None;
                 for rom_file in &self.rom_files {
                     let filename = ImString::new(rom_file.file_name().unwrap().to_str().unwrap());
-                    if ui.button(&filename, [0 as f32, 0 as f32]) {
-                        self.emulator.load_rom(rom_file);
+                    let current = self.current_rom.as_ref() == Some(rom_file);
+                    if Selectable::new(&filename).selected(current).build(&ui) {
+                        selected = Some(rom_file.clone());
+                    }
+                }
+                if let Some(rom_file) = selected {
+                    self.load_rom(rom_file);
+                    self.show_menu = false;
+                }
+            });
+    }
+
+    fn draw_settings(&mut self, ui: &imgui::Ui, screen: &ScreenBuffer) {
+        let window = imgui::Window::new(im_str!("Settings"));
+        window
+            .size([350.0, 320.0], Condition::FirstUseEver)
+            .position([5.0, 635.0], Condition::FirstUseEver)
+            .collapsed(true, Condition::FirstUseEver)
+            .build(&ui, || {
+                ui.text(im_str!("ROM directories:"));
+                let mut dirs_changed = false;
+                let mut removed = None;
+                for (i, rom_dir) in self.config.rom_dirs.iter().enumerate() {
+                    if ui.small_button(&im_str!("Remove##{}", i)) {
+                        removed = Some(i);
+                    }
+                    ui.same_line(0.0);
+                    ui.text(rom_dir.display().to_string());
+                }
+                if let Some(i) = removed {
+                    self.config.rom_dirs.remove(i);
+                    dirs_changed = true;
+                }
+                ui.input_text(im_str!("##new_rom_dir"), &mut self.new_rom_dir)
+                    .build();
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Add")) && !self.new_rom_dir.to_str().is_empty() {
+                    let rom_dir = PathBuf::from(self.new_rom_dir.to_str());
+                    self.config.rom_dirs.push(rom_dir);
+                    self.new_rom_dir.clear();
+                    dirs_changed = true;
+                }
+                if dirs_changed {
+                    self.rom_files = find_roms(&self.config.rom_dirs);
+                }
+
+                ui.separator();
+                ui.text_wrapped(im_str!(
+                    "Region, palette, video and input are set in their windows and saved too."
+                ));
+                if ui.button(im_str!("Save"), [0.0, 0.0]) {
+                    self.save_config(screen.video);
+                }
+            });
+    }
+
+    // Saves settings from all windows to the config file, values from the command line
+    // are only saved when they were changed here
+    fn save_config(&mut self, video: VideoOptions) {
+        self.config.region = self.emulator.region_override;
+        self.config.palette = self.palette_source.clone();
+        self.config.video = video;
+        self.config.input = self.input.config.clone();
+
+        let mut config = self.config.clone();
+        self.args.unapply(&mut config, &Config::load());
+        config.save();
+    }
+
+    // Bindings are saved as soon as they change, leaving other settings in the file as they are
+    fn save_input_config(&self) {
+        let mut config = Config::load();
+        config.input = self.input.config.clone();
+        config.save();
+    }
+
+    // Same for the input setup of a ROM
+    fn save_rom_input(&self, rom_file: &PathBuf, setup: InputSetup) {
+        let mut config = Config::load();
+        config.rom_inputs.insert(rom_file.clone(), setup);
+        config.save();
+    }
+
+    fn toggle_mode(&mut self) {
+        self.layout.mode = match self.layout.mode {
+            UiMode::Player => UiMode::Debug,
+            UiMode::Debug => UiMode::Player,
+        };
+        self.show_menu = false;
+    }
+
+    fn draw_ui(&mut self, ui: &imgui::Ui, screen: &mut ScreenBuffer) {
+        // Input window
+        let window = imgui::Window::new(im_str!("Input"));
+        window
+            .size([350.0, 420.0], Condition::FirstUseEver)
+            .position([5.0, 610.0], Condition::FirstUseEver)
+            .collapsed(true, Condition::FirstUseEver)
+            .build(&ui, || {
+                let mut setup = self.emulator.input_setup;
+                ui.text(im_str!("Players 3 and 4:"));
+                ui.radio_button(im_str!("None"), &mut setup.multitap, Multitap::None);
+                ui.same_line(0.0);
+                ui.radio_button(
+                    im_str!("Four Score"),
+                    &mut setup.multitap,
+                    Multitap::FourScore,
+                );
+                ui.same_line(0.0);
+                ui.radio_button(
+                    im_str!("Famicom"),
+                    &mut setup.multitap,
+                    Multitap::FamicomExpansion,
+                );
+
+                let mut zapper = setup.port_devices[1] == PortDevice::Zapper;
+                ui.checkbox(im_str!("Zapper on port 2"), &mut zapper);
+                setup.port_devices[1] = if zapper {
+                    PortDevice::Zapper
+                } else {
+                    PortDevice::Controller
+                };
+
+                if setup != self.emulator.input_setup {
+                    self.emulator.set_input_setup(setup);
+                    if let Some(rom_file) = &self.current_rom {
+                        self.config.rom_inputs.insert(rom_file.clone(), setup);
+                        self.save_rom_input(rom_file, setup);
+                    }
+                }
+
+                ui.separator();
+                self.input.draw_ui(&ui);
+            });
+
+        // Video window
+        let window = imgui::Window::new(im_str!("Video"));
+        window
+            .size([425.0, 300.0], Condition::FirstUseEver)
+            .position([1170.0, 610.0], Condition::FirstUseEver)
+            .collapsed(true, Condition::FirstUseEver)
+            .build(&ui, || {
+                let mut source = self.palette_source.clone();
+                ui.text(im_str!("Palette:"));
+                if ui.radio_button_bool(im_str!("Default"), source == PaletteSource::Default) {
+                    source = PaletteSource::Default;
+                }
+                let is_ntsc = matches!(source, PaletteSource::Ntsc(_));
+                if ui.radio_button_bool(im_str!("NTSC"), is_ntsc) && !is_ntsc {
+                    source = PaletteSource::Ntsc(NtscParams::default());
+                }
+                if let PaletteSource::Ntsc(params) = &mut source {
+                    ui.indent();
+                    ui.drag_float(im_str!("Hue"), &mut params.hue)
+                        .min(-180.0)
+                        .max(180.0)
+                        .build();
+                    for (label, value) in [
+                        (im_str!("Saturation"), &mut params.saturation),
+                        (im_str!("Contrast"), &mut params.contrast),
+                        (im_str!("Brightness"), &mut params.brightness),
+                    ]
+                    .iter_mut()
+                    {
+                        ui.drag_float(label, value)
+                            .min(0.0)
+                            .max(2.0)
+                            .speed(0.01)
+                            .build();
+                    }
+                    ui.drag_float(im_str!("Gamma"), &mut params.gamma)
+                        .min(1.0)
+                        .max(3.0)
+                        .speed(0.01)
+                        .build();
+                    if ui.small_button(im_str!("Reset")) {
+                        *params = NtscParams::default();
+                    }
+                    ui.unindent();
+                }
+                for pal_file in &self.palette_files {
+                    let filename = ImString::new(pal_file.file_name().unwrap().to_str().unwrap());
+                    let selected = source == PaletteSource::File(pal_file.clone());
+                    if ui.radio_button_bool(&filename, selected) {
+                        source = PaletteSource::File(pal_file.clone());
+                    }
+                }
+                if let Some(dir) = palette_dir() {
+                    ui.text_disabled(format!(".pal files from {}", dir.display()));
+                }
+
+                // A file which fails to load leaves the current palette selected
+                if source != self.palette_source {
+                    if let Some(palette) = Palette::from_source(&source) {
+                        let mut ppu = self.emulator.ppu.borrow_mut();
+                        ppu.screen.update_rgb(&palette);
+                        ppu.palette = palette;
+                        // Re-upload the recolored frame without touching PPU state
+                        screen.dirty = true;
+                        self.palette_source = source;
                     }
                 }
             });
@@ -231,12 +707,14 @@ impl NESApp {
         // Help Window
         let window = imgui::Window::new(im_str!("Help"));
         window
-            .size([350.0, 160.0], Condition::Once)
-            .position([5.0, 660.0], Condition::Once)
+            .size([350.0, 180.0], Condition::FirstUseEver)
+            .position([5.0, 660.0], Condition::FirstUseEver)
             .build(&ui, || {
                 ui.text(im_str!(
-                    "Select ROM file, to control use keys:\nA,S,Z,X,\nArrow Keys\n\nHave fun!"
+                    "Select ROM file, to control use keys:\nA,S,Z,X,\nArrow Keys\n"
                 ));
+                ui.text(im_str!("or gamepad (see Input)\nZapper: mouse"));
+                ui.text(im_str!("F2: player mode, F11: fullscreen\n\nHave fun!"));
             });
 
         // Test Logger
@@ -245,32 +723,44 @@ impl NESApp {
 
         let window = imgui::Window::new(im_str!("Test Logger"));
         window
-            .size([425.0, 160.0], Condition::Once)
-            .position([1170.0, 660.0], Condition::Once)
+            .size([425.0, 160.0], Condition::FirstUseEver)
+            .position([1170.0, 660.0], Condition::FirstUseEver)
             .build(&ui, || {
                 ui.text(format!("{:?}", logger.bytes[0]));
                 ui.text(log_txt.clone());
             });
     }
 
-    fn set_key_state(&mut self, code: VirtualKeyCode, state: bool) {
-        let b = match code {
-            VirtualKeyCode::X => 0x80,
-            VirtualKeyCode::Z => 0x40,
-            VirtualKeyCode::A => 0x20,
-            VirtualKeyCode::S => 0x10,
-            VirtualKeyCode::Up => 0x08,
-            VirtualKeyCode::Down => 0x04,
-            VirtualKeyCode::Left => 0x02,
-            VirtualKeyCode::Right => 0x01,
-            _ => return,
-        };
+    /// Starts the ROM and tools given on the command line.
+    pub fn start(&mut self) {
+        if let Some(rom_file) = self.args.rom.clone() {
+            self.load_rom(rom_file);
+        }
+        self.args.start(&mut self.emulator);
+    }
 
-        let mut controller = self.emulator.controllers[0].borrow_mut();
-        if state {
-            controller.input |= b;
-        } else {
-            controller.input &= !b;
+    fn load_rom(&mut self, rom_file: PathBuf) {
+        self.save_thumbnail();
+        if let Err(e) = self.emulator.load_rom(&rom_file) {
+            println!("{}", e);
+            return;
+        }
+        if let Some(setup) = self.config.rom_inputs.get(&rom_file) {
+            self.emulator.set_input_setup(*setup);
+        }
+
+        let recent = &mut self.layout.recent_roms;
+        recent.retain(|rom| *rom != rom_file);
+        recent.insert(0, rom_file.clone());
+        recent.truncate(MAX_RECENT_ROMS);
+
+        self.current_rom = Some(rom_file);
+    }
+
+    // Keeps the last screen of the current ROM for the ROM browser
+    fn save_thumbnail(&self) {
+        if let Some(rom_file) = &self.current_rom {
+            rom_browser::save_thumbnail(rom_file, self.emulator.ppu.borrow().screen.rgb());
         }
     }
 
@@ -280,10 +770,17 @@ impl NESApp {
         let mut hidpi_factor = 1.0;
         let (window, mut size, surface) = {
             let window = Window::new(&event_loop).unwrap();
+            let layout = &self.layout;
             window.set_inner_size(LogicalSize {
-                width: 1600.0,
-                height: 900.0,
+                width: layout.window_size.0,
+                height: layout.window_size.1,
             });
+            if let Some((x, y)) = layout.window_pos {
+                window.set_outer_position(PhysicalPosition { x, y });
+            }
+            if layout.fullscreen {
+                window.set_fullscreen(Some(Fullscreen::Borderless(window.current_monitor())));
+            }
             window.set_title("nes-rust");
             let size = window.inner_size();
 
@@ -327,7 +824,11 @@ impl NESApp {
             &window,
             imgui_winit_support::HiDpiMode::Default,
         );
+        // Window layout is saved together with the rest of the layout on exit
         imgui.set_ini_filename(None);
+        if let Some(ini) = Layout::load_imgui_ini() {
+            imgui.load_ini_settings(&ini);
+        }
 
         let font_size = (13.0 * hidpi_factor) as f32;
         imgui.io_mut().font_global_scale = (1.0 / hidpi_factor) as f32;
@@ -385,8 +886,18 @@ impl NESApp {
 
         let mut last_frame = Instant::now();
 
-        let mut screen = ScreenBuffer::new(&mut renderer, &device);
+        let mut screen = ScreenBuffer::new(&device, self.config.video);
         let mut state_win = StateWindow::new(&mut renderer, &device);
+        let mut debugger_win = DebuggerWindow::new();
+        let mut memory_win = MemoryWindow::new();
+        let mut nametable_win = NametableWindow::new(&mut renderer, &device);
+        let mut oam_win = OamWindow::new(&mut renderer, &device);
+        let mut palette_win = PaletteWindow::new();
+        let browser_dir = match self.config.rom_dirs.first() {
+            Some(dir) => dir.clone(),
+            None => PathBuf::from("."),
+        };
+        let mut browser = RomBrowser::new(&mut renderer, &device, browser_dir);
 
         let mut last_cursor = None;
 
@@ -406,11 +917,23 @@ impl NESApp {
                 } => {
                     hidpi_factor = scale_factor;
                 }
+                Event::WindowEvent {
+                    event: WindowEvent::Moved(position),
+                    ..
+                } => {
+                    if window.fullscreen().is_none() {
+                        self_mut.layout.window_pos = Some((position.x, position.y));
+                    }
+                }
                 Event::WindowEvent {
                     event: WindowEvent::Resized(_),
                     ..
                 } => {
                     size = window.inner_size();
+                    if window.fullscreen().is_none() {
+                        let logical = size.to_logical::<f64>(window.scale_factor());
+                        self_mut.layout.window_size = (logical.width, logical.height);
+                    }
 
                     sc_desc = wgpu::SwapChainDescriptor {
                         usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
@@ -422,6 +945,22 @@ impl NESApp {
 
                     swap_chain = device.create_swap_chain(&surface, &sc_desc);
                 }
+                // Esc cancels binding or leaves the text field instead of quitting
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    virtual_keycode: Some(VirtualKeyCode::Escape),
+                                    state: ElementState::Pressed,
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } if self_mut.input.is_rebinding() || imgui.io().want_capture_keyboard => {
+                    self_mut.input.cancel_rebinding();
+                }
                 Event::WindowEvent {
                     event:
                         WindowEvent::KeyboardInput {
@@ -454,11 +993,47 @@ impl NESApp {
                         },
                     ..
                 } => {
-                    self_mut.set_key_state(virtual_keycode, state == ElementState::Pressed);
+                    let pressed = state == ElementState::Pressed;
+                    // Keys typed into text fields are not for the game, releases still are
+                    // so no button stays held
+                    if !(pressed && imgui.io().want_capture_keyboard) {
+                        match virtual_keycode {
+                            VirtualKeyCode::F1
+                                if pressed && self_mut.layout.mode == UiMode::Player =>
+                            {
+                                self_mut.show_menu = !self_mut.show_menu;
+                            }
+                            VirtualKeyCode::F2 if pressed => self_mut.toggle_mode(),
+                            VirtualKeyCode::F11 if pressed => {
+                                self_mut.layout.fullscreen = !self_mut.layout.fullscreen;
+                            }
+                            _ => (),
+                        }
+                        self_mut.input.set_key_state(virtual_keycode, pressed);
+                    }
                 }
                 Event::MainEventsCleared => {
+                    if self_mut.quit {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    if self_mut.layout.fullscreen != window.fullscreen().is_some() {
+                        window.set_fullscreen(if self_mut.layout.fullscreen {
+                            Some(Fullscreen::Borderless(window.current_monitor()))
+                        } else {
+                            None
+                        });
+                    }
                     window.request_redraw();
                 }
+                Event::LoopDestroyed => {
+                    // Flushes the CPU log
+                    self_mut.emulator.stop_trace();
+                    self_mut.save_thumbnail();
+                    self_mut.layout.save();
+                    let mut ini = String::new();
+                    imgui.save_ini_settings(&mut ini);
+                    Layout::save_imgui_ini(&ini);
+                }
                 Event::RedrawEventsCleared => {
                     last_frame = imgui.io_mut().update_delta_time(last_frame);
 
@@ -475,26 +1050,69 @@ impl NESApp {
                     let ui = imgui.frame();
 
                     // Run emulator update
-                    self_mut.emulator.update(ui.io().delta_time);
+                    let player_mode = self_mut.layout.mode == UiMode::Player;
+                    if !(player_mode && self_mut.show_menu) {
+                        self_mut
+                            .input
+                            .update(ui.io().delta_time, &mut self_mut.emulator);
+                        self_mut.emulator.update(ui.io().delta_time);
+                    }
 
                     {
                         // Read and update screen buffer if changed:
                         let mut ppu = self_mut.emulator.ppu.borrow_mut();
-                        if ppu.screen.complete {
-                            ppu.screen.complete = false;
-                            screen.update(&ppu, &mut renderer, &device, &mut queue);
-                        }
+                        let frame_ready = ppu.screen.complete;
+                        ppu.screen.complete = false;
+                        screen.update(&ppu, frame_ready, &mut renderer, &device, &mut queue);
 
                         // Read and update state textures
-                        state_win.update(&mut ppu, &mut renderer, &device, &mut queue);
+                        state_win.update(
+                            &ppu,
+                            palette_win.selected,
+                            &mut renderer,
+                            &device,
+                            &mut queue,
+                        );
                     }
+                    if !player_mode {
+                        nametable_win.update(
+                            &mut self_mut.emulator,
+                            &mut renderer,
+                            &device,
+                            &mut queue,
+                        );
+                        oam_win.update(&self_mut.emulator, &mut renderer, &device, &mut queue);
+                    }
+                    browser.update(&mut renderer, &device, &mut queue);
 
-                    // Draw actual app UI
-                    self_mut.draw_ui(&ui);
-                    // Draw screen window
-                    screen.draw_ui(&ui);
-                    // Draw state window
-                    state_win.draw_ui(&ui, &mut self_mut.emulator);
+                    if player_mode {
+                        // Only the game, tool windows keep their layout for debug mode
+                        screen.draw_player_ui(&ui, &mut self_mut.emulator);
+                        if self_mut.show_menu {
+                            self_mut.draw_menu(&ui, &mut screen);
+                        }
+                    } else {
+                        // Draw actual app UI
+                        let rom_dirs = &self_mut.config.rom_dirs;
+                        let recent = &self_mut.layout.recent_roms;
+                        if let Some(rom_file) = browser.draw_ui(&ui, rom_dirs, recent) {
+                            self_mut.load_rom(rom_file);
+                        }
+                        self_mut.draw_ui(&ui, &mut screen);
+                        // Draw screen window
+                        screen.draw_ui(&ui, &mut self_mut.emulator, &oam_win.screen_boxes());
+                        // Draw state window
+                        state_win.draw_ui(&ui, &mut self_mut.emulator, &oam_win.hovered_tiles);
+                        debugger_win.draw_ui(&ui, &mut self_mut.emulator);
+                        memory_win.draw_ui(&ui, &mut self_mut.emulator);
+                        nametable_win.draw_ui(&ui, &self_mut.emulator);
+                        oam_win.draw_ui(&ui);
+                        palette_win.draw_ui(&ui, &self_mut.emulator);
+                        self_mut.draw_settings(&ui, &screen);
+                    }
+                    if self_mut.input.take_config_changed() {
+                        self_mut.save_input_config();
+                    }
 
                     let mut encoder: wgpu::CommandEncoder = device
                         .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
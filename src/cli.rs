@@ -0,0 +1,309 @@
+use crate::config::Config;
+use crate::nes::debugger::Condition;
+use crate::nes::movie::Movie;
+use crate::nes::region::Region;
+use crate::nes::trace::{self, TraceFormat, TraceOptions};
+use crate::nes::Emulator;
+use clap::{value_t_or_exit, App, Arg, ArgMatches, Error, ErrorKind};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// Command line arguments, values given here override the config file.
+pub struct Args {
+    /// ROM to start right away.
+    pub rom: Option<PathBuf>,
+    pub rom_dirs: Vec<PathBuf>,
+    pub region: Option<Region>,
+    pub scale: Option<f32>,
+    /// Run without window for `frames` frames.
+    pub headless: bool,
+    pub frames: u32,
+    /// Where to save the last frame in headless mode.
+    pub screenshot: Option<PathBuf>,
+    pub movie: Option<PathBuf>,
+    pub log_cpu: Option<PathBuf>,
+    /// Format and filters of the CPU log.
+    pub trace: TraceOptions,
+    /// Symbol files loaded in addition to the ones found next to the ROM.
+    pub symbols: Vec<PathBuf>,
+    /// Where to write PRG-ROM disassembly.
+    pub dump_asm: Option<PathBuf>,
+}
+
+// Exits with the usage error clap shows for values rejected by validators
+fn invalid_value(message: String) -> ! {
+    Error::with_description(&message, ErrorKind::InvalidValue).exit()
+}
+
+fn is_scale(value: String) -> Result<(), String> {
+    match value.parse::<f32>() {
+        Ok(scale) if scale.is_finite() && scale > 0.0 => Ok(()),
+        _ => Err(format!("'{}' is not a positive number", value)),
+    }
+}
+
+fn is_count(value: String) -> Result<(), String> {
+    value
+        .parse::<u32>()
+        .map(|_| ())
+        .map_err(|_| format!("'{}' is not a positive integer", value))
+}
+
+fn is_addr_range(value: String) -> Result<(), String> {
+    trace::parse_range(&value, true).map(|_| ())
+}
+
+fn is_frame_range(value: String) -> Result<(), String> {
+    trace::parse_range(&value, false).map(|_| ())
+}
+
+fn is_condition(value: String) -> Result<(), String> {
+    Condition::parse(&value).map(|_| ())
+}
+
+// Same checks as the debugger's trace settings, errors exit instead of tracing everything
+fn trace_options(matches: &ArgMatches) -> Result<TraceOptions, String> {
+    let addr_range = match matches.value_of("trace-range") {
+        Some(range) => {
+            trace::parse_range(range, true)?.map(|(first, last)| (first as u16, last as u16))
+        }
+        None => None,
+    };
+    let frame_range = match matches.value_of("trace-frames") {
+        Some(range) => trace::parse_range(range, false)?,
+        None => None,
+    };
+    let condition = match matches.value_of("trace-if") {
+        Some(condition) => Some(Condition::parse(condition)?),
+        None => None,
+    };
+    Ok(TraceOptions {
+        format: matches
+            .value_of("trace-format")
+            .and_then(TraceFormat::from_name)
+            .unwrap_or_default(),
+        show_bank: matches.is_present("trace-bank"),
+        addr_range,
+        frame_range,
+        condition,
+    })
+}
+
+impl Args {
+    pub fn parse() -> Self {
+        let matches = App::new("nes-rust")
+            .about("NES emulator")
+            .arg(Arg::with_name("ROM").index(1).help("ROM file to start"))
+            .arg(
+                Arg::with_name("rom-dir")
+                    .long("rom-dir")
+                    .value_name("DIR")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Directory with ROMs, can be given several times"),
+            )
+            .arg(
+                Arg::with_name("region")
+                    .long("region")
+                    .value_name("REGION")
+                    .possible_values(&["ntsc", "pal", "dendy"])
+                    .case_insensitive(true)
+                    .help("Region used for all ROMs instead of detecting it"),
+            )
+            .arg(
+                Arg::with_name("scale")
+                    .long("scale")
+                    .value_name("SCALE")
+                    .validator(is_scale)
+                    .help("Screen scale"),
+            )
+            .arg(
+                Arg::with_name("headless")
+                    .long("headless")
+                    .requires("ROM")
+                    .help("Run without window and exit"),
+            )
+            .arg(
+                Arg::with_name("frames")
+                    .long("frames")
+                    .value_name("N")
+                    .default_value("60")
+                    .validator(is_count)
+                    .help("Number of frames to run in headless mode"),
+            )
+            .arg(
+                Arg::with_name("screenshot")
+                    .long("screenshot")
+                    .value_name("PNG")
+                    .requires("headless")
+                    .help("Save the last frame of headless run"),
+            )
+            .arg(
+                // Save states are not emulated yet, kept so scripts using it get told why
+                Arg::with_name("state")
+                    .long("state")
+                    .value_name("SLOT")
+                    .hidden(true),
+            )
+            .arg(
+                Arg::with_name("movie")
+                    .long("movie")
+                    .value_name("FM2")
+                    .requires("ROM")
+                    .help("Play FCEUX movie as input"),
+            )
+            .arg(
+                Arg::with_name("log-cpu")
+                    .long("log-cpu")
+                    .value_name("FILE")
+                    .help("Write every executed instruction to FILE"),
+            )
+            .arg(
+                Arg::with_name("trace-format")
+                    .long("trace-format")
+                    .value_name("FORMAT")
+                    .possible_values(&["nestest", "mesen"])
+                    .case_insensitive(true)
+                    .requires("log-cpu")
+                    .help("Layout of CPU log lines"),
+            )
+            .arg(
+                Arg::with_name("trace-bank")
+                    .long("trace-bank")
+                    .requires("log-cpu")
+                    .help("Prefix CPU log lines with PRG-ROM bank"),
+            )
+            .arg(
+                Arg::with_name("trace-range")
+                    .long("trace-range")
+                    .value_name("ADDR[-ADDR]")
+                    .requires("log-cpu")
+                    .validator(is_addr_range)
+                    .help("Log only instructions in the hex address range"),
+            )
+            .arg(
+                Arg::with_name("trace-frames")
+                    .long("trace-frames")
+                    .value_name("FIRST[-LAST]")
+                    .requires("log-cpu")
+                    .validator(is_frame_range)
+                    .help("Log only instructions in the frames"),
+            )
+            .arg(
+                Arg::with_name("trace-if")
+                    .long("trace-if")
+                    .value_name("CONDITION")
+                    .requires("log-cpu")
+                    .validator(is_condition)
+                    .help("Log only instructions run when the condition holds, e.g. 'X == 0'"),
+            )
+            .arg(
+                Arg::with_name("symbols")
+                    .long("symbols")
+                    .value_name("FILE")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .requires("ROM")
+                    .help("Load labels from ca65 .dbg, FCEUX .nl or Mesen .mlb file"),
+            )
+            .arg(
+                Arg::with_name("dump-asm")
+                    .long("dump-asm")
+                    .value_name("FILE")
+                    .requires("ROM")
+                    .help("Write PRG-ROM disassembly in ca65 syntax to FILE"),
+            )
+            .get_matches();
+
+        if matches.is_present("state") {
+            invalid_value("--state: save states are not supported yet".to_string());
+        }
+
+        Args {
+            rom: matches.value_of("ROM").map(PathBuf::from),
+            rom_dirs: matches
+                .values_of("rom-dir")
+                .map(|dirs| dirs.map(PathBuf::from).collect())
+                .unwrap_or_default(),
+            region: matches.value_of("region").map(|name| {
+                Region::from_name(name)
+                    .unwrap_or_else(|| invalid_value(format!("'{}' is not a region", name)))
+            }),
+            scale: if matches.is_present("scale") {
+                Some(value_t_or_exit!(matches, "scale", f32))
+            } else {
+                None
+            },
+            headless: matches.is_present("headless"),
+            frames: value_t_or_exit!(matches, "frames", u32),
+            screenshot: matches.value_of("screenshot").map(PathBuf::from),
+            movie: matches.value_of("movie").map(PathBuf::from),
+            log_cpu: matches.value_of("log-cpu").map(PathBuf::from),
+            trace: match trace_options(&matches) {
+                Ok(trace) => trace,
+                Err(e) => invalid_value(e),
+            },
+            symbols: matches
+                .values_of("symbols")
+                .map(|files| files.map(PathBuf::from).collect())
+                .unwrap_or_default(),
+            dump_asm: matches.value_of("dump-asm").map(PathBuf::from),
+        }
+    }
+
+    pub fn apply(&self, config: &mut Config) {
+        if !self.rom_dirs.is_empty() {
+            config.rom_dirs = self.rom_dirs.clone();
+        }
+        if self.region.is_some() {
+            config.region = self.region;
+        }
+        if let Some(scale) = self.scale {
+            config.video.scale = scale;
+        }
+    }
+
+    /// Undoes `apply` for values of `config` still as given on the command line, so saving
+    /// it doesn't make one-off overrides permanent. `file` is the config from the file.
+    pub fn unapply(&self, config: &mut Config, file: &Config) {
+        if !self.rom_dirs.is_empty() && config.rom_dirs == self.rom_dirs {
+            config.rom_dirs = file.rom_dirs.clone();
+        }
+        if self.region.is_some() && config.region == self.region {
+            config.region = file.region;
+        }
+        if self.scale == Some(config.video.scale) {
+            config.video.scale = file.video.scale;
+        }
+    }
+
+    /// Sets up what was asked for the run, to be called after the ROM is loaded.
+    pub fn start(&self, emulator: &mut Emulator) {
+        if let Some(movie) = self.movie.as_ref().and_then(Movie::load) {
+            println!("Playing movie, {} frames.", movie.frame_count());
+            emulator.play_movie(movie);
+        }
+        for path in &self.symbols {
+            emulator.symbols.load(path);
+        }
+        if let Some(path) = &self.log_cpu {
+            emulator.start_trace(path, self.trace.clone());
+        }
+        if let Some(path) = &self.dump_asm {
+            let result = File::create(path).and_then(|file| {
+                let mut out = BufWriter::new(file);
+                emulator.dump_prg(&mut out)?;
+                out.flush()
+            });
+            match result {
+                Ok(_) => println!("Saved disassembly: '{0}'.", path.display()),
+                Err(e) => println!(
+                    "Can't save disassembly: '{0}'. Error: {1}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::input::InputConfig;
+use crate::nes::controller::InputSetup;
+use crate::nes::palette::PaletteSource;
+use crate::nes::region::Region;
+use crate::video::VideoOptions;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Path of a file in the nes-rust directory of the user config dir.
+pub fn config_path(file_name: &str) -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("nes-rust").join(file_name))
+}
+
+/// Writes a file creating its directory if needed.
+pub fn write_file(path: &PathBuf, contents: &str) {
+    if let Err(e) =
+        fs::create_dir_all(path.parent().unwrap()).and_then(|_| fs::write(&path, contents))
+    {
+        println!("Can't write file: '{0}'. Error: {1}", path.display(), e);
+    }
+}
+
+fn default_rom_dir() -> PathBuf {
+    let exe_path = std::env::current_exe();
+    exe_path.unwrap().parent().unwrap().join("../../roms")
+}
+
+/// Frontend settings stored in `config.toml` in the user config dir.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Directories searched for `.nes` files.
+    pub rom_dirs: Vec<PathBuf>,
+    /// Region forced for all ROMs, `None` to detect it from the ROM.
+    pub region: Option<Region>,
+    pub palette: PaletteSource,
+    pub video: VideoOptions,
+    pub input: InputConfig,
+    /// Input setup chosen for a ROM, overrides the one from its header.
+    pub rom_inputs: HashMap<PathBuf, InputSetup>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            rom_dirs: vec![default_rom_dir()],
+            region: None,
+            palette: PaletteSource::Default,
+            video: VideoOptions::default(),
+            input: InputConfig::default(),
+            rom_inputs: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Self {
+        let path = match config_path("config.toml") {
+            Some(path) => path,
+            None => return Config::default(),
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => return Config::default(),
+        };
+        match toml::from_str(&contents) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Can't parse config '{0}'. Error: {1}", path.display(), e);
+                Config::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let path = match config_path("config.toml") {
+            Some(path) => path,
+            None => return,
+        };
+        match toml::to_string(self) {
+            Ok(contents) => write_file(&path, &contents),
+            Err(e) => println!("Can't write config '{0}'. Error: {1}", path.display(), e),
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use crate::nes::bus::Bus;
+use crate::nes::debugger::{BreakKind, Breakpoint, Condition as BreakCondition, Step};
+use crate::nes::disasm::{self, Labels};
+use crate::nes::source_map::SourceLine;
+use crate::nes::symbols::SymbolLookup;
+use crate::nes::trace::{self, TraceFormat, TraceOptions};
+use crate::nes::Emulator;
+use imgui::*;
+use std::path::PathBuf;
+
+// Disassembly lines shown before and after PC
+const LINES_BEFORE: u16 = 10;
+const LINES_AFTER: u16 = 20;
+// Source lines shown before and after the current one
+const SOURCE_LINES_AROUND: usize = 2;
+
+const ERROR_COLOR: [f32; 4] = [1.0, 0.4, 0.4, 1.0];
+const COMMENT_COLOR: [f32; 4] = [0.5, 0.8, 0.5, 1.0];
+
+fn decode(bus: &Bus, addr: u16, labels: Option<&dyn Labels>) -> disasm::DisasmInstruction {
+    disasm::decode(&mut |addr| bus.peek(addr), addr, labels)
+}
+
+// Instructions can't be decoded backwards, so look for the farthest address before `pc`
+// which decodes right into it
+fn disasm_start(bus: &Bus, pc: u16) -> u16 {
+    for back in (1..=LINES_BEFORE * 3).rev() {
+        let start = pc.wrapping_sub(back);
+        let mut offset = 0;
+        while offset < back {
+            offset += decode(bus, start.wrapping_add(offset), None).len;
+        }
+        if offset == back {
+            return start;
+        }
+    }
+    pc
+}
+
+/// Window with CPU registers, stack and disassembly, to control execution and breakpoints.
+pub struct DebuggerWindow {
+    kind: BreakKind,
+    addr_input: ImString,
+    condition_input: ImString,
+    error: Option<String>,
+    symbols_input: ImString,
+    scanline: i32,
+    // PC the disassembly was last scrolled to
+    scrolled_pc: Option<u16>,
+    trace_path: ImString,
+    trace_format: TraceFormat,
+    trace_bank: bool,
+    trace_range: ImString,
+    trace_frames: ImString,
+    trace_condition: ImString,
+    trace_error: Option<String>,
+}
+
+impl DebuggerWindow {
+    pub fn new() -> Self {
+        DebuggerWindow {
+            kind: BreakKind::Exec,
+            addr_input: ImString::with_capacity(16),
+            condition_input: ImString::with_capacity(128),
+            error: None,
+            symbols_input: ImString::with_capacity(256),
+            scanline: 0,
+            scrolled_pc: None,
+            trace_path: ImString::new("trace.log"),
+            trace_format: TraceFormat::Nestest,
+            trace_bank: false,
+            trace_range: ImString::with_capacity(16),
+            trace_frames: ImString::with_capacity(32),
+            trace_condition: ImString::with_capacity(128),
+            trace_error: None,
+        }
+    }
+
+    pub fn draw_ui(&mut self, ui: &imgui::Ui, emulator: &mut Emulator) {
+        let window = imgui::Window::new(im_str!("Debugger"));
+        window
+            .size([500.0, 390.0], Condition::FirstUseEver)
+            .position([370.0, 505.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                self.draw_controls(ui, emulator);
+                ui.separator();
+                self.draw_registers(ui, emulator);
+                ui.separator();
+                self.draw_source(ui, emulator);
+                ChildWindow::new(im_str!("Disassembly"))
+                    .size([0.0, -180.0])
+                    .border(true)
+                    .build(&ui, || self.draw_disassembly(ui, emulator));
+                self.draw_breakpoints(ui, emulator);
+                self.draw_symbols(ui, emulator);
+                if CollapsingHeader::new(im_str!("Trace")).build(&ui) {
+                    self.draw_trace(ui, emulator);
+                }
+            });
+    }
+
+    fn draw_controls(&mut self, ui: &imgui::Ui, emulator: &mut Emulator) {
+        if emulator.debugger.paused {
+            if ui.small_button(im_str!("Resume")) {
+                emulator.debugger.resume();
+            }
+        } else if ui.small_button(im_str!("Pause")) {
+            emulator.debugger.pause("Paused");
+        }
+
+        let steps = [
+            (im_str!("Step"), Step::Instruction),
+            (im_str!("Step Over"), Step::Over),
+            (im_str!("Step Out"), Step::Out),
+            (im_str!("Step Line"), Step::Line),
+            (im_str!("Run Frame"), Step::Frame),
+        ];
+        for (label, step) in steps.iter() {
+            ui.same_line(0.0);
+            if ui.small_button(label) {
+                emulator.step(*step);
+            }
+        }
+
+        if ui.small_button(im_str!("Run to scanline")) {
+            emulator.step(Step::Scanline(self.scanline as i16));
+        }
+        ui.same_line(0.0);
+        ui.set_next_item_width(100.0);
+        if ui
+            .input_int(im_str!("##scanline"), &mut self.scanline)
+            .build()
+        {
+            self.scanline = self.scanline.max(-1).min(311);
+        }
+
+        if let Some(reason) = &emulator.debugger.break_reason {
+            ui.text(format!("Stopped: {}", reason));
+        } else {
+            ui.text(im_str!("Running"));
+        }
+    }
+
+    fn draw_registers(&mut self, ui: &imgui::Ui, emulator: &mut Emulator) {
+        let cpu = &mut emulator.cpu;
+        ui.text(format!(
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X}",
+            cpu.PC,
+            cpu.A,
+            cpu.X,
+            cpu.Y,
+            cpu.SP,
+            cpu.flags.to_byte()
+        ));
+        {
+            let ppu = emulator.ppu.borrow();
+            ui.text(format!(
+                "Scanline: {} Dot: {} CPU cycle: {}",
+                ppu.scanline, ppu.cycle, cpu.total_cycles
+            ));
+        }
+
+        let flags = &mut cpu.flags;
+        let flag_boxes = vec![
+            (im_str!("N"), &mut flags.N),
+            (im_str!("V"), &mut flags.O),
+            (im_str!("U"), &mut flags.U),
+            (im_str!("B"), &mut flags.B),
+            (im_str!("D"), &mut flags.D),
+            (im_str!("I"), &mut flags.I),
+            (im_str!("Z"), &mut flags.Z),
+            (im_str!("C"), &mut flags.C),
+        ];
+        for (i, (label, value)) in flag_boxes.into_iter().enumerate() {
+            if i > 0 {
+                ui.same_line(0.0);
+            }
+            ui.checkbox(label, value);
+        }
+
+        // Pushed values from the top of the stack
+        let stack: Vec<String> = (cpu.SP as u16 + 1..0x100)
+            .take(12)
+            .map(|offset| format!("{:02X}", cpu.bus.peek(0x100 + offset)))
+            .collect();
+        ui.text(format!("Stack: {}", stack.join(" ")));
+    }
+
+    // Lines around the current one when there is ca65 debug info
+    fn draw_source(&mut self, ui: &imgui::Ui, emulator: &mut Emulator) {
+        let line = match emulator.source_line() {
+            Some(line) => line,
+            None => return,
+        };
+        let source_map = &emulator.symbols.source_map;
+        ui.text(format!("{}:{}", source_map.file_name(line), line.line));
+        let first = line.line.saturating_sub(SOURCE_LINES_AROUND).max(1);
+        for number in first..=line.line + SOURCE_LINES_AROUND {
+            let other = SourceLine {
+                line: number,
+                ..line
+            };
+            if let Some(text) = source_map.text(other) {
+                let marker = if number == line.line { ">" } else { " " };
+                ui.text(format!("{}{:>5} {}", marker, number, text));
+            }
+        }
+        ui.separator();
+    }
+
+    fn draw_disassembly(&mut self, ui: &imgui::Ui, emulator: &mut Emulator) {
+        let pc = emulator.cpu.PC;
+        let (x, y) = (emulator.cpu.X, emulator.cpu.Y);
+        let bus = &emulator.cpu.bus;
+        let breakpoints = &mut emulator.debugger.breakpoints;
+        let mapping = emulator.cartridge.borrow().prg_mapping();
+        let labels = emulator.symbols.lookup(mapping);
+
+        let mut addr = disasm_start(bus, pc);
+        for _ in 0..LINES_BEFORE + LINES_AFTER {
+            let mut ins = decode(bus, addr, Some(&labels));
+            // Registers only tell where the next instruction accesses
+            if addr == pc {
+                ins.annotate(&mut |addr| bus.peek(addr), x, y);
+            }
+            if let Some(name) = labels.label(addr) {
+                ui.text(format!("{}:", name));
+            }
+            let bytes: Vec<String> = ins.bytes().iter().map(|b| format!("{:02X}", b)).collect();
+            let bp_index = breakpoints
+                .iter()
+                .position(|bp| bp.kind == BreakKind::Exec && bp.start == addr);
+            let marker = if bp_index.is_some() { "o" } else { " " };
+            let unofficial = if ins.official { " " } else { "*" };
+            let label = im_str!(
+                "{}{:04X}  {:<9} {}{:<16} {}##{}",
+                marker,
+                addr,
+                bytes.join(" "),
+                unofficial,
+                ins.to_string(),
+                ins.annotation,
+                addr
+            );
+
+            // Clicking a line toggles breakpoint on it
+            if Selectable::new(&label).selected(addr == pc).build(&ui) {
+                match bp_index {
+                    Some(i) => {
+                        breakpoints.remove(i);
+                    }
+                    None => breakpoints.push(Breakpoint::new(BreakKind::Exec, addr, addr)),
+                }
+            }
+            if let Some(comment) = labels.comment(addr) {
+                ui.same_line(0.0);
+                ui.text_colored(COMMENT_COLOR, format!("; {}", comment));
+            }
+            if addr == pc && self.scrolled_pc != Some(pc) {
+                ui.set_scroll_here_y();
+                self.scrolled_pc = Some(pc);
+            }
+            addr = addr.wrapping_add(ins.len);
+        }
+    }
+
+    fn draw_breakpoints(&mut self, ui: &imgui::Ui, emulator: &mut Emulator) {
+        let mapping = emulator.cartridge.borrow().prg_mapping();
+        let labels: SymbolLookup = emulator.symbols.lookup(mapping);
+        let breakpoints = &mut emulator.debugger.breakpoints;
+        let mut remove = None;
+        for (i, bp) in breakpoints.iter_mut().enumerate() {
+            ui.checkbox(&im_str!("{}##bp{}", bp, i), &mut bp.enabled);
+            ui.same_line(0.0);
+            if ui.small_button(&im_str!("X##bp{}", i)) {
+                remove = Some(i);
+            }
+        }
+        if let Some(i) = remove {
+            breakpoints.remove(i);
+        }
+
+        for kind in BreakKind::ALL.iter() {
+            ui.radio_button(&im_str!("{}", kind.name()), &mut self.kind, *kind);
+            ui.same_line(0.0);
+        }
+        ui.new_line();
+        ui.input_text(im_str!("Address"), &mut self.addr_input)
+            .build();
+        ui.input_text(im_str!("Condition"), &mut self.condition_input)
+            .build();
+        if ui.small_button(im_str!("Add breakpoint")) {
+            match Breakpoint::parse(
+                self.kind,
+                self.addr_input.to_str(),
+                self.condition_input.to_str(),
+                Some(&labels),
+            ) {
+                Ok(bp) => {
+                    breakpoints.push(bp);
+                    self.error = None;
+                }
+                Err(e) => self.error = Some(e),
+            }
+        }
+        if let Some(error) = &self.error {
+            ui.text_colored(ERROR_COLOR, error);
+        }
+    }
+
+    // Symbol files are loaded with the ROM, others can be added here
+    fn draw_symbols(&mut self, ui: &imgui::Ui, emulator: &mut Emulator) {
+        ui.text(format!("Symbols: {}", emulator.symbols.len()));
+        ui.same_line(0.0);
+        if ui.small_button(im_str!("Load")) {
+            let path = PathBuf::from(self.symbols_input.to_str());
+            emulator.symbols.load(&path);
+        }
+        ui.same_line(0.0);
+        ui.input_text(im_str!("##symbols"), &mut self.symbols_input)
+            .build();
+    }
+
+    fn trace_options(&self) -> Result<TraceOptions, String> {
+        let condition = self.trace_condition.to_str().trim();
+        Ok(TraceOptions {
+            format: self.trace_format,
+            show_bank: self.trace_bank,
+            addr_range: trace::parse_range(self.trace_range.to_str(), true)?
+                .map(|(first, last)| (first as u16, last as u16)),
+            frame_range: trace::parse_range(self.trace_frames.to_str(), false)?,
+            condition: if condition.is_empty() {
+                None
+            } else {
+                Some(BreakCondition::parse(condition)?)
+            },
+        })
+    }
+
+    // Logging of executed instructions to a file, filters apply when it starts
+    fn draw_trace(&mut self, ui: &imgui::Ui, emulator: &mut Emulator) {
+        if let Some(trace) = &emulator.trace {
+            ui.text(format!(
+                "Tracing to {}: {} lines",
+                trace.path().display(),
+                trace.lines
+            ));
+            if ui.small_button(im_str!("Stop trace")) {
+                emulator.stop_trace();
+            }
+            return;
+        }
+
+        ui.input_text(im_str!("File##trace"), &mut self.trace_path)
+            .build();
+        for format in TraceFormat::ALL.iter() {
+            ui.radio_button(
+                &im_str!("{}", format.name()),
+                &mut self.trace_format,
+                *format,
+            );
+            ui.same_line(0.0);
+        }
+        ui.checkbox(im_str!("Bank"), &mut self.trace_bank);
+        ui.input_text(im_str!("Addresses##trace"), &mut self.trace_range)
+            .build();
+        ui.input_text(im_str!("Frames##trace"), &mut self.trace_frames)
+            .build();
+        ui.input_text(im_str!("Condition##trace"), &mut self.trace_condition)
+            .build();
+        if ui.small_button(im_str!("Start trace")) {
+            match self.trace_options() {
+                Ok(options) => {
+                    let path = PathBuf::from(self.trace_path.to_str());
+                    emulator.start_trace(&path, options);
+                    self.trace_error = None;
+                }
+                Err(e) => self.trace_error = Some(e),
+            }
+        }
+        if let Some(error) = &self.trace_error {
+            ui.text_colored(ERROR_COLOR, error);
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use crate::cli::Args;
+use crate::config::Config;
+use crate::nes::palette::Palette;
+use crate::nes::ppu::SCREEN_SIZE;
+use crate::nes::Emulator;
+use crate::video::cpu_filters;
+use std::process;
+
+/// Runs the ROM from `args` without window as fast as possible, for scripts and tests.
+pub fn run(args: &Args, config: &Config) {
+    let rom = match &args.rom {
+        Some(rom) => rom,
+        None => return,
+    };
+
+    let mut emulator = Emulator::new();
+    emulator.set_region_override(config.region);
+    if let Some(palette) = Palette::from_source(&config.palette) {
+        emulator.ppu.borrow_mut().palette = palette;
+    }
+    if let Err(e) = emulator.load_rom(rom) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    args.start(&mut emulator);
+
+    for _ in 0..args.frames {
+        emulator.ppu.borrow_mut().screen.complete = false;
+        emulator.run_frame();
+    }
+    // Flush the CPU log
+    emulator.stop_trace();
+
+    if let Some(path) = &args.screenshot {
+        let (width, height) = SCREEN_SIZE;
+        let screen = emulator.ppu.borrow().screen.rgb().to_vec();
+        let image = cpu_filters::Image::new(width, height, screen);
+        cpu_filters::apply(&config.video, &image).save_png(path);
+    }
+}
@@ -526,6 +526,40 @@ impl Renderer {
 
     /// Creates a new wgpu texture made from the imgui font atlas.
     pub fn create_texture(&mut self, device: &Device, width: u32, height: u32) -> TextureId {
+        self.create_texture_with_usage(
+            device,
+            width,
+            height,
+            TextureUsage::SAMPLED | TextureUsage::COPY_DST,
+        )
+    }
+
+    /// Creates a texture which can be rendered to and then drawn by imgui.
+    pub fn create_render_target(&mut self, device: &Device, width: u32, height: u32) -> TextureId {
+        self.create_texture_with_usage(
+            device,
+            width,
+            height,
+            TextureUsage::SAMPLED | TextureUsage::OUTPUT_ATTACHMENT,
+        )
+    }
+
+    /// Creates a view to render into a texture made with `create_render_target`.
+    pub fn texture_view(&self, id: TextureId) -> Option<TextureView> {
+        Some(self.textures.get(id)?.texture.create_default_view())
+    }
+
+    pub fn remove_texture(&mut self, id: TextureId) {
+        self.textures.remove(id);
+    }
+
+    fn create_texture_with_usage(
+        &mut self,
+        device: &Device,
+        width: u32,
+        height: u32,
+        usage: TextureUsage,
+    ) -> TextureId {
         // Create the wgpu texture.
         let texture = device.create_texture(&TextureDescriptor {
             label: None,
@@ -539,7 +573,7 @@ impl Renderer {
             sample_count: 1,
             dimension: TextureDimension::D2,
             format: TextureFormat::Rgba8Unorm,
-            usage: TextureUsage::SAMPLED | TextureUsage::COPY_DST,
+            usage,
         });
 
         let texture = Texture::new(texture, &self.texture_layout, device);
@@ -0,0 +1,431 @@
+use crate::nes;
+use gilrs::{Axis, EventType, Gilrs};
+use imgui::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use winit::event::VirtualKeyCode;
+
+// Axis deflection after which it counts as a pressed button
+const AXIS_THRESHOLD: f32 = 0.5;
+
+/// Button of a standard controller that can be bound, including turbo versions of A and B.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum PadButton {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+    TurboA,
+    TurboB,
+}
+
+const PAD_BUTTONS: [PadButton; 10] = [
+    PadButton::A,
+    PadButton::B,
+    PadButton::Select,
+    PadButton::Start,
+    PadButton::Up,
+    PadButton::Down,
+    PadButton::Left,
+    PadButton::Right,
+    PadButton::TurboA,
+    PadButton::TurboB,
+];
+
+impl PadButton {
+    // Bit in `Controller::input`
+    fn mask(self) -> u8 {
+        match self {
+            PadButton::A | PadButton::TurboA => 0x80,
+            PadButton::B | PadButton::TurboB => 0x40,
+            PadButton::Select => 0x20,
+            PadButton::Start => 0x10,
+            PadButton::Up => 0x08,
+            PadButton::Down => 0x04,
+            PadButton::Left => 0x02,
+            PadButton::Right => 0x01,
+        }
+    }
+
+    fn is_turbo(self) -> bool {
+        self == PadButton::TurboA || self == PadButton::TurboB
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum InputSource {
+    Key {
+        key: VirtualKeyCode,
+    },
+    GamepadButton {
+        gamepad: usize,
+        // Flattened into `Binding` next to its own `button`
+        #[serde(rename = "pad_button")]
+        button: gilrs::Button,
+    },
+    GamepadAxis {
+        gamepad: usize,
+        axis: Axis,
+        positive: bool,
+    },
+}
+
+impl InputSource {
+    fn describe(&self) -> String {
+        match self {
+            InputSource::Key { key } => format!("{:?}", key),
+            InputSource::GamepadButton { gamepad, button } => {
+                format!("Pad{} {:?}", gamepad, button)
+            }
+            InputSource::GamepadAxis {
+                gamepad,
+                axis,
+                positive,
+            } => format!(
+                "Pad{} {:?}{}",
+                gamepad,
+                axis,
+                if *positive { "+" } else { "-" }
+            ),
+        }
+    }
+}
+
+/// Maps an input source to a button of the pad of one of the players (0-3).
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Binding {
+    pub player: usize,
+    pub button: PadButton,
+    #[serde(flatten)]
+    pub source: InputSource,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputConfig {
+    /// Turbo presses per second.
+    pub turbo_rate: f32,
+    pub bindings: Vec<Binding>,
+}
+
+fn key(player: usize, button: PadButton, key: VirtualKeyCode) -> Binding {
+    Binding {
+        player,
+        button,
+        source: InputSource::Key { key },
+    }
+}
+
+fn gamepad_button(player: usize, button: PadButton, pad_button: gilrs::Button) -> Binding {
+    Binding {
+        player,
+        button,
+        source: InputSource::GamepadButton {
+            gamepad: player,
+            button: pad_button,
+        },
+    }
+}
+
+fn gamepad_axis(player: usize, button: PadButton, axis: Axis, positive: bool) -> Binding {
+    Binding {
+        player,
+        button,
+        source: InputSource::GamepadAxis {
+            gamepad: player,
+            axis,
+            positive,
+        },
+    }
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        let mut bindings = vec![
+            key(0, PadButton::A, VirtualKeyCode::X),
+            key(0, PadButton::B, VirtualKeyCode::Z),
+            key(0, PadButton::Select, VirtualKeyCode::A),
+            key(0, PadButton::Start, VirtualKeyCode::S),
+            key(0, PadButton::Up, VirtualKeyCode::Up),
+            key(0, PadButton::Down, VirtualKeyCode::Down),
+            key(0, PadButton::Left, VirtualKeyCode::Left),
+            key(0, PadButton::Right, VirtualKeyCode::Right),
+        ];
+
+        // Gamepad N controls player N
+        for player in 0..4 {
+            bindings.extend_from_slice(&[
+                gamepad_button(player, PadButton::A, gilrs::Button::East),
+                gamepad_button(player, PadButton::B, gilrs::Button::South),
+                gamepad_button(player, PadButton::TurboA, gilrs::Button::North),
+                gamepad_button(player, PadButton::TurboB, gilrs::Button::West),
+                gamepad_button(player, PadButton::Select, gilrs::Button::Select),
+                gamepad_button(player, PadButton::Start, gilrs::Button::Start),
+                gamepad_button(player, PadButton::Up, gilrs::Button::DPadUp),
+                gamepad_button(player, PadButton::Down, gilrs::Button::DPadDown),
+                gamepad_button(player, PadButton::Left, gilrs::Button::DPadLeft),
+                gamepad_button(player, PadButton::Right, gilrs::Button::DPadRight),
+                gamepad_axis(player, PadButton::Up, Axis::LeftStickY, true),
+                gamepad_axis(player, PadButton::Down, Axis::LeftStickY, false),
+                gamepad_axis(player, PadButton::Left, Axis::LeftStickX, false),
+                gamepad_axis(player, PadButton::Right, Axis::LeftStickX, true),
+            ]);
+        }
+
+        InputConfig {
+            turbo_rate: 15.0,
+            bindings,
+        }
+    }
+}
+
+/// Keyboard and gamepad state mapped to controller inputs through `InputConfig` bindings.
+pub struct Input {
+    pub config: InputConfig,
+    gilrs: Option<Gilrs>,
+    keys: HashSet<VirtualKeyCode>,
+    gamepad_buttons: HashSet<(usize, gilrs::Button)>,
+    gamepad_axes: HashMap<(usize, Axis), f32>,
+    turbo_time: f32,
+    // Binding edited in UI which waits for the next pressed key or gamepad button
+    rebinding: Option<(usize, PadButton)>,
+    ui_player: usize,
+    // Bindings were edited and have to be saved
+    config_changed: bool,
+    // Turbo rate is being dragged, it's saved when the drag ends
+    turbo_rate_edited: bool,
+}
+
+impl Input {
+    pub fn new(config: InputConfig) -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                println!("Gamepads are not available. Error: {}", e);
+                None
+            }
+        };
+
+        Input {
+            config,
+            gilrs,
+            keys: HashSet::new(),
+            gamepad_buttons: HashSet::new(),
+            gamepad_axes: HashMap::new(),
+            turbo_time: 0.0,
+            rebinding: None,
+            ui_player: 0,
+            config_changed: false,
+            turbo_rate_edited: false,
+        }
+    }
+
+    pub fn set_key_state(&mut self, code: VirtualKeyCode, state: bool) {
+        if state {
+            if self.bind(InputSource::Key { key: code }) {
+                return;
+            }
+            self.keys.insert(code);
+        } else {
+            self.keys.remove(&code);
+        }
+    }
+
+    pub fn is_rebinding(&self) -> bool {
+        self.rebinding.is_some()
+    }
+
+    /// Stops waiting for the input to bind, the old bindings are kept.
+    pub fn cancel_rebinding(&mut self) {
+        self.rebinding = None;
+    }
+
+    // Finishes rebinding from UI with the given source, returns false when not rebinding
+    fn bind(&mut self, source: InputSource) -> bool {
+        let (player, button) = match self.rebinding.take() {
+            Some(rebinding) => rebinding,
+            None => return false,
+        };
+        let binding = Binding {
+            player,
+            button,
+            source,
+        };
+        if !self.config.bindings.contains(&binding) {
+            self.config.bindings.push(binding);
+            self.config_changed = true;
+        }
+        true
+    }
+
+    fn poll_gamepads(&mut self) {
+        let mut events = vec![];
+        if let Some(gilrs) = &mut self.gilrs {
+            while let Some(event) = gilrs.next_event() {
+                events.push((event.id.into(), event.event));
+            }
+        }
+
+        for (gamepad, event) in events {
+            match event {
+                EventType::ButtonPressed(button, _) => {
+                    if !self.bind(InputSource::GamepadButton { gamepad, button }) {
+                        self.gamepad_buttons.insert((gamepad, button));
+                    }
+                }
+                EventType::ButtonReleased(button, _) => {
+                    self.gamepad_buttons.remove(&(gamepad, button));
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    if value.abs() > AXIS_THRESHOLD {
+                        let positive = value > 0.0;
+                        if self.bind(InputSource::GamepadAxis {
+                            gamepad,
+                            axis,
+                            positive,
+                        }) {
+                            continue;
+                        }
+                    }
+                    self.gamepad_axes.insert((gamepad, axis), value);
+                }
+                EventType::Disconnected => {
+                    self.gamepad_buttons.retain(|(id, _)| *id != gamepad);
+                    self.gamepad_axes.retain(|(id, _), _| *id != gamepad);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn is_active(&self, source: &InputSource) -> bool {
+        match source {
+            InputSource::Key { key } => self.keys.contains(key),
+            InputSource::GamepadButton { gamepad, button } => {
+                self.gamepad_buttons.contains(&(*gamepad, *button))
+            }
+            InputSource::GamepadAxis {
+                gamepad,
+                axis,
+                positive,
+            } => {
+                let value = *self.gamepad_axes.get(&(*gamepad, *axis)).unwrap_or(&0.0);
+                if *positive {
+                    value > AXIS_THRESHOLD
+                } else {
+                    value < -AXIS_THRESHOLD
+                }
+            }
+        }
+    }
+
+    /// Polls gamepads and updates inputs of all controllers.
+    pub fn update(&mut self, dt: f32, emulator: &mut nes::Emulator) {
+        self.poll_gamepads();
+
+        // Turbo buttons are pressed during the first half of each period
+        let period = 1.0 / self.config.turbo_rate.max(1.0);
+        self.turbo_time = (self.turbo_time + dt) % period;
+        let turbo_on = self.turbo_time < period / 2.0;
+
+        let mut inputs = [0u8; 4];
+        for binding in &self.config.bindings {
+            if binding.player >= inputs.len() || !self.is_active(&binding.source) {
+                continue;
+            }
+            if binding.button.is_turbo() && !turbo_on {
+                continue;
+            }
+            inputs[binding.player] |= binding.button.mask();
+        }
+
+        for (controller, input) in emulator.controllers.iter().zip(inputs.iter()) {
+            controller.borrow_mut().input = *input;
+        }
+    }
+
+    /// Draws binding editor, to be called inside a window.
+    pub fn draw_ui(&mut self, ui: &imgui::Ui) {
+        ui.text(im_str!("Bindings for player:"));
+        for player in 0..4 {
+            ui.same_line(0.0);
+            ui.radio_button(&im_str!("{}", player + 1), &mut self.ui_player, player);
+        }
+
+        let mut changed = false;
+        for (i, button) in PAD_BUTTONS.iter().enumerate() {
+            let player = self.ui_player;
+            let sources: Vec<String> = self
+                .config
+                .bindings
+                .iter()
+                .filter(|b| b.player == player && b.button == *button)
+                .map(|b| b.source.describe())
+                .collect();
+
+            ui.text(format!("{:?}", button));
+            ui.same_line(70.0);
+            if self.rebinding == Some((player, *button)) {
+                ui.text(im_str!("Press key or gamepad button, Esc to cancel..."));
+            } else {
+                if ui.small_button(&im_str!("Bind##{}", i)) {
+                    self.rebinding = Some((player, *button));
+                }
+                ui.same_line(0.0);
+                if ui.small_button(&im_str!("Clear##{}", i)) {
+                    self.config
+                        .bindings
+                        .retain(|b| b.player != player || b.button != *button);
+                    changed = true;
+                }
+                ui.same_line(0.0);
+                ui.text(sources.join(", "));
+            }
+        }
+
+        if ui
+            .drag_float(im_str!("Turbo rate"), &mut self.config.turbo_rate)
+            .min(1.0)
+            .max(30.0)
+            .build()
+        {
+            self.turbo_rate_edited = true;
+        }
+        if self.turbo_rate_edited && !ui.is_item_active() {
+            self.turbo_rate_edited = false;
+            changed = true;
+        }
+
+        if ui.button(im_str!("Reset to defaults"), [0.0, 0.0]) {
+            self.config = InputConfig::default();
+            changed = true;
+        }
+
+        if changed {
+            self.config_changed = true;
+        }
+    }
+
+    /// Returns true once after bindings were edited.
+    pub fn take_config_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.config_changed, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_round_trip() {
+        let config = InputConfig::default();
+        let text = toml::to_string(&config).unwrap();
+        let loaded: InputConfig = toml::from_str(&text).unwrap();
+        assert_eq!(loaded.bindings, config.bindings);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::config::{config_path, write_file};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+/// Which windows the frontend shows.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum UiMode {
+    /// Only the game screen scaled to the window, with an overlay menu.
+    Player,
+    /// Game screen together with ROM list, state and other tool windows.
+    Debug,
+}
+
+/// Frontend state restored on the next run.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Layout {
+    pub mode: UiMode,
+    pub fullscreen: bool,
+    /// Logical size of the window when it's not fullscreen.
+    pub window_size: (f64, f64),
+    pub window_pos: Option<(i32, i32)>,
+    /// Recently played ROMs, the last one first.
+    pub recent_roms: Vec<PathBuf>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            mode: UiMode::Debug,
+            fullscreen: false,
+            window_size: (1600.0, 900.0),
+            window_pos: None,
+            recent_roms: vec![],
+        }
+    }
+}
+
+impl Layout {
+    pub fn load() -> Self {
+        let path = match config_path("layout.toml") {
+            Some(path) => path,
+            None => return Layout::default(),
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => return Layout::default(),
+        };
+        match toml::from_str(&contents) {
+            Ok(layout) => layout,
+            Err(e) => {
+                println!("Can't parse layout '{0}'. Error: {1}", path.display(), e);
+                Layout::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        if let Some(path) = config_path("layout.toml") {
+            write_file(&path, &toml::to_string(self).unwrap());
+        }
+    }
+
+    /// Positions and sizes of imgui windows saved by `save_imgui_ini`.
+    pub fn load_imgui_ini() -> Option<String> {
+        config_path("imgui.ini").and_then(|path| fs::read_to_string(path).ok())
+    }
+
+    pub fn save_imgui_ini(ini: &str) {
+        if let Some(path) = config_path("imgui.ini") {
+            write_file(&path, ini);
+        }
+    }
+}
@@ -1,11 +1,35 @@
 mod app;
+mod cli;
+mod config;
+mod debugger_window;
+mod headless;
 mod imgui_wgpu;
+mod input;
+mod layout;
+mod memory_window;
+mod nametable_window;
 mod nes;
+mod oam_window;
+mod palette_window;
+mod rom_browser;
+mod video;
 
 use app::NESApp;
+use cli::Args;
+use config::Config;
 use std::rc::Rc;
 
 fn main() {
-    let app = Rc::new(NESApp::new());
-    app.run()
+    let args = Args::parse();
+    let mut config = Config::load();
+    args.apply(&mut config);
+
+    if args.headless {
+        headless::run(&args, &config);
+        return;
+    }
+
+    let mut app = NESApp::new(config, args);
+    app.start();
+    Rc::new(app).run()
 }
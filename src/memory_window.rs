@@ -0,0 +1,233 @@
+use crate::nes::memory::MemorySpace;
+use crate::nes::Emulator;
+use imgui::*;
+use std::ops::Range;
+
+const BYTES_PER_ROW: usize = 16;
+// Frames a changed byte stays highlighted
+const HIGHLIGHT_FRAMES: u8 = 60;
+
+const CHANGED_COLOR: [f32; 4] = [1.0, 0.4, 0.4, 1.0];
+const FROZEN_COLOR: [f32; 4] = [0.4, 0.7, 1.0, 1.0];
+const ERROR_COLOR: [f32; 4] = [1.0, 0.4, 0.4, 1.0];
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text.trim().trim_start_matches('$'), 16).ok()
+}
+
+/// Hex view of CPU, PPU and cartridge memory with editing and freezing of bytes.
+pub struct MemoryWindow {
+    space: MemorySpace,
+    selected: Option<usize>,
+    goto_input: ImString,
+    value_input: ImString,
+    error: Option<String>,
+    // Row to scroll to on the next draw
+    scroll_to: Option<usize>,
+    // Values at the previous check and how many frames ago they changed
+    previous: Vec<u8>,
+    ages: Vec<u8>,
+    last_frame: u32,
+    // Addresses read on the last draw, only those are compared on the next one
+    scanned: Range<usize>,
+}
+
+impl MemoryWindow {
+    pub fn new() -> Self {
+        MemoryWindow {
+            space: MemorySpace::Cpu,
+            selected: None,
+            goto_input: ImString::with_capacity(16),
+            value_input: ImString::with_capacity(8),
+            error: None,
+            scroll_to: None,
+            previous: vec![],
+            ages: vec![],
+            last_frame: 0,
+            scanned: 0..0,
+        }
+    }
+
+    pub fn draw_ui(&mut self, ui: &imgui::Ui, emulator: &mut Emulator) {
+        let window = imgui::Window::new(im_str!("Memory"));
+        window
+            .size([560.0, 390.0], Condition::FirstUseEver)
+            .position([875.0, 505.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                self.draw_spaces(ui);
+                self.track_changes(emulator);
+                ChildWindow::new(im_str!("Bytes"))
+                    .size([0.0, -55.0])
+                    .border(true)
+                    .build(&ui, || self.draw_bytes(ui, emulator));
+                self.draw_edit(ui, emulator);
+            });
+    }
+
+    fn draw_spaces(&mut self, ui: &imgui::Ui) {
+        let mut space = self.space;
+        for (i, other) in MemorySpace::ALL.iter().enumerate() {
+            if i > 0 {
+                ui.same_line(0.0);
+            }
+            ui.radio_button(&im_str!("{}", other.name()), &mut space, *other);
+        }
+        if space != self.space {
+            self.space = space;
+            self.selected = None;
+            self.previous.clear();
+        }
+    }
+
+    // Ages highlights of changed bytes once per emulated frame, values are read in `refresh`
+    fn track_changes(&mut self, emulator: &Emulator) {
+        let size = emulator.memory_size(self.space);
+        let frame = emulator.ppu.borrow().frame;
+        if self.previous.len() != size {
+            self.previous = vec![0; size];
+            self.ages = vec![HIGHLIGHT_FRAMES; size];
+            self.scanned = 0..0;
+            self.last_frame = frame;
+            return;
+        }
+
+        if frame != self.last_frame {
+            self.last_frame = frame;
+            for age in self.ages.iter_mut() {
+                *age = (*age + 1).min(HIGHLIGHT_FRAMES);
+            }
+        }
+    }
+
+    // Reads the shown bytes. Ones which weren't shown last time only get their value,
+    // since it's not known when they changed.
+    fn refresh(&mut self, emulator: &Emulator, addrs: Range<usize>) {
+        for addr in addrs.clone() {
+            let value = emulator.peek_memory(self.space, addr).unwrap_or(0);
+            if value != self.previous[addr] {
+                self.previous[addr] = value;
+                if self.scanned.contains(&addr) {
+                    self.ages[addr] = 0;
+                }
+            }
+        }
+        self.scanned = addrs;
+    }
+
+    fn draw_bytes(&mut self, ui: &imgui::Ui, emulator: &mut Emulator) {
+        let size = self.previous.len();
+        let rows = (size + BYTES_PER_ROW - 1) / BYTES_PER_ROW;
+        let line_height = ui.text_line_height_with_spacing();
+        if let Some(row) = self.scroll_to.take() {
+            ui.set_scroll_y(row as f32 * line_height);
+        }
+
+        // Only visible rows are drawn
+        let first = (ui.scroll_y() / line_height) as usize;
+        let last = (first + (ui.window_size()[1] / line_height) as usize + 2).min(rows);
+        let first = first.min(last);
+        self.refresh(
+            emulator,
+            first * BYTES_PER_ROW..(last * BYTES_PER_ROW).min(size),
+        );
+        let byte_width = ui.calc_text_size(im_str!("FF"), false, -1.0)[0];
+        for row in first..last {
+            ui.set_cursor_pos([ui.cursor_pos()[0], row as f32 * line_height]);
+            let start = row * BYTES_PER_ROW;
+            ui.text(format!("{:06X}:", start));
+            let mut chars = String::new();
+            for addr in start..(start + BYTES_PER_ROW).min(size) {
+                ui.same_line(0.0);
+                let value = self.previous[addr];
+                let color = if emulator
+                    .freezes
+                    .iter()
+                    .any(|freeze| freeze.space == self.space && freeze.addr == addr)
+                {
+                    Some(FROZEN_COLOR)
+                } else if self.ages[addr] < HIGHLIGHT_FRAMES {
+                    Some(CHANGED_COLOR)
+                } else {
+                    None
+                };
+                let text = im_str!("{:02X}##{}", value, addr);
+                let token = color.map(|color| ui.push_style_color(StyleColor::Text, color));
+                if Selectable::new(&text)
+                    .selected(self.selected == Some(addr))
+                    .size([byte_width, 0.0])
+                    .build(&ui)
+                {
+                    self.select(addr, value);
+                }
+                if let Some(token) = token {
+                    token.pop(&ui);
+                }
+                chars.push(match value {
+                    0x20..=0x7E => value as char,
+                    _ => '.',
+                });
+            }
+            ui.same_line(0.0);
+            ui.text(chars);
+        }
+        ui.set_cursor_pos([0.0, rows as f32 * line_height]);
+    }
+
+    fn select(&mut self, addr: usize, value: u8) {
+        self.selected = Some(addr);
+        self.value_input.clear();
+        self.value_input.push_str(&format!("{:02X}", value));
+    }
+
+    fn draw_edit(&mut self, ui: &imgui::Ui, emulator: &mut Emulator) {
+        ui.set_next_item_width(80.0);
+        ui.input_text(im_str!("##goto"), &mut self.goto_input)
+            .build();
+        ui.same_line(0.0);
+        if ui.small_button(im_str!("Go to")) {
+            match parse_hex(self.goto_input.to_str()) {
+                Some(addr) if addr < self.previous.len() => {
+                    let value = emulator.peek_memory(self.space, addr).unwrap_or(0);
+                    self.select(addr, value);
+                    self.scroll_to = Some(addr / BYTES_PER_ROW);
+                    self.error = None;
+                }
+                _ => self.error = Some(format!("No address {}", self.goto_input)),
+            }
+        }
+
+        if let Some(addr) = self.selected {
+            ui.same_line(0.0);
+            ui.text(format!("{:06X}:", addr));
+            ui.same_line(0.0);
+            ui.set_next_item_width(40.0);
+            ui.input_text(im_str!("##value"), &mut self.value_input)
+                .build();
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Write")) {
+                match parse_hex(self.value_input.to_str()) {
+                    Some(value) if value <= 0xFF => {
+                        emulator.poke_memory(self.space, addr, value as u8);
+                        // Frozen byte keeps the new value
+                        for freeze in emulator.freezes.iter_mut() {
+                            if freeze.space == self.space && freeze.addr == addr {
+                                freeze.value = value as u8;
+                            }
+                        }
+                        self.error = None;
+                    }
+                    _ => self.error = Some(format!("Bad value {}", self.value_input)),
+                }
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Freeze")) {
+                emulator.toggle_freeze(self.space, addr);
+            }
+        }
+
+        match &self.error {
+            Some(error) => ui.text_colored(ERROR_COLOR, error),
+            None => ui.text(format!("Frozen: {}", emulator.freezes.len())),
+        }
+    }
+}
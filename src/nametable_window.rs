@@ -0,0 +1,143 @@
+use crate::app::UiTexture;
+use crate::imgui_wgpu::Renderer;
+use crate::nes::ppu_view::{NametableView, NAMETABLES_SIZE};
+use crate::nes::Emulator;
+use imgui::*;
+use wgpu::{Device, Queue};
+
+const SCROLL_COLOR: [f32; 4] = [1.0, 0.2, 0.2, 1.0];
+const HOVER_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+// Parts of a span starting at `start` that wrap around `size`
+fn wrapped_spans(start: usize, len: usize, size: usize) -> Vec<(usize, usize)> {
+    let start = start % size;
+    if start + len <= size {
+        vec![(start, start + len)]
+    } else {
+        vec![(start, size), (0, start + len - size)]
+    }
+}
+
+/// The four nametables with the scroll position, captured live or at a scanline.
+pub struct NametableWindow {
+    texture: UiTexture,
+    view: Option<NametableView>,
+    capture_at_scanline: bool,
+    scanline: i32,
+    show_scroll: bool,
+}
+
+impl NametableWindow {
+    pub fn new(renderer: &mut Renderer, device: &Device) -> Self {
+        NametableWindow {
+            texture: UiTexture::new(renderer, device, NAMETABLES_SIZE),
+            view: None,
+            capture_at_scanline: false,
+            scanline: 0,
+            show_scroll: true,
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        emulator: &mut Emulator,
+        renderer: &mut Renderer,
+        device: &Device,
+        queue: &mut Queue,
+    ) {
+        if self.capture_at_scanline {
+            emulator.nametable_scanline = Some(self.scanline as i16);
+            match emulator.nametable_view.take() {
+                Some(view) => self.view = Some(view),
+                None => return,
+            }
+        } else {
+            emulator.nametable_scanline = None;
+            self.view = Some(NametableView::capture(&emulator.ppu.borrow()));
+        }
+
+        let view = self.view.as_ref().unwrap();
+        let ppu = emulator.ppu.borrow();
+        let width = NAMETABLES_SIZE.0;
+        for (i, &color) in view.pixels.iter().enumerate() {
+            self.texture
+                .set_pixel(i % width, i / width, ppu.palette.get_color(color));
+        }
+        self.texture.update(renderer, device, queue);
+    }
+
+    pub fn draw_ui(&mut self, ui: &imgui::Ui, emulator: &Emulator) {
+        let window = imgui::Window::new(im_str!("Nametables"));
+        window
+            .size([530.0, 600.0], Condition::FirstUseEver)
+            .position([1440.0, 5.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                let image_pos = ui.cursor_screen_pos();
+                Image::new(self.texture.texture_id, self.texture.get_size(1.0)).build(&ui);
+                let hovered = ui.is_item_hovered();
+                if let Some(view) = &self.view {
+                    self.draw_overlay(ui, view, image_pos, hovered);
+                }
+
+                ui.checkbox(im_str!("Scroll"), &mut self.show_scroll);
+                ui.same_line(0.0);
+                ui.checkbox(
+                    im_str!("Capture at scanline"),
+                    &mut self.capture_at_scanline,
+                );
+                ui.same_line(0.0);
+                ui.set_next_item_width(100.0);
+                if ui
+                    .input_int(im_str!("##capture"), &mut self.scanline)
+                    .build()
+                {
+                    let last = emulator.region.last_scanline() as i32;
+                    self.scanline = self.scanline.max(-1).min(last);
+                }
+                if let Some(view) = &self.view {
+                    ui.text(format!(
+                        "Scroll: {}, {} at scanline {}",
+                        view.scroll.0, view.scroll.1, view.scanline
+                    ));
+                }
+            });
+    }
+
+    fn draw_overlay(&self, ui: &imgui::Ui, view: &NametableView, origin: [f32; 2], hovered: bool) {
+        let draw_list = ui.get_window_draw_list();
+        let rect = |x0: usize, y0: usize, x1: usize, y1: usize, color: [f32; 4]| {
+            draw_list
+                .add_rect(
+                    [origin[0] + x0 as f32, origin[1] + y0 as f32],
+                    [origin[0] + x1 as f32, origin[1] + y1 as f32],
+                    color,
+                )
+                .build();
+        };
+
+        // Visible screen wraps around the nametables
+        if self.show_scroll {
+            let (width, height) = NAMETABLES_SIZE;
+            for &(x0, x1) in wrapped_spans(view.scroll.0, 256, width).iter() {
+                for &(y0, y1) in wrapped_spans(view.scroll.1, 240, height).iter() {
+                    rect(x0, y0, x1, y1, SCROLL_COLOR);
+                }
+            }
+        }
+
+        if !hovered {
+            return;
+        }
+        let mouse_pos = ui.io().mouse_pos;
+        let x = (mouse_pos[0] - origin[0]).max(0.0) as usize;
+        let y = (mouse_pos[1] - origin[1]).max(0.0) as usize;
+        if let Some(tile) = view.tile_at(x, y) {
+            let (x0, y0) = (x / 8 * 8, y / 8 * 8);
+            rect(x0, y0, x0 + 8, y0 + 8, HOVER_COLOR);
+            ui.tooltip_text(format!(
+                "Tile: ${:02X} at ${:04X}\nAttribute: ${:02X} at ${:04X}\nPalette: {}",
+                tile.tile, tile.addr, tile.attr, tile.attr_addr, tile.palette
+            ));
+        }
+    }
+}
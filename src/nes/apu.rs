@@ -0,0 +1,232 @@
+use super::bus::CpuBusDevice;
+use super::region::Region;
+use std::ops::Range;
+
+// DMC output periods in CPU cycles, Dendy runs the NTSC APU at its own CPU clock
+const DMC_RATES_NTSC: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const DMC_RATES_PAL: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+/// Delta modulation channel.
+///
+/// Only what the rest of the system sees is emulated: the timer which empties the
+/// sample buffer and the fetches the DMA unit makes to refill it, which steal CPU cycles.
+#[derive(Default)]
+pub struct Dmc {
+    irq_enabled: bool,
+    loop_sample: bool,
+    rate_index: usize,
+    output_level: u8,
+    sample_addr: u16,
+    sample_length: u16,
+    current_addr: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    // Fetch requested from the DMA unit and not done yet
+    fetching: bool,
+    timer: u16,
+    bits_remaining: u8,
+    shift_register: u8,
+    silence: bool,
+    // Set when a sample ends with IRQs enabled, the CPU has no IRQ line yet so it's
+    // only visible in $4015
+    irq: bool,
+}
+
+impl Dmc {
+    fn write_flags(&mut self, data: u8) {
+        self.irq_enabled = data & 0x80 != 0;
+        if !self.irq_enabled {
+            self.irq = false;
+        }
+        self.loop_sample = data & 0x40 != 0;
+        self.rate_index = (data & 0x0F) as usize;
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // Runs one CPU cycle, returns the address to fetch when the sample buffer is empty
+    fn clock(&mut self, period: u16) -> Option<u16> {
+        if self.timer == 0 {
+            self.timer = period - 1;
+            self.clock_output();
+        } else {
+            self.timer -= 1;
+        }
+
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 && !self.fetching {
+            self.fetching = true;
+            Some(self.current_addr)
+        } else {
+            None
+        }
+    }
+
+    fn clock_output(&mut self) {
+        if !self.silence {
+            if self.shift_register & 0x01 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining = self.bits_remaining.saturating_sub(1);
+
+        // Output cycle ends, the next one plays the sample buffer
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.shift_register = data;
+                    self.silence = false;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// Fills the sample buffer with the byte fetched by the DMA unit.
+    pub fn fill(&mut self, data: u8) {
+        self.fetching = false;
+        // Channel was disabled while the fetch was pending
+        if self.bytes_remaining == 0 {
+            return;
+        }
+        self.sample_buffer = Some(data);
+        self.current_addr = if self.current_addr == 0xFFFF {
+            0x8000
+        } else {
+            self.current_addr + 1
+        };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_sample {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+}
+
+/// Audio processing unit.
+///
+/// Sound generation is not emulated yet. The DMC runs for its sample fetches, and the
+/// frame counter register ($4017) is latched so writes to it no longer end up in the
+/// controller port.
+#[derive(Default)]
+pub struct Apu {
+    pub five_step_mode: bool,
+    pub irq_inhibit: bool,
+    pub region: Region,
+    pub dmc: Dmc,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu::default()
+    }
+
+    pub fn reset(&mut self) {
+        let region = self.region;
+        *self = Self::new();
+        self.region = region;
+    }
+
+    pub fn write_frame_counter(&mut self, data: u8) {
+        self.five_step_mode = (data & 0x80) != 0;
+        self.irq_inhibit = (data & 0x40) != 0;
+    }
+
+    fn dmc_rates(&self) -> &'static [u16; 16] {
+        match self.region {
+            Region::Pal => &DMC_RATES_PAL,
+            Region::Ntsc | Region::Dendy => &DMC_RATES_NTSC,
+        }
+    }
+
+    /// Runs one CPU cycle. Returns the address of a DMC sample fetch for the DMA unit.
+    pub fn clock(&mut self) -> Option<u16> {
+        let period = self.dmc_rates()[self.dmc.rate_index];
+        self.dmc.clock(period)
+    }
+}
+
+// $4010-$4013 DMC registers and $4015 status, $4014 is taken by the DMA unit
+impl CpuBusDevice for Apu {
+    fn get_addr_range(&self) -> &Range<u16> {
+        &(0x4010..0x4016)
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4010 => self.dmc.write_flags(data),
+            0x4011 => self.dmc.output_level = data & 0x7F,
+            0x4012 => self.dmc.sample_addr = 0xC000 | (data as u16) << 6,
+            0x4013 => self.dmc.sample_length = (data as u16) << 4 | 1,
+            0x4015 => self.dmc.set_enabled(data & 0x10 != 0),
+            _ => {}
+        }
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        if addr == 0x4015 {
+            (self.dmc.irq as u8) << 7 | ((self.dmc.bytes_remaining > 0) as u8) << 4
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dmc_fetches() {
+        let mut apu = Apu::new();
+        apu.cpu_write(0x4010, 0x8F);
+        apu.cpu_write(0x4012, 0x01);
+        apu.cpu_write(0x4013, 0x00);
+        assert_eq!(apu.clock(), None);
+
+        // Enabling starts the 1 byte sample at $C040
+        apu.cpu_write(0x4015, 0x10);
+        assert_eq!(apu.peek(0x4015), 0x10);
+        assert_eq!(apu.clock(), Some(0xC040));
+        assert_eq!(apu.clock(), None);
+        apu.dmc.fill(0x55);
+        assert_eq!(apu.peek(0x4015), 0x80);
+
+        // Next fetch comes when the buffer moves to the shift register after the
+        // current output cycle, 8 bits at 54 CPU cycles with the first one partly played
+        apu.cpu_write(0x4015, 0x10);
+        let cycles = (1..1000).find(|_| apu.clock().is_some());
+        assert_eq!(cycles, Some(430));
+        apu.cpu_write(0x4015, 0x00);
+        apu.dmc.fill(0xAA);
+        assert_eq!(apu.peek(0x4015), 0x00);
+    }
+}
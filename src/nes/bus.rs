@@ -7,8 +7,20 @@ struct DeviceConnection {
     addr_range: Range<u16>,
 }
 
+/// Memory access recorded for the debugger.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BusAccess {
+    pub addr: u16,
+    pub data: u8,
+    pub write: bool,
+}
+
 pub struct Bus {
     connections: Vec<DeviceConnection>,
+    pub last_read_addr: u16,
+    /// Accesses are recorded to `accesses` while set.
+    pub record_accesses: bool,
+    pub accesses: Vec<BusAccess>,
 }
 
 pub trait CpuBusDevice {
@@ -16,16 +28,28 @@ pub trait CpuBusDevice {
 
     fn cpu_write(&mut self, addr: u16, data: u8);
     fn cpu_read(&mut self, addr: u16) -> u8;
+    /// Value `cpu_read` would return, without changing any state. For debugging tools.
+    fn peek(&self, addr: u16) -> u8;
 }
 
 impl Bus {
     pub fn new() -> Self {
         Bus {
             connections: vec![],
+            last_read_addr: 0,
+            record_accesses: false,
+            accesses: vec![],
         }
     }
 
     pub fn cpu_write(&mut self, addr: u16, data: u8) {
+        if self.record_accesses {
+            self.accesses.push(BusAccess {
+                addr,
+                data,
+                write: true,
+            });
+        }
         for connection in &mut self.connections {
             if connection.addr_range.contains(&addr) {
                 let mut device = connection.device.borrow_mut();
@@ -38,6 +62,19 @@ impl Bus {
     }
 
     pub fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.last_read_addr = addr;
+        let data = self.read_device(addr);
+        if self.record_accesses {
+            self.accesses.push(BusAccess {
+                addr,
+                data,
+                write: false,
+            });
+        }
+        data
+    }
+
+    fn read_device(&mut self, addr: u16) -> u8 {
         for connection in &mut self.connections {
             if connection.addr_range.contains(&addr) {
                 let mut device = connection.device.borrow_mut();
@@ -49,6 +86,17 @@ impl Bus {
         0
     }
 
+    /// Reads without side effects, for debugging tools.
+    pub fn peek(&self, addr: u16) -> u8 {
+        for connection in &self.connections {
+            if connection.addr_range.contains(&addr) {
+                return connection.device.borrow().peek(addr);
+            }
+        }
+
+        0
+    }
+
     pub fn connect(&mut self, device: Rc<RefCell<dyn CpuBusDevice>>) {
         let addr_range = device.borrow_mut().get_addr_range().clone();
         self.connections.push(DeviceConnection {
@@ -78,5 +126,6 @@ mod tests {
         assert_eq!(b.cpu_read(25), 16);
         assert_eq!(b.cpu_read(24), 0);
         assert_eq!(b.cpu_read(5), 0);
+        assert_eq!(b.peek(25), 16);
     }
 }
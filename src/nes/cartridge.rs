@@ -1,9 +1,142 @@
 use super::bus::CpuBusDevice;
 use std::fs;
+use std::io::Read;
 use std::ops::Range;
 use std::path::PathBuf;
 
-use super::mappers::{Mapper, Mapper0, Mapper3};
+use super::mappers::{self, Mapper, Mapper0};
+use super::region::Region;
+
+pub const HEADER_SIZE: usize = 16;
+
+/// iNES header fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomHeader {
+    pub mapper: u8,
+    /// Number of 16K PRG-ROM pages.
+    pub prg_pages: u8,
+    /// Number of 8K CHR-ROM pages, 0 for CHR-RAM.
+    pub chr_pages: u8,
+    pub ram_pages: u8,
+    pub vertical_mirror: bool,
+    pub four_screen: bool,
+    pub battery: bool,
+    pub has_trainer: bool,
+    pub is_nes2: bool,
+    pub expansion_device: u8,
+    /// Region from the header or file name, `None` when unknown.
+    pub region: Option<Region>,
+}
+
+impl RomHeader {
+    /// Reads just the header of a ROM file, without loading the rest.
+    pub fn read(romfile: &PathBuf) -> Option<Self> {
+        let mut bytes = [0; HEADER_SIZE];
+        fs::File::open(romfile)
+            .and_then(|mut file| file.read_exact(&mut bytes))
+            .ok()?;
+        RomHeader::parse(&bytes, romfile)
+    }
+
+    /// Parses the 16 byte header, `None` if it's not an iNES file.
+    pub fn parse(contents: &[u8], romfile: &PathBuf) -> Option<Self> {
+        //////////////////////////////////////////////
+        // 16 byte      Header
+        //////////////////////////////////////////////
+
+        // 00h  File ID ("NES",1Ah) (aka 4Eh,45h,53h,1Ah)
+        if contents.len() < HEADER_SIZE || contents[0..4] != [b'N', b'E', b'S', 0x1A] {
+            return None;
+        }
+
+        // 04h  Number of 16K PRG-ROM pages
+        let prg_pages = contents[4];
+
+        // 05h  Number of 8K CHR-ROM pages (00h=None / VRAM)
+        let chr_pages = contents[5];
+
+        // 06h  Cartridge Type LSB
+        // Bit7-4  Mapper Number (lower 4bits)
+        // Bit3    1=Four-screen VRAM layout
+        // Bit2    1=512-byte trainer/patch at 7000h-71FFh
+        // Bit1    1=Battery-backed SRAM at 6000h-7FFFh, set only if battery-backed
+        // Bit0    0=Horizontal mirroring, 1=Vertical mirroring
+        let type_lsb = contents[6];
+
+        // 07h  Cartridge Type MSB (ignore this and further bytes if Byte 0Fh nonzero)
+        // Bit7-4  Mapper Number (upper 4bits)
+        // Bit3-2  Reserved (zero)
+        // Bit1    1=PC10 game (arcade machine with additional 8K Z80-ROM) (*)
+        // Bit0    1=VS Unisystem game (arcade machine with different palette)
+        let type_msb = contents[7];
+
+        // NES 2.0 header if Bit3-2 of 07h are 10b
+        let is_nes2 = (type_msb & 0x0C) == 0x08;
+
+        Some(RomHeader {
+            mapper: ((type_lsb >> 4) & 0xf) | (type_msb & 0xf0),
+            prg_pages,
+            chr_pages,
+            // 08h  Number of 8K RAM (SRAM?) pages (usually 00h=None-or-not-specified)
+            ram_pages: contents[8],
+            vertical_mirror: (type_lsb & (1 << 0)) != 0,
+            four_screen: (type_lsb & (1 << 3)) != 0,
+            battery: (type_lsb & (1 << 1)) != 0,
+            has_trainer: (type_lsb & (1 << 2)) != 0,
+            is_nes2,
+            // 0Fh  Default Expansion Device (NES 2.0)
+            // Bit5-0  Device (01h=Standard pads, 02h=Four Score, 03h=Famicom 4 players, 08h=Zapper)
+            expansion_device: if is_nes2 { contents[15] & 0x3F } else { 0 },
+            // 0Ch  CPU/PPU Timing (NES 2.0)
+            // Bit1-0  0=NTSC, 1=PAL, 2=Multiple-region, 3=Dendy
+            // iNES 1.0 TV system bits are rarely set correctly, guess from file name instead
+            region: if is_nes2 {
+                Some(Region::from_nes2_timing(contents[12]))
+            } else {
+                Region::from_file_name(romfile)
+            },
+        })
+    }
+
+    pub fn prg_rom_size(&self) -> usize {
+        (self.prg_pages as usize) * 0x4000
+    }
+
+    pub fn chr_rom_size(&self) -> usize {
+        (self.chr_pages as usize) * 0x2000
+    }
+}
+
+/// PRG-ROM offsets of the 8K windows at $8000, $A000, $C000 and $E000.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PrgMapping {
+    pub windows: [usize; 4],
+}
+
+impl PrgMapping {
+    /// PRG-ROM offset mapped at CPU address `addr`.
+    pub fn offset(&self, addr: u16) -> Option<usize> {
+        if addr < 0x8000 {
+            return None;
+        }
+        let addr = addr as usize - 0x8000;
+        Some(self.windows[addr >> 13] + (addr & 0x1FFF))
+    }
+
+    /// CPU address where PRG-ROM `offset` is mapped. When it's mapped to several
+    /// windows `hint` is preferred, otherwise the last one.
+    pub fn addr(&self, offset: usize, hint: Option<u16>) -> Option<u16> {
+        if let Some(hint) = hint {
+            if self.offset(hint) == Some(offset) {
+                return Some(hint);
+            }
+        }
+        self.windows
+            .iter()
+            .rposition(|&window| offset >= window && offset - window < 0x2000)
+            .map(|i| 0x8000 + (i * 0x2000 + offset - self.windows[i]) as u16)
+    }
+}
 
 pub struct Cartridge {
     prg_rom: Vec<u8>,
@@ -11,6 +144,9 @@ pub struct Cartridge {
     prg_ram: Vec<u8>,
     mapper: Box<dyn Mapper>,
     vertical_mirror: bool,
+    pub expansion_device: u8,
+    /// Region from the header or file name, `None` when unknown.
+    pub region: Option<Region>,
 }
 
 impl CpuBusDevice for Cartridge {
@@ -26,6 +162,11 @@ impl CpuBusDevice for Cartridge {
         let mapped_addr = self.mapper.map_read(addr);
         self.prg_rom[(mapped_addr as usize)]
     }
+
+    fn peek(&self, addr: u16) -> u8 {
+        let mapped_addr = self.mapper.map_peek(addr) as usize;
+        self.prg_rom.get(mapped_addr).copied().unwrap_or(0)
+    }
 }
 
 impl Cartridge {
@@ -36,6 +177,8 @@ impl Cartridge {
             prg_ram: vec![],
             mapper: Box::new(Mapper0::new(0)),
             vertical_mirror: false,
+            expansion_device: 0,
+            region: None,
         }
     }
 
@@ -52,77 +195,94 @@ impl Cartridge {
         self.chr_rom[mapped_addr]
     }
 
-    pub fn is_vertical_mirror(&self) -> bool {
-        // TODO: add mapper?
-        self.vertical_mirror
+    /// CHR read without changing mapper state, for debugging tools.
+    pub fn ppu_peek(&self, addr: u16) -> u8 {
+        let mapped_addr = self.mapper.map_ppu_peek(addr) as usize;
+        self.chr_rom.get(mapped_addr).copied().unwrap_or(0)
     }
 
-    pub fn load_from_file(&mut self, romfile: &PathBuf) {
-        // Load ROM from file
-        let contents = match fs::read(romfile) {
-            Err(e) => {
-                println!("Can't read file: '{0}'. Error: {1}", romfile.display(), e);
-                return;
-            }
-            Ok(f) => f,
-        };
+    /// Current PRG-ROM banking, to find code of symbols and source lines.
+    pub fn prg_mapping(&self) -> PrgMapping {
+        let mut mapping = PrgMapping::default();
+        for (i, window) in mapping.windows.iter_mut().enumerate() {
+            *window = self.mapper.map_peek(0x8000 + (i as u16) * 0x2000) as usize;
+        }
+        mapping
+    }
 
-        let mut next = 0;
+    /// Raw PRG-ROM, CHR-ROM (or RAM) and PRG-RAM contents for the memory viewer.
+    pub fn prg_rom(&self) -> &[u8] {
+        &self.prg_rom
+    }
 
-        //////////////////////////////////////////////
-        // 16 byte      Header
-        //////////////////////////////////////////////
+    pub fn chr_rom(&self) -> &[u8] {
+        &self.chr_rom
+    }
 
-        // 00h  File ID ("NES",1Ah) (aka 4Eh,45h,53h,1Ah)
-        if contents[0..4] != ['N' as u8, 'E' as u8, 'S' as u8, 0x1A] {
-            println!("Wrong file ID in nes file: '{0}'.", romfile.display());
-            return;
-        }
+    pub fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
 
-        // 04h  Number of 16K PRG-ROM pages
-        let rom_pages = contents[4];
+    pub fn prg_rom_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_rom
+    }
 
-        // 05h  Number of 8K CHR-ROM pages (00h=None / VRAM)
-        let chr_pages = contents[5];
+    pub fn chr_rom_mut(&mut self) -> &mut [u8] {
+        &mut self.chr_rom
+    }
 
-        // 06h  Cartridge Type LSB
-        // Bit7-4  Mapper Number (lower 4bits)
-        // Bit3    1=Four-screen VRAM layout
-        // Bit2    1=512-byte trainer/patch at 7000h-71FFh
-        // Bit1    1=Battery-backed SRAM at 6000h-7FFFh, set only if battery-backed
-        // Bit0    0=Horizontal mirroring, 1=Vertical mirroring
-        let type_lsb = contents[6];
-        let has_trainer = (type_lsb & (1 << 2)) != 0;
-        self.vertical_mirror = (type_lsb & (1 << 0)) != 0;
+    pub fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
 
-        // 07h  Cartridge Type MSB (ignore this and further bytes if Byte 0Fh nonzero)
-        // Bit7-4  Mapper Number (upper 4bits)
-        // Bit3-2  Reserved (zero)
-        // Bit1    1=PC10 game (arcade machine with additional 8K Z80-ROM) (*)
-        // Bit0    1=VS Unisystem game (arcade machine with different palette)
-        let type_msb = contents[7];
+    pub fn is_vertical_mirror(&self) -> bool {
+        // TODO: add mapper?
+        self.vertical_mirror
+    }
 
-        // Read mapper
-        let mapper_number = ((type_lsb >> 4) & 0xf) | (type_msb & 0xf0);
+    /// Loads an iNES file, the cartridge is left as it was when that fails.
+    pub fn load_from_file(&mut self, romfile: &PathBuf) -> Result<(), String> {
+        // Load ROM from file
+        let contents = fs::read(romfile)
+            .map_err(|e| format!("Can't read file: '{0}'. Error: {1}", romfile.display(), e))?;
 
-        // 08h  Number of 8K RAM (SRAM?) pages (usually 00h=None-or-not-specified)
-        let ram_pages = contents[8];
+        let header = match RomHeader::parse(&contents, romfile) {
+            Some(header) => header,
+            None => {
+                return Err(format!(
+                    "Wrong file ID in nes file: '{0}'.",
+                    romfile.display()
+                ))
+            }
+        };
+        let mapper = match mappers::create(header.mapper, header.prg_pages) {
+            Some(mapper) => mapper,
+            None => return Err(format!("Unsupported mapper: {:?}", header.mapper)),
+        };
+        let trainer_size = if header.has_trainer { 512 } else { 0 };
+        if contents.len()
+            < HEADER_SIZE + trainer_size + header.prg_rom_size() + header.chr_rom_size()
+        {
+            return Err(format!("ROM file is cut short: '{0}'.", romfile.display()));
+        }
+
+        self.vertical_mirror = header.vertical_mirror;
+        self.expansion_device = header.expansion_device;
+        self.region = header.region;
 
-        next += 16; // Header size
+        let mut next = HEADER_SIZE;
 
         //////////////////////////////////////////////
         // 512 byte      Trainer
         //////////////////////////////////////////////
 
-        if has_trainer {
-            next += 512;
-        }
+        next += trainer_size;
 
         //////////////////////////////////////////////
         // N*16K        PRG-ROM
         //////////////////////////////////////////////
         {
-            let rom_size = (rom_pages as usize) * 0x4000;
+            let rom_size = header.prg_rom_size();
             self.prg_rom = contents[next..next + rom_size].to_vec();
             println!("rom_size: {:?}", rom_size);
             next += rom_size;
@@ -132,7 +292,7 @@ impl Cartridge {
         // N*8K        CHR-ROM
         //////////////////////////////////////////////
         {
-            let chr_size = (chr_pages as usize) * 0x2000;
+            let chr_size = header.chr_rom_size();
             self.chr_rom = contents[next..next + chr_size].to_vec();
             println!("chr_size: {:?}", chr_size);
             //next += chr_size;
@@ -142,19 +302,11 @@ impl Cartridge {
         // N*8K        PRG-RAM
         //////////////////////////////////////////////
         {
-            let ram_size = (ram_pages as usize) * 0x204C;
+            let ram_size = (header.ram_pages as usize) * 0x204C;
             self.prg_ram = vec![0; ram_size];
         }
 
-        // Load mapper
-        match mapper_number {
-            0 => self.mapper = Box::new(Mapper0::new(rom_pages)),
-            3 => self.mapper = Box::new(Mapper3::new(rom_pages)),
-            _ => {
-                println!("Unsupported mapper: {:?}", mapper_number);
-                return;
-            }
-        }
+        self.mapper = mapper;
 
         /*
         iNES Format (.NES)
@@ -170,5 +322,51 @@ impl Cartridge {
         Items marked as (*) are regulary used, but not offical part of the format.
         Many PC10 files declare Z80-ROM as additional VROM bank (instead Byte7/Bit1).
                 */
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_header() {
+        let romfile = PathBuf::from("Game (E).nes");
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[0..8].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 2, 1, 0x43, 0x00]);
+        let header = RomHeader::parse(&bytes, &romfile).unwrap();
+        assert_eq!(header.mapper, 4);
+        assert_eq!(
+            (header.prg_rom_size(), header.chr_rom_size()),
+            (0x8000, 0x2000)
+        );
+        assert!(header.vertical_mirror && header.battery && !header.has_trainer);
+        assert_eq!(header.region, Some(Region::Pal));
+        assert!(!mappers::is_supported(header.mapper));
+
+        // NES 2.0 with Dendy timing
+        bytes[7] = 0x08;
+        bytes[12] = 3;
+        let header = RomHeader::parse(&bytes, &romfile).unwrap();
+        assert!(header.is_nes2);
+        assert_eq!(header.region, Some(Region::Dendy));
+
+        bytes[0] = 0;
+        assert_eq!(RomHeader::parse(&bytes, &romfile), None);
+    }
+
+    #[test]
+    fn prg_mapping() {
+        // 16K ROM mirrored at $8000 and $C000
+        let mut cartridge = Cartridge::new();
+        cartridge.mapper = mappers::create(0, 1).unwrap();
+        let mapping = cartridge.prg_mapping();
+        assert_eq!(mapping.windows, [0, 0x2000, 0, 0x2000]);
+        assert_eq!(mapping.offset(0xC123), Some(0x0123));
+        assert_eq!(mapping.offset(0x6000), None);
+        assert_eq!(mapping.addr(0x0123, None), Some(0xC123));
+        assert_eq!(mapping.addr(0x0123, Some(0x8123)), Some(0x8123));
+        assert_eq!(mapping.addr(0x4000, None), None);
     }
 }
@@ -1,40 +1,179 @@
+use super::apu::Apu;
 use super::bus::CpuBusDevice;
+use super::multitap::Multitap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::ops::Range;
+use std::rc::Rc;
 
-// Support only one for now
+/// Device plugged into one of the two controller ports (standard pad, Zapper,
+/// Four Score, Power Pad, Arkanoid paddle...).
+pub trait ControllerPort {
+    /// Write to $4016. Bits 0-2 are the OUT0-OUT2 lines, OUT0 is the strobe.
+    fn write(&mut self, data: u8);
 
+    /// Read from the port register, returns the D0-D4 lines.
+    fn read(&mut self) -> u8;
+
+    /// Value the next `read` returns, without shifting anything.
+    fn peek(&self) -> u8;
+}
+
+/// Device types that can be plugged into a controller port.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum PortDevice {
+    Controller,
+    Zapper,
+}
+
+/// Devices plugged into the controller ports.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct InputSetup {
+    pub multitap: Multitap,
+    pub port_devices: [PortDevice; 2],
+}
+
+impl Default for InputSetup {
+    fn default() -> Self {
+        InputSetup {
+            multitap: Multitap::None,
+            port_devices: [PortDevice::Controller; 2],
+        }
+    }
+}
+
+impl InputSetup {
+    /// Setup requested by the NES 2.0 "default expansion device" header field, if any.
+    pub fn from_expansion_device(device: u8) -> Option<Self> {
+        let mut setup = InputSetup::default();
+        match device {
+            0x01 => {}
+            0x02 => setup.multitap = Multitap::FourScore,
+            0x03 => setup.multitap = Multitap::FamicomExpansion,
+            0x08 => setup.port_devices[1] = PortDevice::Zapper,
+            _ => return None,
+        }
+        Some(setup)
+    }
+}
+
+/// Standard controller. `input` bits from high to low: A, B, Select, Start, Up, Down,
+/// Left, Right.
 pub struct Controller {
     pub input: u8,
-    pub state: u8,
-    pub num: u16,
+    pub shift: u8,
+    pub strobe: bool,
 }
 
-impl CpuBusDevice for Controller {
-    fn get_addr_range(&self) -> &Range<u16> {
-        if self.num == 0 {
-            &(0x4016..0x4017)
-        } else {
-            &(0x4017..0x4018)
+impl ControllerPort for Controller {
+    fn write(&mut self, data: u8) {
+        self.strobe = (data & 0x01) != 0;
+        if self.strobe {
+            self.shift = self.input;
         }
     }
 
-    fn cpu_write(&mut self, _: u16, _: u8) {
-        self.state = self.input;
+    fn read(&mut self) -> u8 {
+        // While strobe is high the shift register keeps reloading, so A is returned
+        if self.strobe {
+            self.shift = self.input;
+        }
+        let data = self.peek();
+        // Official pads shift in 1s, so reads after the 8th return 1
+        self.shift = (self.shift << 1) | 0x01;
+        data
     }
 
-    fn cpu_read(&mut self, _: u16) -> u8 {
-        let data = ((self.state & 0x80) > 0) as u8;
-        self.state <<= 1;
-        data
+    fn peek(&self) -> u8 {
+        let shift = if self.strobe { self.input } else { self.shift };
+        ((shift & 0x80) > 0) as u8
     }
 }
 
 impl Controller {
-    pub fn new(num: u16) -> Self {
+    pub fn new() -> Self {
         Controller {
             input: 0,
-            state: 0,
-            num: num,
+            shift: 0,
+            strobe: false,
+        }
+    }
+}
+
+/// $4016/$4017 registers: controller port strobe and data lines, $4017 writes go to
+/// the APU frame counter.
+pub struct ControllerPorts {
+    pub ports: [Rc<RefCell<dyn ControllerPort>>; 2],
+    apu: Rc<RefCell<Apu>>,
+}
+
+impl CpuBusDevice for ControllerPorts {
+    fn get_addr_range(&self) -> &Range<u16> {
+        &(0x4016..0x4018)
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr == 0x4016 {
+            for port in &self.ports {
+                port.borrow_mut().write(data);
+            }
+        } else {
+            self.apu.borrow_mut().write_frame_counter(data);
         }
     }
+
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let port = &self.ports[(addr - 0x4016) as usize];
+        // Upper bits are open bus, which holds the high byte of the address
+        (port.borrow_mut().read() & 0x1F) | 0x40
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        let port = &self.ports[(addr - 0x4016) as usize];
+        (port.borrow().peek() & 0x1F) | 0x40
+    }
+}
+
+impl ControllerPorts {
+    pub fn new(
+        port0: Rc<RefCell<dyn ControllerPort>>,
+        port1: Rc<RefCell<dyn ControllerPort>>,
+        apu: Rc<RefCell<Apu>>,
+    ) -> Self {
+        ControllerPorts {
+            ports: [port0, port1],
+            apu,
+        }
+    }
+
+    /// Plugs `device` into port `num` (0 or 1).
+    pub fn connect(&mut self, num: usize, device: Rc<RefCell<dyn ControllerPort>>) {
+        self.ports[num] = device;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn controller_strobe() {
+        let mut c = Controller::new();
+        c.input = 0b1010_0000;
+
+        // Strobe held high keeps returning A
+        c.write(1);
+        assert_eq!(c.read(), 1);
+        c.input = 0b0010_0000;
+        assert_eq!(c.read(), 0);
+
+        c.input = 0b1010_0000;
+        c.write(1);
+        c.write(0);
+        c.input = 0;
+        // Peeking doesn't shift
+        assert_eq!((c.peek(), c.peek()), (1, 1));
+        let bits: Vec<u8> = (0..10).map(|_| c.read()).collect();
+        assert_eq!(bits, [1, 0, 1, 0, 0, 0, 0, 0, 1, 1]);
+    }
 }
@@ -29,7 +29,7 @@ pub fn to_u16(hi: u8, lo: u8) -> u16 {
     return (hi as u16) << 8 | (lo as u16);
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AddressingMode {
     ABS, // Absolute
     ABX, //
@@ -0,0 +1,520 @@
+use super::bus::BusAccess;
+use super::disasm::Labels;
+use super::source_map::SourceLine;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakKind {
+    Exec,
+    Read,
+    Write,
+}
+
+impl BreakKind {
+    pub const ALL: [BreakKind; 3] = [BreakKind::Exec, BreakKind::Read, BreakKind::Write];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BreakKind::Exec => "Exec",
+            BreakKind::Read => "Read",
+            BreakKind::Write => "Write",
+        }
+    }
+}
+
+/// Values a breakpoint condition can look at.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Context {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub p: u8,
+    pub scanline: i16,
+    pub cycle: i16,
+    /// Accessed address and value for read and write breakpoints.
+    pub addr: u16,
+    pub value: u8,
+    /// Source line of PC, filled only when stepping by lines.
+    pub source_line: Option<SourceLine>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    Number(i32),
+    A,
+    X,
+    Y,
+    SP,
+    PC,
+    P,
+    Scanline,
+    Cycle,
+    Addr,
+    Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// Checked from the longest, so "<=" isn't taken for "<"
+const COMPARE_OPS: [(&str, CompareOp); 6] = [
+    ("==", CompareOp::Eq),
+    ("!=", CompareOp::Ne),
+    ("<=", CompareOp::Le),
+    (">=", CompareOp::Ge),
+    ("<", CompareOp::Lt),
+    (">", CompareOp::Gt),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Comparison {
+    left: Operand,
+    op: CompareOp,
+    right: Operand,
+}
+
+/// Breakpoint condition like `A == $10 && X != 0 || scanline > 240`.
+///
+/// Operands are registers (A, X, Y, SP, PC, P), `scanline`, `cycle`, the accessed
+/// `addr` and `value`, and numbers in decimal, `$hex` or `0xhex`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    text: String,
+    // Any of the groups must hold, with all comparisons in the group
+    any: Vec<Vec<Comparison>>,
+}
+
+fn parse_number(s: &str) -> Option<i32> {
+    if s.starts_with('$') || s.starts_with("0x") {
+        let hex = s.trim_start_matches('$').trim_start_matches("0x");
+        i32::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+fn parse_operand(s: &str) -> Result<Operand, String> {
+    let operand = match s.to_lowercase().as_str() {
+        "a" => Operand::A,
+        "x" => Operand::X,
+        "y" => Operand::Y,
+        "sp" => Operand::SP,
+        "pc" => Operand::PC,
+        "p" => Operand::P,
+        "scanline" => Operand::Scanline,
+        "cycle" => Operand::Cycle,
+        "addr" => Operand::Addr,
+        "value" => Operand::Value,
+        _ => {
+            let number = parse_number(s).ok_or(format!("unknown operand '{}'", s))?;
+            Operand::Number(number)
+        }
+    };
+    Ok(operand)
+}
+
+fn parse_comparison(s: &str) -> Result<Comparison, String> {
+    for (token, op) in COMPARE_OPS.iter() {
+        if let Some(pos) = s.find(token) {
+            return Ok(Comparison {
+                left: parse_operand(s[..pos].trim())?,
+                op: *op,
+                right: parse_operand(s[pos + token.len()..].trim())?,
+            });
+        }
+    }
+    // Just an operand is true when not zero
+    Ok(Comparison {
+        left: parse_operand(s.trim())?,
+        op: CompareOp::Ne,
+        right: Operand::Number(0),
+    })
+}
+
+impl Operand {
+    fn value(&self, context: &Context) -> i32 {
+        match *self {
+            Operand::Number(n) => n,
+            Operand::A => context.a as i32,
+            Operand::X => context.x as i32,
+            Operand::Y => context.y as i32,
+            Operand::SP => context.sp as i32,
+            Operand::PC => context.pc as i32,
+            Operand::P => context.p as i32,
+            Operand::Scanline => context.scanline as i32,
+            Operand::Cycle => context.cycle as i32,
+            Operand::Addr => context.addr as i32,
+            Operand::Value => context.value as i32,
+        }
+    }
+}
+
+impl Comparison {
+    fn eval(&self, context: &Context) -> bool {
+        let left = self.left.value(context);
+        let right = self.right.value(context);
+        match self.op {
+            CompareOp::Eq => left == right,
+            CompareOp::Ne => left != right,
+            CompareOp::Lt => left < right,
+            CompareOp::Le => left <= right,
+            CompareOp::Gt => left > right,
+            CompareOp::Ge => left >= right,
+        }
+    }
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let any = text
+            .split("||")
+            .map(|group| group.split("&&").map(parse_comparison).collect())
+            .collect::<Result<_, _>>()?;
+        Ok(Condition {
+            text: text.trim().to_string(),
+            any,
+        })
+    }
+
+    pub fn eval(&self, context: &Context) -> bool {
+        self.any
+            .iter()
+            .any(|group| group.iter().all(|cmp| cmp.eval(context)))
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+pub struct Breakpoint {
+    pub kind: BreakKind,
+    /// First and last address, inclusive.
+    pub start: u16,
+    pub end: u16,
+    pub condition: Option<Condition>,
+    pub enabled: bool,
+}
+
+impl Breakpoint {
+    pub fn new(kind: BreakKind, start: u16, end: u16) -> Self {
+        Breakpoint {
+            kind,
+            start,
+            end,
+            condition: None,
+            enabled: true,
+        }
+    }
+
+    /// Parses address like `C000`, `$C000`, label from `labels` or range `C000-C0FF`,
+    /// and optional condition. Labels win over hex numbers, `$` always means hex.
+    pub fn parse(
+        kind: BreakKind,
+        addr: &str,
+        condition: &str,
+        labels: Option<&dyn Labels>,
+    ) -> Result<Self, String> {
+        let parse_addr = |s: &str| {
+            let s = s.trim();
+            let label = if s.starts_with('$') {
+                None
+            } else {
+                labels.and_then(|labels| labels.address(s))
+            };
+            label
+                .or_else(|| u16::from_str_radix(s.trim_start_matches('$'), 16).ok())
+                .ok_or(format!("bad address '{}'", s))
+        };
+        let mut parts = addr.splitn(2, '-');
+        let start = parse_addr(parts.next().unwrap_or(""))?;
+        let end = match parts.next() {
+            Some(end) => parse_addr(end)?,
+            None => start,
+        };
+        if end < start {
+            return Err("range end is before start".to_string());
+        }
+
+        let mut bp = Breakpoint::new(kind, start, end);
+        if !condition.trim().is_empty() {
+            bp.condition = Some(Condition::parse(condition)?);
+        }
+        Ok(bp)
+    }
+
+    fn hits(&self, kind: BreakKind, addr: u16, context: &Context) -> bool {
+        self.enabled
+            && self.kind == kind
+            && addr >= self.start
+            && addr <= self.end
+            && self.condition.as_ref().map_or(true, |c| c.eval(context))
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ${:04X}", self.kind.name(), self.start)?;
+        if self.end != self.start {
+            write!(f, "-${:04X}", self.end)?;
+        }
+        if let Some(condition) = &self.condition {
+            write!(f, " if {}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    Instruction,
+    /// Runs a subroutine called by JSR as one instruction.
+    Over,
+    /// Runs until the current subroutine returns.
+    Out,
+    /// Runs until PC gets to another source line.
+    Line,
+    Scanline(i16),
+    Frame,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StepState {
+    Instruction,
+    // Stops at `pc` with the stack back to `sp`
+    Return { pc: u16, sp: u8 },
+    // Stops when the stack gets above `sp`
+    Out { sp: u8 },
+    Line { start: Option<SourceLine> },
+    // `left` is set once the PPU is on another scanline
+    Scanline { scanline: i16, left: bool },
+    Frame,
+}
+
+/// Breakpoints and stepping state, `Emulator` checks them on every instruction.
+#[derive(Default)]
+pub struct Debugger {
+    pub paused: bool,
+    pub breakpoints: Vec<Breakpoint>,
+    /// Why execution stopped, shown to user.
+    pub break_reason: Option<String>,
+    step: Option<StepState>,
+    // Read or write breakpoint hit, execution stops after the instruction
+    pending_break: Option<String>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+
+    pub fn pause(&mut self, reason: &str) {
+        self.paused = true;
+        self.step = None;
+        self.break_reason = Some(reason.to_string());
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.step = None;
+        self.break_reason = None;
+    }
+
+    /// Resumes until `step` is done. `jsr_at_pc` tells if the next instruction is
+    /// JSR, to step over it.
+    pub fn step(&mut self, step: Step, context: &Context, jsr_at_pc: bool) {
+        let state = match step {
+            Step::Instruction => StepState::Instruction,
+            Step::Over if jsr_at_pc => StepState::Return {
+                pc: context.pc.wrapping_add(3),
+                sp: context.sp,
+            },
+            Step::Over => StepState::Instruction,
+            Step::Out => StepState::Out { sp: context.sp },
+            Step::Line => StepState::Line {
+                start: context.source_line,
+            },
+            Step::Scanline(scanline) => StepState::Scanline {
+                scanline,
+                left: context.scanline != scanline,
+            },
+            Step::Frame => StepState::Frame,
+        };
+        self.resume();
+        self.step = Some(state);
+    }
+
+    /// Looking up source line of every instruction is needed only for line steps.
+    pub fn needs_source_line(&self) -> bool {
+        matches!(self.step, Some(StepState::Line { .. }))
+    }
+
+    /// Bus accesses have to be recorded only for read and write breakpoints.
+    pub fn watches_memory(&self) -> bool {
+        self.breakpoints
+            .iter()
+            .any(|bp| bp.enabled && bp.kind != BreakKind::Exec)
+    }
+
+    /// Checks bus accesses of an instruction, except reads of the instruction itself.
+    pub fn check_accesses(&mut self, accesses: &[BusAccess], ins_len: u16, context: &Context) {
+        for access in accesses {
+            let kind = if access.write {
+                BreakKind::Write
+            } else if access.addr.wrapping_sub(context.pc) < ins_len {
+                continue;
+            } else {
+                BreakKind::Read
+            };
+            let context = Context {
+                addr: access.addr,
+                value: access.data,
+                ..*context
+            };
+            if let Some(bp) = self
+                .breakpoints
+                .iter()
+                .find(|bp| bp.hits(kind, access.addr, &context))
+            {
+                self.pending_break = Some(format!("{} (${:02X})", bp, access.data));
+                return;
+            }
+        }
+    }
+
+    /// Called before each instruction, returns true when execution has to stop.
+    pub fn on_instruction(&mut self, context: &Context) -> bool {
+        if let Some(reason) = self.pending_break.take() {
+            self.pause(&reason);
+            return true;
+        }
+
+        let step_done = match &mut self.step {
+            None | Some(StepState::Frame) => false,
+            Some(StepState::Instruction) => true,
+            Some(StepState::Return { pc, sp }) => context.pc == *pc && context.sp >= *sp,
+            Some(StepState::Out { sp }) => context.sp > *sp,
+            Some(StepState::Line { start }) => {
+                context.source_line.is_some() && context.source_line != *start
+            }
+            Some(StepState::Scanline { scanline, left }) => {
+                if context.scanline != *scanline {
+                    *left = true;
+                    false
+                } else {
+                    *left
+                }
+            }
+        };
+        if step_done {
+            self.pause("Step");
+            return true;
+        }
+
+        if let Some(bp) = self
+            .breakpoints
+            .iter()
+            .find(|bp| bp.hits(BreakKind::Exec, context.pc, context))
+        {
+            let reason = bp.to_string();
+            self.pause(&reason);
+            return true;
+        }
+        false
+    }
+
+    /// Called when the PPU completes a frame.
+    pub fn on_frame(&mut self) {
+        if self.step == Some(StepState::Frame) {
+            self.pause("Frame");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conditions() {
+        let context = Context {
+            a: 0x10,
+            x: 3,
+            scanline: 241,
+            ..Context::default()
+        };
+        let eval = |text| Condition::parse(text).unwrap().eval(&context);
+        assert!(eval("A == $10"));
+        assert!(eval("a == 0x10 && X < 4"));
+        assert!(!eval("A == 16 && X >= 4"));
+        assert!(eval("X > 5 || scanline == 241"));
+        assert!(eval("X"));
+        assert!(!eval("Y"));
+        assert!(Condition::parse("Q == 1").is_err());
+        assert!(Condition::parse("A == $zz").is_err());
+    }
+
+    #[test]
+    fn exec_breakpoint() {
+        let mut debugger = Debugger::new();
+        let bp = Breakpoint::parse(BreakKind::Exec, "$C000-C010", "A == 1", None).unwrap();
+        debugger.breakpoints.push(bp);
+        assert!(Breakpoint::parse(BreakKind::Exec, "C010-C000", "", None).is_err());
+        assert!(Breakpoint::parse(BreakKind::Read, "10000", "", None).is_err());
+
+        let mut labels = std::collections::HashMap::new();
+        labels.insert(0x10, "counter".to_string());
+        labels.insert(0x20, "add".to_string());
+        let bp = Breakpoint::parse(BreakKind::Write, "counter", "", Some(&labels)).unwrap();
+        assert_eq!((bp.start, bp.end), (0x10, 0x10));
+        // Labels which are also hex numbers
+        let bp = Breakpoint::parse(BreakKind::Write, "add", "", Some(&labels)).unwrap();
+        assert_eq!(bp.start, 0x20);
+        let bp = Breakpoint::parse(BreakKind::Write, "$add", "", Some(&labels)).unwrap();
+        assert_eq!(bp.start, 0xADD);
+
+        let mut context = Context {
+            pc: 0xC004,
+            ..Context::default()
+        };
+        assert!(!debugger.on_instruction(&context));
+        context.a = 1;
+        assert!(debugger.on_instruction(&context));
+        assert!(debugger.paused);
+        assert_eq!(
+            debugger.break_reason.as_deref(),
+            Some("Exec $C000-$C010 if A == 1")
+        );
+    }
+
+    #[test]
+    fn line_step() {
+        let mut debugger = Debugger::new();
+        let line = |line| Some(SourceLine { file: 0, line });
+        let mut context = Context {
+            source_line: line(10),
+            ..Context::default()
+        };
+        debugger.step(Step::Line, &context, false);
+        assert!(debugger.needs_source_line());
+
+        // Second instruction of the line and code without source
+        assert!(!debugger.on_instruction(&context));
+        context.source_line = None;
+        assert!(!debugger.on_instruction(&context));
+        context.source_line = line(11);
+        assert!(debugger.on_instruction(&context));
+        assert!(!debugger.needs_source_line());
+    }
+}
@@ -1,10 +1,10 @@
-use super::bus::Bus;
-use super::cpu::{AddressingMode, Instruction, Opcode, INSTRUCTION_LOOKUP};
+use super::cpu::{to_u16, AddressingMode, Instruction, Opcode, INSTRUCTION_LOOKUP};
+use std::collections::HashMap;
 use std::fmt;
-use std::io::Write;
+use std::io::{self, Write};
 
-fn get_instruction_size(ins: &Instruction) -> u16 {
-    match ins.mode {
+fn mode_size(mode: AddressingMode) -> u16 {
+    match mode {
         AddressingMode::ABS => 3,
         AddressingMode::ABX => 3,
         AddressingMode::ABY => 3,
@@ -21,9 +21,130 @@ fn get_instruction_size(ins: &Instruction) -> u16 {
     }
 }
 
-impl fmt::Display for Opcode {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let name = match *self {
+pub fn get_instruction_size(ins: &Instruction) -> u16 {
+    mode_size(ins.mode)
+}
+
+// Opcodes the CPU doesn't officially support, with the names ca65 uses for them
+fn unofficial(code: u8) -> Option<(&'static str, AddressingMode)> {
+    use AddressingMode::*;
+    // Read-modify-write combos share the layout of the official ALU columns
+    let combo_mode = |code: u8| match code & 0x1F {
+        0x03 => Some(IZX),
+        0x07 => Some(ZP0),
+        0x0F => Some(ABS),
+        0x13 => Some(IZY),
+        0x17 => Some(ZPX),
+        0x1B => Some(ABY),
+        0x1F => Some(ABX),
+        _ => None,
+    };
+    let combo = match code >> 5 {
+        0 => "SLO",
+        1 => "RLA",
+        2 => "SRE",
+        3 => "RRA",
+        6 => "DCP",
+        7 => "ISC",
+        _ => "",
+    };
+    if !combo.is_empty() {
+        if let Some(mode) = combo_mode(code) {
+            return Some((combo, mode));
+        }
+    }
+
+    let op = match code {
+        0x83 => ("SAX", IZX),
+        0x87 => ("SAX", ZP0),
+        0x8F => ("SAX", ABS),
+        0x97 => ("SAX", ZPY),
+        0xA3 => ("LAX", IZX),
+        0xA7 => ("LAX", ZP0),
+        0xAB => ("LAX", IMM),
+        0xAF => ("LAX", ABS),
+        0xB3 => ("LAX", IZY),
+        0xB7 => ("LAX", ZPY),
+        0xBF => ("LAX", ABY),
+        0x0B | 0x2B => ("ANC", IMM),
+        0x4B => ("ALR", IMM),
+        0x6B => ("ARR", IMM),
+        0x8B => ("XAA", IMM),
+        0xCB => ("AXS", IMM),
+        0xEB => ("SBC", IMM),
+        0x93 => ("AHX", IZY),
+        0x9F => ("AHX", ABY),
+        0x9B => ("TAS", ABY),
+        0x9C => ("SHY", ABX),
+        0x9E => ("SHX", ABY),
+        0xBB => ("LAS", ABY),
+        0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xB2 | 0xD2 | 0xF2 => {
+            ("JAM", IMP)
+        }
+        0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => ("NOP", IMP),
+        0x80 | 0x82 | 0x89 | 0xC2 | 0xE2 => ("NOP", IMM),
+        0x04 | 0x44 | 0x64 => ("NOP", ZP0),
+        0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 => ("NOP", ZPX),
+        0x0C => ("NOP", ABS),
+        0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => ("NOP", ABX),
+        _ => return None,
+    };
+    Some(op)
+}
+
+// Unofficial opcodes ca65 assembles back to the same byte, others are dumped as data
+fn ca65_supports(code: u8) -> bool {
+    match unofficial(code) {
+        None => true,
+        Some(("SLO", _)) | Some(("RLA", _)) | Some(("SRE", _)) | Some(("RRA", _)) => true,
+        Some(("DCP", _)) | Some(("ISC", _)) | Some(("SAX", _)) => true,
+        Some(("LAX", mode)) => mode != AddressingMode::IMM,
+        _ => matches!(code, 0x0B | 0x4B | 0x6B | 0xCB),
+    }
+}
+
+/// Names for addresses, substituted into operands.
+pub trait Labels {
+    fn label(&self, addr: u16) -> Option<&str>;
+
+    /// Address of label `name`.
+    fn address(&self, name: &str) -> Option<u16>;
+}
+
+impl Labels for HashMap<u16, String> {
+    fn label(&self, addr: u16) -> Option<&str> {
+        self.get(&addr).map(String::as_str)
+    }
+
+    fn address(&self, name: &str) -> Option<u16> {
+        self.iter()
+            .find(|(_, label)| label.as_str() == name)
+            .map(|(addr, _)| *addr)
+    }
+}
+
+/// One decoded instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisasmInstruction {
+    pub addr: u16,
+    /// Opcode followed by operand bytes, `len` of them.
+    pub bytes: [u8; 3],
+    pub len: u16,
+    pub mnemonic: &'static str,
+    pub official: bool,
+    pub mode: AddressingMode,
+    /// Operand as written in assembly, like `$10,X`, with labels substituted.
+    pub operand: String,
+    /// Address the operand refers to, for branches it's the target.
+    pub target: Option<u16>,
+    /// Address and value accessed with current registers, filled by `annotate`.
+    pub effective_addr: Option<u16>,
+    pub annotation: String,
+}
+
+impl Opcode {
+    pub fn name(&self) -> &'static str {
+        match *self {
             Opcode::ADC => "ADC",
             Opcode::AND => "AND",
             Opcode::ASL => "ASL",
@@ -81,25 +202,372 @@ impl fmt::Display for Opcode {
             Opcode::TXA => "TXA",
             Opcode::TXS => "TXS",
             Opcode::TYA => "TYA",
-        };
-        write!(f, "{}", name)
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+fn format_operand(
+    mode: AddressingMode,
+    bytes: &[u8; 3],
+    target: Option<u16>,
+    label: Option<&str>,
+) -> String {
+    let name = |default: String| label.map_or(default, str::to_string);
+    let zp = bytes[1];
+    let abs = to_u16(bytes[2], bytes[1]);
+    match mode {
+        AddressingMode::IMP => String::new(),
+        AddressingMode::ACC => "A".to_string(),
+        AddressingMode::IMM => format!("#${:02X}", zp),
+        AddressingMode::ZP0 => name(format!("${:02X}", zp)),
+        AddressingMode::ZPX => format!("{},X", name(format!("${:02X}", zp))),
+        AddressingMode::ZPY => format!("{},Y", name(format!("${:02X}", zp))),
+        AddressingMode::IZX => format!("({},X)", name(format!("${:02X}", zp))),
+        AddressingMode::IZY => format!("({}),Y", name(format!("${:02X}", zp))),
+        AddressingMode::ABS => name(format!("${:04X}", abs)),
+        AddressingMode::ABX => format!("{},X", name(format!("${:04X}", abs))),
+        AddressingMode::ABY => format!("{},Y", name(format!("${:04X}", abs))),
+        AddressingMode::IND => format!("({})", name(format!("${:04X}", abs))),
+        AddressingMode::REL => name(format!("${:04X}", target.unwrap_or(0))),
+    }
+}
+
+/// Decodes the instruction at `addr`, reading memory with `read`.
+pub fn decode(
+    read: &mut impl FnMut(u16) -> u8,
+    addr: u16,
+    labels: Option<&dyn Labels>,
+) -> DisasmInstruction {
+    let code = read(addr);
+    let (mnemonic, mode, official) = match unofficial(code) {
+        Some((mnemonic, mode)) => (mnemonic, mode, false),
+        None => {
+            let ins = &INSTRUCTION_LOOKUP[code as usize];
+            (ins.opcode.name(), ins.mode, true)
+        }
+    };
+
+    let len = mode_size(mode);
+    let mut bytes = [code, 0, 0];
+    for i in 1..len {
+        bytes[i as usize] = read(addr.wrapping_add(i));
+    }
+
+    let target = match mode {
+        AddressingMode::REL => Some(addr.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16)),
+        AddressingMode::ZP0
+        | AddressingMode::ZPX
+        | AddressingMode::ZPY
+        | AddressingMode::IZX
+        | AddressingMode::IZY => Some(bytes[1] as u16),
+        AddressingMode::ABS | AddressingMode::ABX | AddressingMode::ABY | AddressingMode::IND => {
+            Some(to_u16(bytes[2], bytes[1]))
+        }
+        _ => None,
+    };
+    let label = target.and_then(|target| labels.and_then(|labels| labels.label(target)));
+
+    DisasmInstruction {
+        addr,
+        bytes,
+        len,
+        mnemonic,
+        official,
+        mode,
+        operand: format_operand(mode, &bytes, target, label),
+        target,
+        effective_addr: None,
+        annotation: String::new(),
     }
 }
 
-#[allow(dead_code)]
-pub fn disasm(out: &mut impl Write, bus: &mut Bus, addr: u16) {
-    let ins_code = bus.cpu_read(addr);
+impl DisasmInstruction {
+    /// Instruction bytes.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
 
-    let ins = &INSTRUCTION_LOOKUP[ins_code as usize];
+    pub fn is_jump(&self) -> bool {
+        self.mode == AddressingMode::REL
+            || (self.mode == AddressingMode::ABS && matches!(self.mnemonic, "JMP" | "JSR"))
+    }
 
-    let ins_size = get_instruction_size(ins);
-    for i in 0..4 {
-        if i < ins_size {
-            write!(out, "{:02X} ", bus.cpu_read(addr + i)).unwrap();
+    /// Fills the effective address and annotation in nestest style, like `@ 0300 = 5A`,
+    /// for current X and Y registers.
+    pub fn annotate(&mut self, read: &mut impl FnMut(u16) -> u8, x: u8, y: u8) {
+        let zp = self.bytes[1];
+        let abs = to_u16(self.bytes[2], self.bytes[1]);
+        let read_ptr = |read: &mut dyn FnMut(u16) -> u8, ptr: u8| {
+            to_u16(read(ptr.wrapping_add(1) as u16), read(ptr as u16))
+        };
+        let (addr, annotation) = match self.mode {
+            AddressingMode::ZP0 => (zp as u16, String::new()),
+            AddressingMode::ZPX => {
+                let addr = zp.wrapping_add(x) as u16;
+                (addr, format!("@ {:02X} ", addr))
+            }
+            AddressingMode::ZPY => {
+                let addr = zp.wrapping_add(y) as u16;
+                (addr, format!("@ {:02X} ", addr))
+            }
+            AddressingMode::ABS if !self.is_jump() => (abs, String::new()),
+            AddressingMode::ABX => {
+                let addr = abs.wrapping_add(x as u16);
+                (addr, format!("@ {:04X} ", addr))
+            }
+            AddressingMode::ABY => {
+                let addr = abs.wrapping_add(y as u16);
+                (addr, format!("@ {:04X} ", addr))
+            }
+            AddressingMode::IZX => {
+                let ptr = zp.wrapping_add(x);
+                let addr = read_ptr(read, ptr);
+                (addr, format!("@ {:02X} = {:04X} ", ptr, addr))
+            }
+            AddressingMode::IZY => {
+                let base = read_ptr(read, zp);
+                let addr = base.wrapping_add(y as u16);
+                (addr, format!("= {:04X} @ {:04X} ", base, addr))
+            }
+            AddressingMode::IND => {
+                // Pointer doesn't cross the page, like on the CPU
+                let hi = (abs & 0xFF00) | (abs.wrapping_add(1) & 0x00FF);
+                let addr = to_u16(read(hi), read(abs));
+                self.effective_addr = Some(addr);
+                self.annotation = format!("= {:04X}", addr);
+                return;
+            }
+            _ => return,
+        };
+        self.effective_addr = Some(addr);
+        self.annotation = format!("{}= {:02X}", annotation, read(addr));
+    }
+}
+
+impl fmt::Display for DisasmInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.operand.is_empty() {
+            write!(f, "{}", self.mnemonic)
         } else {
-            write!(out, "   ").unwrap();
+            write!(f, "{} {}", self.mnemonic, self.operand)
+        }
+    }
+}
+
+/// Writes `bank` mapped at `base` as ca65 source which assembles back to the same bytes.
+///
+/// Targets where an instruction in the bank starts get labels, `L` and the address unless
+/// `labels` has a name for them. Other labeled addresses, including ones in the middle of
+/// an instruction, are defined as constants.
+pub fn dump_ca65(
+    out: &mut impl Write,
+    bank: &[u8],
+    base: u16,
+    labels: Option<&dyn Labels>,
+) -> io::Result<()> {
+    let end = base as usize + bank.len();
+    let mut read = |addr: u16| {
+        let offset = (addr as usize).wrapping_sub(base as usize);
+        bank.get(offset).copied().unwrap_or(0)
+    };
+    let in_bank = |addr: u16| (addr as usize) >= base as usize && (addr as usize) < end;
+
+    let mut instructions = vec![];
+    let mut addr = base as usize;
+    while addr < end {
+        let ins = decode(&mut read, addr as u16, None);
+        addr += ins.len as usize;
+        instructions.push(ins);
+    }
+
+    // Labels for code in the bank and constants for the rest
+    let starts: Vec<u16> = instructions.iter().map(|ins| ins.addr).collect();
+    let mut names: HashMap<u16, String> = HashMap::new();
+    let mut constants: Vec<(u16, String)> = vec![];
+    for ins in &instructions {
+        let target = match ins.target {
+            Some(target) if ins.mode != AddressingMode::IMM => target,
+            _ => continue,
+        };
+        if names.contains_key(&target) {
+            continue;
+        }
+        let symbol = labels.and_then(|labels| labels.label(target));
+        if in_bank(target) && starts.binary_search(&target).is_ok() {
+            let name = symbol.map_or(format!("L{:04X}", target), str::to_string);
+            names.insert(target, name);
+        } else if let Some(symbol) = symbol {
+            names.insert(target, symbol.to_string());
+            constants.push((target, symbol.to_string()));
+        }
+    }
+    constants.sort();
+
+    writeln!(out, "; PRG bank ${:04X}-${:04X}", base, end - 1)?;
+    writeln!(out, ".setcpu \"6502X\"")?;
+    writeln!(out)?;
+    for (addr, name) in &constants {
+        writeln!(out, "{} = ${:04X}", name, addr)?;
+    }
+    if !constants.is_empty() {
+        writeln!(out)?;
+    }
+    writeln!(out, ".org ${:04X}", base)?;
+
+    for ins in &instructions {
+        if let Some(name) = names.get(&ins.addr) {
+            if in_bank(ins.addr) {
+                writeln!(out, "{}:", name)?;
+            }
+        }
+
+        let bytes: Vec<String> = ins.bytes().iter().map(|b| format!("${:02X}", b)).collect();
+        let crosses_end = ins.addr as usize + ins.len as usize > end;
+        if crosses_end {
+            // Data at the end of the bank looking like instruction start
+            let count = end - ins.addr as usize;
+            writeln!(out, "    .byte {}", bytes[..count].join(", "))?;
+            continue;
+        }
+        if !ca65_supports(ins.bytes[0]) {
+            let text = format!(".byte {}", bytes.join(", "));
+            writeln!(out, "    {:<24} ; {}", text, ins)?;
+            continue;
         }
+
+        let label = ins
+            .target
+            .and_then(|target| names.get(&target).map(String::as_str));
+        let mut operand = format_operand(ins.mode, &ins.bytes, ins.target, label);
+        // ca65 would use zero page addressing for these
+        let abs_mode = matches!(
+            ins.mode,
+            AddressingMode::ABS | AddressingMode::ABX | AddressingMode::ABY
+        );
+        if abs_mode && ins.target.map_or(false, |target| target < 0x100) {
+            operand = format!("a:{}", operand);
+        }
+        let text = format!("{} {}", ins.mnemonic, operand);
+        writeln!(out, "    {:<24} ; ${:04X}", text.trim_end(), ins.addr)?;
     }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    write!(out, "{} ", ins.opcode).unwrap();
+    // Decodes instructions one after another starting at `range.start`.
+    fn decode_range(
+        read: &mut impl FnMut(u16) -> u8,
+        range: std::ops::Range<u16>,
+        labels: Option<&dyn Labels>,
+    ) -> Vec<DisasmInstruction> {
+        let mut instructions = vec![];
+        let mut addr = range.start;
+        while addr >= range.start && addr < range.end {
+            let ins = decode(read, addr, labels);
+            addr = addr.wrapping_add(ins.len);
+            instructions.push(ins);
+        }
+        instructions
+    }
+
+    #[test]
+    fn decode_instructions() {
+        // LDA $10,X; BNE -4; LAX ($20),Y; JMP ($02FF)
+        let code = [0xB5, 0x10, 0xD0, 0xFC, 0xB3, 0x20, 0x6C, 0xFF, 0x02];
+        let mut read = |addr: u16| code.get(addr as usize - 0x8000).copied().unwrap_or(0);
+        let mut labels = HashMap::new();
+        labels.insert(0x8000, "start".to_string());
+
+        let instructions = decode_range(&mut read, 0x8000..0x8009, Some(&labels));
+        let text: Vec<String> = instructions.iter().map(|ins| ins.to_string()).collect();
+        assert_eq!(
+            text,
+            ["LDA $10,X", "BNE start", "LAX ($20),Y", "JMP ($02FF)"]
+        );
+        assert_eq!(instructions[1].target, Some(0x8000));
+        assert!(!instructions[2].official);
+        assert_eq!(instructions[3].bytes(), &[0x6C, 0xFF, 0x02]);
+
+        let mut memory = [0u8; 0x400];
+        memory[0x15] = 0x42;
+        memory[0x20] = 0xF0;
+        memory[0x21] = 0x01;
+        memory[0x1F3] = 0x99;
+        memory[0x2FF] = 0x34;
+        memory[0x200] = 0x12;
+        let mut read_ram = |addr: u16| memory[addr as usize & 0x3FF];
+        let mut ins = instructions[0].clone();
+        ins.annotate(&mut read_ram, 5, 3);
+        assert_eq!(ins.annotation, "@ 15 = 42");
+        let mut ins = instructions[2].clone();
+        ins.annotate(&mut read_ram, 5, 3);
+        assert_eq!(ins.annotation, "= 01F0 @ 01F3 = 99");
+        let mut ins = instructions[3].clone();
+        ins.annotate(&mut read_ram, 5, 3);
+        assert_eq!(ins.effective_addr, Some(0x1234));
+    }
+
+    #[test]
+    fn ca65_dump() {
+        // LDA $0010 (absolute); BEQ to itself; SHY $1234,X; start of JSR cut by the bank end
+        let bank = [0xAD, 0x10, 0x00, 0xF0, 0xFE, 0x9C, 0x34, 0x12, 0x20, 0x00];
+        let mut labels = HashMap::new();
+        labels.insert(0x0010, "counter".to_string());
+
+        let mut out = Vec::new();
+        dump_ca65(&mut out, &bank, 0xC000, Some(&labels)).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+        assert_eq!(
+            lines,
+            [
+                "; PRG bank $C000-$C009",
+                ".setcpu \"6502X\"",
+                "",
+                "counter = $0010",
+                "",
+                ".org $C000",
+                "    LDA a:counter            ; $C000",
+                "LC003:",
+                "    BEQ LC003                ; $C003",
+                "    .byte $9C, $34, $12      ; SHY $1234,X",
+                "    .byte $20, $00",
+            ]
+        );
+    }
+
+    #[test]
+    fn ca65_dump_label_inside_instruction() {
+        // LDA $C001, which reads the operand of the LDA itself; RTS
+        let bank = [0xAD, 0x01, 0xC0, 0x60];
+        let mut labels = HashMap::new();
+        labels.insert(0xC001, "table".to_string());
+
+        let mut out = Vec::new();
+        dump_ca65(&mut out, &bank, 0xC000, Some(&labels)).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+        assert_eq!(
+            lines,
+            [
+                "; PRG bank $C000-$C003",
+                ".setcpu \"6502X\"",
+                "",
+                "table = $C001",
+                "",
+                ".org $C000",
+                "    LDA table                ; $C000",
+                "    RTS                      ; $C003",
+            ]
+        );
+    }
 }
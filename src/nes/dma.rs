@@ -1,14 +1,41 @@
 use super::bus::CpuBusDevice;
-use super::cpu::Cpu;
-use super::ppu::Ppu;
 use std::ops::Range;
 
+/// Bus access performed by the DMA unit on a halted CPU cycle.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DmaAccess {
+    /// Cycle with no bus activity.
+    None,
+    /// Halt/dummy/alignment cycle which repeats the read the CPU was about to make.
+    DummyRead(u16),
+    /// Sprite DMA read cycle, the result goes into `DmaDevice::data`.
+    OamRead(u16),
+    /// Sprite DMA write cycle to OAMDATA.
+    OamWrite(u8),
+    /// DMC sample fetch, the result goes into the DMC sample buffer.
+    DmcRead(u16),
+}
+
+/// OAM and DMC DMA unit.
+///
+/// CPU cycles alternate between get (read) and put (write) cycles. Once halted the
+/// unit reads only on get cycles and writes only on put cycles, so a sprite DMA takes
+/// 513 or 514 cycles and a lone DMC fetch 3 or 4. Sprite DMA cycles double as the
+/// halt/dummy cycles of a DMC fetch, which then usually steals 2 cycles.
 pub struct DmaDevice {
     pub page: u8,
     pub addr: u8,
     pub data: u8,
-    pub flag: bool,
     pub transfer: bool,
+    count: u16,
+
+    pub dmc_transfer: bool,
+    pub dmc_addr: u16,
+
+    halted: bool,
+    need_halt: bool,
+    need_dummy: bool,
+    read_addr: u16,
 }
 
 impl DmaDevice {
@@ -17,8 +44,14 @@ impl DmaDevice {
             page: 0,
             addr: 0,
             data: 0,
-            flag: true,
             transfer: false,
+            count: 0,
+            dmc_transfer: false,
+            dmc_addr: 0,
+            halted: false,
+            need_halt: false,
+            need_dummy: false,
+            read_addr: 0,
         }
     }
 
@@ -26,22 +59,76 @@ impl DmaDevice {
         *self = Self::new();
     }
 
-    pub fn clock(&mut self, clock: i32, cpu: &mut Cpu, ppu: &mut Ppu) {
-        if self.flag {
-            if clock % 2 == 1 {
-                self.flag = false;
-            }
-        } else {
-            if clock % 2 == 0 {
-                self.data = cpu.bus.cpu_read((self.page as u16) << 8 | (self.addr as u16));
+    /// Requests a DMC sample fetch from `addr`.
+    pub fn request_dmc(&mut self, addr: u16) {
+        self.dmc_addr = addr;
+        self.dmc_transfer = true;
+        self.need_halt = true;
+        self.need_dummy = true;
+    }
+
+    /// Whether the DMA unit owns the CPU this cycle. Sprite DMA waits for the next
+    /// opcode fetch (a read cycle) to halt the CPU, DMC fetches halt it right away.
+    pub fn is_active(&self, instruction_boundary: bool) -> bool {
+        self.halted || self.dmc_transfer || (self.transfer && instruction_boundary)
+    }
+
+    /// Runs one halted CPU cycle and returns the bus access to perform.
+    /// `read_addr` is the address of the last CPU read, repeated by the halt cycle.
+    pub fn clock(&mut self, get_cycle: bool, read_addr: u16) -> DmaAccess {
+        if !self.halted {
+            // Halt cycle: hijacks the CPU read, the value is discarded
+            self.halted = true;
+            self.need_halt = false;
+            self.read_addr = read_addr;
+            return DmaAccess::DummyRead(read_addr);
+        }
+
+        let dmc_ready = self.dmc_transfer && !self.need_halt && !self.need_dummy;
+
+        // Any cycle counts as a halt/dummy cycle for a pending DMC fetch
+        if self.need_halt {
+            self.need_halt = false;
+        } else if self.need_dummy {
+            self.need_dummy = false;
+        }
+
+        let access = if get_cycle {
+            if dmc_ready {
+                self.dmc_transfer = false;
+                DmaAccess::DmcRead(self.dmc_addr)
+            } else if self.transfer && self.count & 1 == 0 {
+                self.count += 1;
+                DmaAccess::OamRead((self.page as u16) << 8 | (self.addr as u16))
             } else {
-                ppu.write_oam(self.addr, self.data);
-                self.addr = self.addr.wrapping_add(1);
-                if self.addr == 0x00 {
-                    self.transfer = false;
-                    self.flag = true;
-                }
+                self.dummy_read()
+            }
+        } else if self.transfer && self.count & 1 == 1 {
+            let access = DmaAccess::OamWrite(self.data);
+            self.addr = self.addr.wrapping_add(1);
+            self.count += 1;
+            if self.count == 0x200 {
+                self.transfer = false;
             }
+            access
+        } else {
+            // Alignment cycle
+            self.dummy_read()
+        };
+
+        if !self.transfer && !self.dmc_transfer {
+            self.halted = false;
+        }
+
+        access
+    }
+
+    fn dummy_read(&self) -> DmaAccess {
+        // Controller ports only see the first of back-to-back reads
+        if self.read_addr == 0x4016 || self.read_addr == 0x4017 {
+            DmaAccess::None
+        } else {
+            DmaAccess::DummyRead(self.read_addr)
         }
     }
 }
@@ -54,10 +141,81 @@ impl CpuBusDevice for DmaDevice {
     fn cpu_write(&mut self, _: u16, data: u8) {
         self.page = data;
         self.addr = 0x00;
+        self.count = 0;
         self.transfer = true;
+        self.need_halt = true;
     }
 
     fn cpu_read(&mut self, _: u16) -> u8 {
         0
     }
+
+    fn peek(&self, _: u16) -> u8 {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(dma: &mut DmaDevice, start_cycle: usize) -> (usize, Vec<DmaAccess>) {
+        let mut cycle = start_cycle;
+        let mut accesses = vec![];
+        while dma.is_active(true) {
+            accesses.push(dma.clock(cycle % 2 == 0, 0x8000));
+            cycle += 1;
+        }
+        (cycle - start_cycle, accesses)
+    }
+
+    #[test]
+    fn oam_dma_alignment() {
+        let mut dma = DmaDevice::new();
+        dma.cpu_write(0x4014, 0x02);
+        let (cycles, accesses) = run(&mut dma, 0);
+        assert_eq!(cycles, 514);
+        assert_eq!(accesses[2], DmaAccess::OamRead(0x0200));
+
+        dma.cpu_write(0x4014, 0x02);
+        let (cycles, accesses) = run(&mut dma, 1);
+        assert_eq!(cycles, 513);
+        assert_eq!(accesses[1], DmaAccess::OamRead(0x0200));
+        assert_eq!(accesses[512], DmaAccess::OamWrite(0));
+    }
+
+    #[test]
+    fn dmc_dma() {
+        let mut dma = DmaDevice::new();
+        dma.request_dmc(0xC000);
+        assert_eq!(run(&mut dma, 0).0, 3);
+
+        dma.request_dmc(0xC000);
+        let (cycles, accesses) = run(&mut dma, 1);
+        assert_eq!(cycles, 4);
+        assert_eq!(accesses[3], DmaAccess::DmcRead(0xC000));
+    }
+
+    #[test]
+    fn dmc_during_oam_dma() {
+        let mut dma = DmaDevice::new();
+        dma.cpu_write(0x4014, 0x02);
+        for cycle in 1..101 {
+            dma.clock(cycle % 2 == 0, 0x8000);
+        }
+        dma.request_dmc(0xC000);
+        let (cycles, accesses) = run(&mut dma, 101);
+        assert_eq!(100 + cycles, 513 + 2);
+        assert!(accesses.contains(&DmaAccess::DmcRead(0xC000)));
+    }
+
+    #[test]
+    fn controller_dummy_reads() {
+        let mut dma = DmaDevice::new();
+        dma.request_dmc(0xC000);
+        let accesses: Vec<DmaAccess> = (0..3).map(|c| dma.clock(c % 2 == 0, 0x4016)).collect();
+        assert_eq!(accesses[0], DmaAccess::DummyRead(0x4016));
+        assert_eq!(accesses[1], DmaAccess::None);
+        assert_eq!(accesses[2], DmaAccess::DmcRead(0xC000));
+    }
 }
@@ -19,6 +19,10 @@ impl CpuBusDevice for Logger {
     fn cpu_read(&mut self, _: u16) -> u8 {
         0
     }
+
+    fn peek(&self, _: u16) -> u8 {
+        0
+    }
 }
 
 impl Logger {
@@ -3,6 +3,22 @@ pub trait Mapper {
     fn map_read(&mut self, addr: u16) -> u16;
     fn map_ppu_write(&mut self, addr: u16) -> u16;
     fn map_ppu_read(&mut self, addr: u16) -> u16;
+    /// Mappings of `map_read` and `map_ppu_read` without changing mapper state.
+    fn map_peek(&self, addr: u16) -> u16;
+    fn map_ppu_peek(&self, addr: u16) -> u16;
+}
+
+/// Creates mapper by its iNES number, `None` when it's not supported.
+pub fn create(mapper_number: u8, rom_pages: u8) -> Option<Box<dyn Mapper>> {
+    match mapper_number {
+        0 => Some(Box::new(Mapper0::new(rom_pages))),
+        3 => Some(Box::new(Mapper3::new(rom_pages))),
+        _ => None,
+    }
+}
+
+pub fn is_supported(mapper_number: u8) -> bool {
+    create(mapper_number, 1).is_some()
 }
 
 ///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
@@ -17,11 +33,7 @@ impl Mapper for Mapper0 {
     }
 
     fn map_read(&mut self, addr: u16) -> u16 {
-        let mut mapped_addr = addr - 0x8000;
-        if self.one_bank {
-            mapped_addr &= 0x3fff;
-        }
-        mapped_addr
+        self.map_peek(addr)
     }
 
     fn map_ppu_write(&mut self, addr: u16) -> u16 {
@@ -29,6 +41,18 @@ impl Mapper for Mapper0 {
     }
 
     fn map_ppu_read(&mut self, addr: u16) -> u16 {
+        self.map_ppu_peek(addr)
+    }
+
+    fn map_peek(&self, addr: u16) -> u16 {
+        let mut mapped_addr = addr - 0x8000;
+        if self.one_bank {
+            mapped_addr &= 0x3fff;
+        }
+        mapped_addr
+    }
+
+    fn map_ppu_peek(&self, addr: u16) -> u16 {
         addr
     }
 }
@@ -55,11 +79,7 @@ impl Mapper for Mapper3 {
     }
 
     fn map_read(&mut self, addr: u16) -> u16 {
-        let mut mapped_addr = addr - 0x8000;
-        if self.one_bank {
-            mapped_addr &= 0x3fff;
-        }
-        mapped_addr
+        self.map_peek(addr)
     }
 
     fn map_ppu_write(&mut self, addr: u16) -> u16 {
@@ -67,6 +87,18 @@ impl Mapper for Mapper3 {
     }
 
     fn map_ppu_read(&mut self, addr: u16) -> u16 {
+        self.map_ppu_peek(addr)
+    }
+
+    fn map_peek(&self, addr: u16) -> u16 {
+        let mut mapped_addr = addr - 0x8000;
+        if self.one_bank {
+            mapped_addr &= 0x3fff;
+        }
+        mapped_addr
+    }
+
+    fn map_ppu_peek(&self, addr: u16) -> u16 {
         addr | (self.bank_select << 13)
     }
 }
@@ -0,0 +1,46 @@
+/// Memory the memory viewer can show and edit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemorySpace {
+    /// CPU address space, as the CPU sees it.
+    Cpu,
+    /// Internal 2K RAM.
+    Ram,
+    /// PPU address space: pattern tables, name tables and palettes.
+    Ppu,
+    Oam,
+    PrgRom,
+    ChrRom,
+    PrgRam,
+}
+
+impl MemorySpace {
+    pub const ALL: [MemorySpace; 7] = [
+        MemorySpace::Cpu,
+        MemorySpace::Ram,
+        MemorySpace::Ppu,
+        MemorySpace::Oam,
+        MemorySpace::PrgRom,
+        MemorySpace::ChrRom,
+        MemorySpace::PrgRam,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MemorySpace::Cpu => "CPU",
+            MemorySpace::Ram => "RAM",
+            MemorySpace::Ppu => "PPU",
+            MemorySpace::Oam => "OAM",
+            MemorySpace::PrgRom => "PRG-ROM",
+            MemorySpace::ChrRom => "CHR",
+            MemorySpace::PrgRam => "PRG-RAM",
+        }
+    }
+}
+
+/// Value written back to memory every frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Freeze {
+    pub space: MemorySpace,
+    pub addr: usize,
+    pub value: u8,
+}
@@ -3,35 +3,61 @@
 use std::path::PathBuf;
 
 use std::cell::RefCell;
-// use std::fs::File;
-use std::io::Write;
+use std::io::{self, Write};
 use std::rc::Rc;
 
+pub mod apu;
 pub mod bus;
 pub mod cartridge;
 pub mod controller;
 pub mod cpu;
+pub mod debugger;
 pub mod disasm;
 pub mod dma;
 pub mod logger;
 pub mod mappers;
+pub mod memory;
+pub mod movie;
+pub mod multitap;
+pub mod ntsc;
+pub mod palette;
 pub mod ppu;
+pub mod ppu_view;
 pub mod ram;
-
+pub mod region;
+pub mod source_map;
+pub mod symbols;
+pub mod trace;
+pub mod zapper;
+
+use apu::Apu;
+use bus::CpuBusDevice;
 use cartridge::Cartridge;
-use controller::Controller;
+use controller::{Controller, ControllerPort, ControllerPorts, InputSetup, PortDevice};
 use cpu::Cpu;
-use cpu::{to_u16, AddressingMode, INSTRUCTION_LOOKUP};
-use dma::DmaDevice;
+use cpu::{Opcode, INSTRUCTION_LOOKUP};
+use debugger::{Context, Debugger, Step};
+use disasm::DisasmInstruction;
+use dma::{DmaAccess, DmaDevice};
 use logger::Logger;
+use memory::{Freeze, MemorySpace};
+use movie::Movie;
+use multitap::{FamicomPads, FourScore, Multitap};
 use ppu::Ppu;
+use ppu_view::NametableView;
 use ram::Ram;
+use region::Region;
+use source_map::SourceLine;
+use symbols::{SymbolLookup, Symbols};
+use trace::{TraceFormat, TraceLine, TraceLogger, TraceOptions};
+use zapper::Zapper;
 
 #[derive(Default)]
 pub struct FrameTime {
     pub dt: f32,
     pub dt_accum: f32,
     pub fps: f32,
+    pub frame_rate: f32,
 }
 
 /// NES main emulator
@@ -42,10 +68,33 @@ pub struct Emulator {
     pub cartridge: Rc<RefCell<Cartridge>>,
     pub logger: Rc<RefCell<Logger>>,
     pub clock: i32,
+    // PPU dots accumulated towards the next CPU cycle, in region ratio denominator units
+    cpu_clock_phase: u32,
+    /// Region forced by user, `None` to detect it from the ROM.
+    pub region_override: Option<Region>,
+    pub region: Region,
     pub dma: Rc<RefCell<DmaDevice>>,
-    pub controllers: [Rc<RefCell<Controller>>; 2],
+    pub controllers: [Rc<RefCell<Controller>>; 4],
+    pub ports: Rc<RefCell<ControllerPorts>>,
+    pub input_setup: InputSetup,
+    pub zapper: Rc<RefCell<Zapper>>,
+    pub apu: Rc<RefCell<Apu>>,
     pub rom_loaded: bool,
     pub frame_time: FrameTime,
+    /// Movie providing pad input instead of the player.
+    pub movie: Option<Movie>,
+    /// Log of executed instructions, see `start_trace`.
+    pub trace: Option<TraceLogger>,
+    pub debugger: Debugger,
+    /// Values kept by the memory viewer, written every frame.
+    pub freezes: Vec<Freeze>,
+    /// Scanline to capture `nametable_view` at in every frame.
+    pub nametable_scanline: Option<i16>,
+    pub nametable_view: Option<NametableView>,
+    /// Labels for the debugger and CPU log, loaded with the ROM.
+    pub symbols: Symbols,
+    // Frame was started by `run_frame` and stopped by the debugger before completion
+    frame_started: bool,
 }
 
 impl Emulator {
@@ -57,16 +106,27 @@ impl Emulator {
         let logger = Rc::new(RefCell::new(Logger::new()));
         let ppu = Rc::new(RefCell::new(Ppu::new(cartridge.clone())));
         let dma = Rc::new(RefCell::new(DmaDevice::new()));
-        let controller0 = Rc::new(RefCell::new(Controller::new(0)));
-        let controller1 = Rc::new(RefCell::new(Controller::new(1)));
+        let apu = Rc::new(RefCell::new(Apu::new()));
+        let controllers = [
+            Rc::new(RefCell::new(Controller::new())),
+            Rc::new(RefCell::new(Controller::new())),
+            Rc::new(RefCell::new(Controller::new())),
+            Rc::new(RefCell::new(Controller::new())),
+        ];
+        let ports = Rc::new(RefCell::new(ControllerPorts::new(
+            controllers[0].clone(),
+            controllers[1].clone(),
+            apu.clone(),
+        )));
+        let zapper = Rc::new(RefCell::new(Zapper::new(ppu.clone())));
 
         cpu.bus.connect(ram.clone());
         cpu.bus.connect(cartridge.clone());
         cpu.bus.connect(logger.clone());
         cpu.bus.connect(ppu.clone());
         cpu.bus.connect(dma.clone());
-        cpu.bus.connect(controller0.clone());
-        cpu.bus.connect(controller1.clone());
+        cpu.bus.connect(apu.clone());
+        cpu.bus.connect(ports.clone());
 
         Emulator {
             cpu,
@@ -75,119 +135,303 @@ impl Emulator {
             cartridge,
             logger,
             clock: 0,
+            cpu_clock_phase: 0,
+            region_override: None,
+            region: Region::Ntsc,
             dma,
-            controllers: [controller0, controller1],
+            controllers,
+            ports,
+            input_setup: InputSetup::default(),
+            zapper,
+            apu,
             rom_loaded: false,
-            frame_time: FrameTime::default(),
+            frame_time: FrameTime {
+                frame_rate: Region::Ntsc.frame_rate(),
+                ..FrameTime::default()
+            },
+            movie: None,
+            trace: None,
+            debugger: Debugger::new(),
+            freezes: vec![],
+            nametable_scanline: None,
+            nametable_view: None,
+            symbols: Symbols::new(),
+            frame_started: false,
         }
     }
 
-    pub fn load_rom(&mut self, romfile: &PathBuf) {
-        self.cartridge.borrow_mut().load_from_file(romfile);
+    /// Loads and starts `romfile`, the current ROM keeps running when that fails.
+    pub fn load_rom(&mut self, romfile: &PathBuf) -> Result<(), String> {
+        self.cartridge.borrow_mut().load_from_file(romfile)?;
+        self.symbols = Symbols::new();
+        self.symbols.load_for_rom(romfile);
+        // Devices of the previous ROM are unplugged unless this one asks for them too
+        let expansion_device = self.cartridge.borrow().expansion_device;
+        let setup = InputSetup::from_expansion_device(expansion_device).unwrap_or_default();
+        self.set_input_setup(setup);
+        self.reset();
+        self.apply_region();
+        self.rom_loaded = true;
+        Ok(())
+    }
+
+    pub fn reset(&mut self) {
         self.cpu.reset();
         self.ppu.borrow_mut().reset();
         self.dma.borrow_mut().reset();
+        self.apu.borrow_mut().reset();
         self.clock = 1;
-        self.rom_loaded = true;
+        self.cpu_clock_phase = 0;
+        self.frame_started = false;
     }
 
-    #[allow(dead_code)]
-    pub fn write_state(&mut self, f: &mut impl Write) {
-        write!(f, "{:04X} ", self.cpu.PC).unwrap();
-        disasm::disasm(f, &mut self.cpu.bus, self.cpu.PC);
-
-        let ins_code = self.cpu.bus.cpu_read(self.cpu.PC);
-        let ins = &INSTRUCTION_LOOKUP[ins_code as usize];
-        let mut addr_str = String::new();
-        match ins.mode {
-            // JMP ABS
-            AddressingMode::ABS => {
-                addr_str = format!(
-                    "${:04X} ",
-                    to_u16(
-                        self.cpu.bus.cpu_read(self.cpu.PC + 2),
-                        self.cpu.bus.cpu_read(self.cpu.PC + 1)
-                    )
-                );
-            }
-            AddressingMode::ABX => {
-                addr_str = format!(
-                    "${:04X},X ",
-                    to_u16(
-                        self.cpu.bus.cpu_read(self.cpu.PC + 2),
-                        self.cpu.bus.cpu_read(self.cpu.PC + 1)
-                    )
-                );
-            }
-            AddressingMode::ABY => {
-                addr_str = format!(
-                    "${:04X},Y ",
-                    to_u16(
-                        self.cpu.bus.cpu_read(self.cpu.PC + 2),
-                        self.cpu.bus.cpu_read(self.cpu.PC + 1)
-                    )
-                );
-            }
-            AddressingMode::REL => {
-                addr_str = format!(
-                    "${:04X} ",
-                    self.cpu.PC + self.cpu.bus.cpu_read(self.cpu.PC + 1) as u16 + 2
-                );
-            }
-            AddressingMode::IND => {
-                addr_str = format!(
-                    "(${:04X}) ",
-                    to_u16(
-                        self.cpu.bus.cpu_read(self.cpu.PC + 2),
-                        self.cpu.bus.cpu_read(self.cpu.PC + 1)
-                    )
-                );
-            }
-            AddressingMode::IMP => {}
-            AddressingMode::ACC => {
-                addr_str = format!("A ");
-            }
-            AddressingMode::IMM => {
-                addr_str = format!("#${:02X} ", self.cpu.bus.cpu_read(self.cpu.PC + 1));
-            }
-            AddressingMode::ZP0 => {
-                addr_str = format!("${:02X} ", self.cpu.bus.cpu_read(self.cpu.PC + 1));
-            }
-            AddressingMode::IZX => {
-                addr_str = format!("(${:02X},X) ", self.cpu.bus.cpu_read(self.cpu.PC + 1));
-            }
-            AddressingMode::IZY => {
-                addr_str = format!("(${:02X}),Y ", self.cpu.bus.cpu_read(self.cpu.PC + 1));
+    /// Plays `movie` from the next frame, it should start right after the ROM is loaded.
+    pub fn play_movie(&mut self, movie: Movie) {
+        self.set_region_override(Some(movie.region));
+        if movie.four_score {
+            let mut setup = self.input_setup;
+            setup.multitap = Multitap::FourScore;
+            self.set_input_setup(setup);
+        }
+        self.movie = Some(movie);
+    }
+
+    /// Starts logging executed instructions passing `options` filters to `path`.
+    pub fn start_trace(&mut self, path: &PathBuf, options: TraceOptions) {
+        self.trace = TraceLogger::create(path, options);
+    }
+
+    /// Stops the trace, flushing it to the file.
+    pub fn stop_trace(&mut self) {
+        self.trace = None;
+    }
+
+    /// Forces `region`, or detects it from the loaded ROM when `None`.
+    pub fn set_region_override(&mut self, region: Option<Region>) {
+        self.region_override = region;
+        self.apply_region();
+    }
+
+    fn apply_region(&mut self) {
+        let region = self
+            .region_override
+            .or(self.cartridge.borrow().region)
+            .unwrap_or_default();
+        self.region = region;
+        self.ppu.borrow_mut().region = region;
+        self.apu.borrow_mut().region = region;
+        self.frame_time.frame_rate = region.frame_rate();
+    }
+
+    /// Reconnects the controller ports according to `setup`.
+    pub fn set_input_setup(&mut self, setup: InputSetup) {
+        for num in 0..2 {
+            let port: Rc<RefCell<dyn ControllerPort>> =
+                match (setup.port_devices[num], setup.multitap) {
+                    (PortDevice::Zapper, _) => self.zapper.clone(),
+                    (PortDevice::Controller, Multitap::None) => self.controllers[num].clone(),
+                    (PortDevice::Controller, Multitap::FourScore) => {
+                        Rc::new(RefCell::new(FourScore::new(
+                            num,
+                            self.controllers[num].clone(),
+                            self.controllers[num + 2].clone(),
+                        )))
+                    }
+                    (PortDevice::Controller, Multitap::FamicomExpansion) => {
+                        Rc::new(RefCell::new(FamicomPads::new(
+                            self.controllers[num].clone(),
+                            self.controllers[num + 2].clone(),
+                        )))
+                    }
+                };
+            self.ports.borrow_mut().connect(num, port);
+        }
+        self.input_setup = setup;
+    }
+
+    pub fn memory_size(&self, space: MemorySpace) -> usize {
+        match space {
+            MemorySpace::Cpu => 0x10000,
+            MemorySpace::Ram => self.ram.borrow().bytes.len(),
+            MemorySpace::Ppu => 0x4000,
+            MemorySpace::Oam => 0x100,
+            MemorySpace::PrgRom => self.cartridge.borrow().prg_rom().len(),
+            MemorySpace::ChrRom => self.cartridge.borrow().chr_rom().len(),
+            MemorySpace::PrgRam => self.cartridge.borrow().prg_ram().len(),
+        }
+    }
+
+    /// Reads memory for the viewer without side effects, `None` past the end.
+    pub fn peek_memory(&self, space: MemorySpace, addr: usize) -> Option<u8> {
+        match space {
+            MemorySpace::Cpu if addr < 0x10000 => Some(self.cpu.bus.peek(addr as u16)),
+            MemorySpace::Ram => self.ram.borrow().bytes.get(addr).copied(),
+            MemorySpace::Ppu if addr < 0x4000 => Some(self.ppu.borrow().ppu_peek(addr as u16)),
+            MemorySpace::Oam if addr < 0x100 => Some(self.ppu.borrow().read_oam(addr as u8)),
+            MemorySpace::PrgRom => self.cartridge.borrow().prg_rom().get(addr).copied(),
+            MemorySpace::ChrRom => self.cartridge.borrow().chr_rom().get(addr).copied(),
+            MemorySpace::PrgRam => self.cartridge.borrow().prg_ram().get(addr).copied(),
+            _ => None,
+        }
+    }
+
+    /// Changes memory from the viewer. CPU writes to ROM change the mapped PRG-ROM
+    /// byte, registers are not written.
+    pub fn poke_memory(&mut self, space: MemorySpace, addr: usize, data: u8) {
+        if addr >= self.memory_size(space) {
+            return;
+        }
+        match space {
+            MemorySpace::Cpu => match addr {
+                0x0000..=0x1FFF => self.ram.borrow_mut().cpu_write(addr as u16, data),
+                0x8000..=0xFFFF => {
+                    let mut cartridge = self.cartridge.borrow_mut();
+                    if let Some(offset) = cartridge.prg_mapping().offset(addr as u16) {
+                        cartridge.prg_rom_mut()[offset] = data;
+                    }
+                }
+                _ => {}
+            },
+            MemorySpace::Ram => self.ram.borrow_mut().bytes[addr] = data,
+            MemorySpace::Ppu => self.ppu.borrow_mut().ppu_write(addr as u16, data),
+            MemorySpace::Oam => self.ppu.borrow_mut().write_oam(addr as u8, data),
+            MemorySpace::PrgRom => self.cartridge.borrow_mut().prg_rom_mut()[addr] = data,
+            MemorySpace::ChrRom => self.cartridge.borrow_mut().chr_rom_mut()[addr] = data,
+            MemorySpace::PrgRam => self.cartridge.borrow_mut().prg_ram_mut()[addr] = data,
+        }
+    }
+
+    /// Freezes `addr` at its current value, or unfreezes it.
+    pub fn toggle_freeze(&mut self, space: MemorySpace, addr: usize) {
+        match self
+            .freezes
+            .iter()
+            .position(|freeze| freeze.space == space && freeze.addr == addr)
+        {
+            Some(i) => {
+                self.freezes.remove(i);
             }
-            AddressingMode::ZPX => {
-                addr_str = format!("${:02X},X ", self.cpu.bus.cpu_read(self.cpu.PC + 1));
+            None => {
+                if let Some(value) = self.peek_memory(space, addr) {
+                    self.freezes.push(Freeze { space, addr, value });
+                }
             }
-            AddressingMode::ZPY => {
-                addr_str = format!("${:02X},Y ", self.cpu.bus.cpu_read(self.cpu.PC + 1));
+        }
+    }
+
+    /// Symbols as seen by CPU with the current PRG banks.
+    pub fn labels(&self) -> SymbolLookup<'_> {
+        let mapping = self.cartridge.borrow().prg_mapping();
+        self.symbols.lookup(mapping)
+    }
+
+    /// Writes nestest style line of the next instruction.
+    pub fn write_state(&mut self, f: &mut impl Write) {
+        let context = self.debug_context();
+        let ins = self.decode_current(&context);
+        let line = TraceLine {
+            ins: &ins,
+            context: &context,
+            cycles: self.cpu.total_cycles,
+            frame: self.ppu.borrow().frame,
+            bank: None,
+        };
+        line.write(f, TraceFormat::Nestest).unwrap();
+    }
+
+    fn decode(&self, addr: u16) -> DisasmInstruction {
+        let labels = self.labels();
+        disasm::decode(&mut |addr| self.cpu.bus.peek(addr), addr, Some(&labels))
+    }
+
+    // Instruction at PC with the address it accesses with the current registers
+    fn decode_current(&self, context: &Context) -> DisasmInstruction {
+        let mut ins = self.decode(context.pc);
+        ins.annotate(&mut |addr| self.cpu.bus.peek(addr), context.x, context.y);
+        ins
+    }
+
+    /// Writes PRG-ROM as ca65 source, 16K banks mapped now keep their address and labels.
+    pub fn dump_prg(&self, out: &mut impl Write) -> io::Result<()> {
+        let cartridge = self.cartridge.borrow();
+        let mapping = cartridge.prg_mapping();
+        let labels = self.symbols.lookup(cartridge.prg_mapping());
+        for (i, bank) in cartridge.prg_rom().chunks(0x4000).enumerate() {
+            let offset = i * 0x4000;
+            let last = bank.len() - 1;
+            let base = mapping.addr(offset, None).filter(|&base| {
+                let end = base.checked_add(last as u16);
+                end.and_then(|end| mapping.offset(end)) == Some(offset + last)
+            });
+            match base {
+                Some(base) => disasm::dump_ca65(out, bank, base, Some(&labels))?,
+                None => disasm::dump_ca65(out, bank, 0x8000, None)?,
             }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    // Logs the next instruction if it passes the trace filters, false on write error
+    fn trace_instruction(&mut self, trace: &mut TraceLogger) -> bool {
+        let context = self.debug_context();
+        let frame = self.ppu.borrow().frame;
+        if !trace.options.accepts(&context, frame) {
+            return true;
         }
-        write!(f, "{:<20}", addr_str).unwrap();
+        let ins = self.decode_current(&context);
+        let bank = if trace.options.show_bank {
+            let mapping = self.cartridge.borrow().prg_mapping();
+            mapping.offset(context.pc).map(|offset| offset / 0x4000)
+        } else {
+            None
+        };
+        trace.log(&TraceLine {
+            ins: &ins,
+            context: &context,
+            cycles: self.cpu.total_cycles,
+            frame,
+            bank,
+        })
+    }
 
-        write!(f, "A:{:02X} ", self.cpu.A).unwrap();
-        write!(f, "X:{:02X} ", self.cpu.X).unwrap();
-        write!(f, "Y:{:02X} ", self.cpu.Y).unwrap();
+    /// Registers and PPU position for breakpoint conditions.
+    pub fn debug_context(&self) -> Context {
+        let ppu = self.ppu.borrow();
+        Context {
+            pc: self.cpu.PC,
+            a: self.cpu.A,
+            x: self.cpu.X,
+            y: self.cpu.Y,
+            sp: self.cpu.SP,
+            p: self.cpu.flags.to_byte(),
+            scanline: ppu.scanline,
+            cycle: ppu.cycle,
+            ..Context::default()
+        }
+    }
 
-        write!(f, "P:{:02X} ", self.cpu.flags.to_byte()).unwrap();
-        write!(f, "SP:{:02X} ", self.cpu.SP).unwrap();
-        write!(
-            f,
-            "PPU:{:>3},{:>3} ",
-            self.ppu.borrow().scanline,
-            self.ppu.borrow().cycle
-        )
-        .unwrap();
-        write!(f, "CYC:{} ", self.cpu.total_cycles).unwrap();
+    /// Source line of the next instruction, from ca65 debug info.
+    pub fn source_line(&self) -> Option<SourceLine> {
+        let source_map = &self.symbols.source_map;
+        if source_map.is_empty() {
+            return None;
+        }
+        let mapping = self.cartridge.borrow().prg_mapping();
+        source_map.line_at(self.cpu.PC, &mapping)
+    }
 
-        f.write_all(b"\n").unwrap();
+    /// Resumes execution until `step` is done, `update` keeps running it.
+    pub fn step(&mut self, step: Step) {
+        let ins_code = self.cpu.bus.peek(self.cpu.PC);
+        let jsr_at_pc = INSTRUCTION_LOOKUP[ins_code as usize].opcode == Opcode::JSR;
+        let mut context = self.debug_context();
+        context.source_line = self.source_line();
+        self.debugger.step(step, &context, jsr_at_pc);
     }
 
     pub fn update(&mut self, dt: f32) {
-        if !self.rom_loaded {
+        if !self.rom_loaded || self.debugger.paused {
             return;
         }
 
@@ -196,21 +440,88 @@ impl Emulator {
             return;
         }
 
+        self.run_frame();
+    }
+
+    /// Runs until the PPU completes a frame or the debugger stops.
+    pub fn run_frame(&mut self) {
+        if !self.frame_started {
+            self.frame_started = true;
+            self.start_frame();
+        }
+
         while !self.ppu.borrow().screen.complete {
             self.clock();
+            if self.debugger.paused {
+                return;
+            }
+        }
+        self.frame_started = false;
+        self.debugger.on_frame();
+    }
+
+    fn start_frame(&mut self) {
+        for i in 0..self.freezes.len() {
+            let freeze = self.freezes[i];
+            self.poke_memory(freeze.space, freeze.addr, freeze.value);
+        }
+        if let Some(movie) = &mut self.movie {
+            match movie.next_frame() {
+                Some(frame) => {
+                    for (controller, input) in self.controllers.iter().zip(frame.pads.iter()) {
+                        controller.borrow_mut().input = *input;
+                    }
+                    if frame.reset {
+                        self.reset();
+                    }
+                }
+                None => {
+                    println!("Movie finished.");
+                    self.movie = None;
+                }
+            }
         }
     }
 
     pub fn clock(&mut self) {
         self.ppu.borrow_mut().clock();
+        if let Some(scanline) = self.nametable_scanline {
+            let ppu = self.ppu.borrow();
+            if ppu.scanline == scanline && ppu.cycle == 1 {
+                self.nametable_view = Some(NametableView::capture(&ppu));
+            }
+        }
 
-        if self.clock % 3 == 0 {
-            if self.dma.borrow_mut().transfer {
-                self.dma
-                    .borrow_mut()
-                    .clock(self.clock, &mut self.cpu, &mut self.ppu.borrow_mut());
+        // 3 dots per CPU cycle on NTSC and Dendy, 3.2 on PAL
+        let (dots, cycles) = self.region.ppu_dots_per_cpu_cycle();
+        self.cpu_clock_phase += cycles;
+        let mut cpu_clocked = false;
+        if self.cpu_clock_phase >= dots {
+            self.cpu_clock_phase -= dots;
+            let instruction_boundary = self.cpu.cycles == 0;
+            if self.dma.borrow().is_active(instruction_boundary) {
+                self.dma_clock();
             } else {
-                self.cpu.clock();
+                if instruction_boundary {
+                    if let Some(mut trace) = self.trace.take() {
+                        if self.trace_instruction(&mut trace) {
+                            self.trace = Some(trace);
+                        }
+                    }
+                    self.cpu.bus.record_accesses = self.debugger.watches_memory();
+                    let context = self.debug_context();
+                    self.cpu.clock();
+                    self.check_accesses(&context);
+                } else {
+                    self.cpu.clock();
+                }
+                cpu_clocked = true;
+            }
+
+            // DMC timer runs on every CPU cycle, halted or not
+            let dmc_fetch = self.apu.borrow_mut().clock();
+            if let Some(addr) = dmc_fetch {
+                self.dma.borrow_mut().request_dmc(addr);
             }
         }
 
@@ -219,21 +530,70 @@ impl Emulator {
             self.cpu.nmi();
         }
 
+        // Next CPU cycle starts an instruction
+        if cpu_clocked && self.cpu.cycles == 0 {
+            let mut context = self.debug_context();
+            if self.debugger.needs_source_line() {
+                context.source_line = self.source_line();
+            }
+            self.debugger.on_instruction(&context);
+        }
+
         self.clock += 1;
     }
+
+    // Checks memory breakpoints on accesses of the instruction executed at `context.pc`
+    fn check_accesses(&mut self, context: &Context) {
+        if !self.cpu.bus.record_accesses {
+            return;
+        }
+        self.cpu.bus.record_accesses = false;
+        let accesses = std::mem::take(&mut self.cpu.bus.accesses);
+        let ins_code = accesses.first().map_or(0, |access| access.data);
+        let ins_len = disasm::get_instruction_size(&INSTRUCTION_LOOKUP[ins_code as usize]);
+        self.debugger.check_accesses(&accesses, ins_len, context);
+    }
+
+    fn dma_clock(&mut self) {
+        let get_cycle = self.cpu.total_cycles % 2 == 0;
+        let access = self
+            .dma
+            .borrow_mut()
+            .clock(get_cycle, self.cpu.bus.last_read_addr);
+        self.cpu.total_cycles += 1;
+
+        match access {
+            DmaAccess::None => {}
+            DmaAccess::DummyRead(addr) => {
+                // Repeating a $4016/$4017 read clocks the controller once more
+                self.cpu.bus.cpu_read(addr);
+            }
+            DmaAccess::OamRead(addr) => {
+                let data = self.cpu.bus.cpu_read(addr);
+                self.dma.borrow_mut().data = data;
+            }
+            DmaAccess::OamWrite(data) => {
+                self.ppu.borrow_mut().write_oam_data(data);
+            }
+            DmaAccess::DmcRead(addr) => {
+                let data = self.cpu.bus.cpu_read(addr);
+                self.apu.borrow_mut().dmc.fill(data);
+            }
+        }
+    }
 }
 
 impl FrameTime {
     pub fn update(&mut self, dt: f32) -> bool {
-        // Limit to 60 fps
+        // Limit to the region frame rate
         self.dt_accum += dt;
         self.dt += dt;
-        const FRAME_TIME: f32 = 1.0 / 60.0;
-        if self.dt_accum < FRAME_TIME {
+        let frame_time = 1.0 / self.frame_rate;
+        if self.dt_accum < frame_time {
             return false;
         }
         self.fps = 1.0 / self.dt;
-        self.dt_accum -= FRAME_TIME;
+        self.dt_accum -= frame_time;
         self.dt = 0.0;
         true
     }
@@ -255,7 +615,7 @@ mod tests {
 
         let log_file = File::create(&PathBuf::from("nestest_out.log")).unwrap();
 
-        e.load_rom(&PathBuf::from("roms/nestest.nes"));
+        e.load_rom(&PathBuf::from("roms/nestest.nes")).unwrap();
         e.cpu.PC = 0xC000;
         let mut cmp_file = BufReader::new(File::open(&PathBuf::from("roms/nestest.log")).unwrap());
         for _ in 1..100000 {
@@ -275,23 +635,7 @@ mod tests {
 
                 // ignore whitespace
                 let out_vec: Vec<&str> = out_line.split_whitespace().collect();
-                let mut cmp_vec: Vec<&str> = cmp_line.split_whitespace().collect();
-
-                // filter out some data we don't support:
-                // find range from "=" to "A:" and remove
-                let i1 = cmp_vec.iter().position(|&r| r.starts_with("A:")).unwrap();
-                let p0 = cmp_vec
-                    .iter()
-                    .take(i1 + 1)
-                    .position(|&r| r.starts_with("@"));
-                let p1 = cmp_vec
-                    .iter()
-                    .take(i1 + 1)
-                    .position(|&r| r.starts_with("="));
-                let p = std::cmp::min(p0.unwrap_or(i1), p1.unwrap_or(i1));
-                if p < i1 {
-                    cmp_vec.drain(p..i1);
-                }
+                let cmp_vec: Vec<&str> = cmp_line.split_whitespace().collect();
 
                 assert_eq!(out_vec, cmp_vec);
             }
@@ -301,4 +645,98 @@ mod tests {
             e.clock();
         }
     }
+
+    fn run_until_paused(e: &mut Emulator) {
+        for _ in 0..1000000 {
+            e.clock();
+            if e.debugger.paused {
+                return;
+            }
+        }
+        panic!("debugger didn't stop");
+    }
+
+    #[test]
+    fn debugger_stepping() {
+        let mut e = Emulator::new();
+        e.load_rom(&PathBuf::from("roms/nestest.nes")).unwrap();
+        e.cpu.PC = 0xC000;
+        e.cpu.cycles = 0;
+
+        e.step(Step::Instruction);
+        run_until_paused(&mut e);
+        assert_eq!(e.cpu.PC, 0xC5F5);
+
+        // STX $10
+        let bp = debugger::Breakpoint::new(debugger::BreakKind::Write, 0x10, 0x10);
+        e.debugger.breakpoints.push(bp);
+        e.debugger.resume();
+        run_until_paused(&mut e);
+        assert_eq!(e.cpu.PC, 0xC5FB);
+        e.debugger.breakpoints.clear();
+
+        e.step(Step::Over);
+        run_until_paused(&mut e);
+        assert_eq!(e.cpu.PC, 0xC5FD);
+        // JSR $C72D
+        e.step(Step::Over);
+        run_until_paused(&mut e);
+        assert_eq!((e.cpu.PC, e.cpu.SP), (0xC600, 0xFD));
+
+        // Into JSR $C7DB and out of it
+        e.step(Step::Instruction);
+        run_until_paused(&mut e);
+        assert_eq!(e.cpu.PC, 0xC7DB);
+        e.step(Step::Out);
+        run_until_paused(&mut e);
+        assert_eq!(e.cpu.PC, 0xC603);
+
+        e.step(Step::Scanline(20));
+        run_until_paused(&mut e);
+        assert_eq!(e.ppu.borrow().scanline, 20);
+    }
+
+    #[test]
+    fn prg_dump() {
+        let mut e = Emulator::new();
+        e.load_rom(&PathBuf::from("roms/nestest.nes")).unwrap();
+        let mut out = vec![];
+        e.dump_prg(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        // 16K bank is mirrored, the upper copy is used
+        assert!(text.contains(".org $C000"));
+        assert!(text.contains("    JMP LC5F5"));
+    }
+
+    #[test]
+    fn memory_viewer() {
+        let mut e = Emulator::new();
+        e.load_rom(&PathBuf::from("roms/nestest.nes")).unwrap();
+
+        e.poke_memory(MemorySpace::Cpu, 0x0812, 0x55);
+        assert_eq!(e.peek_memory(MemorySpace::Ram, 0x12), Some(0x55));
+        // Peeking PPU status keeps vblank flag
+        e.ppu.borrow_mut().status.vertical_blank = true;
+        assert_eq!(e.peek_memory(MemorySpace::Cpu, 0x2002), Some(0x80));
+        assert!(e.ppu.borrow().status.vertical_blank);
+        assert_eq!(e.peek_memory(MemorySpace::Ram, 0x800), None);
+
+        // 16K PRG-ROM is mirrored
+        e.poke_memory(MemorySpace::Cpu, 0xC000, 0xEA);
+        assert_eq!(e.peek_memory(MemorySpace::PrgRom, 0), Some(0xEA));
+        assert_eq!(e.peek_memory(MemorySpace::Cpu, 0x8000), Some(0xEA));
+
+        e.poke_memory(MemorySpace::Ppu, 0x3F10, 0x21);
+        assert_eq!(e.peek_memory(MemorySpace::Ppu, 0x3F00), Some(0x21));
+        e.poke_memory(MemorySpace::Oam, 5, 0x30);
+        assert_eq!(e.ppu.borrow().oam_mem[1].id, 0x30);
+
+        e.poke_memory(MemorySpace::Ram, 0x20, 0x42);
+        e.toggle_freeze(MemorySpace::Ram, 0x20);
+        e.poke_memory(MemorySpace::Ram, 0x20, 0);
+        e.run_frame();
+        assert_eq!(e.peek_memory(MemorySpace::Ram, 0x20), Some(0x42));
+        e.toggle_freeze(MemorySpace::Ram, 0x20);
+        assert!(e.freezes.is_empty());
+    }
 }
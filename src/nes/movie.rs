@@ -0,0 +1,139 @@
+use super::region::Region;
+use std::fs;
+use std::path::PathBuf;
+
+// Soft and hard reset bits of the FM2 command field
+const COMMAND_SOFT_RESET: u8 = 0x01;
+const COMMAND_HARD_RESET: u8 = 0x02;
+
+/// Input of all pads for one frame of a movie.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MovieFrame {
+    /// Reset requested before the frame.
+    pub reset: bool,
+    /// Buttons in `Controller::input` bit order.
+    pub pads: [u8; 4],
+}
+
+/// FCEUX movie (.fm2) played back as pad input frame by frame.
+pub struct Movie {
+    pub region: Region,
+    pub four_score: bool,
+    frames: Vec<MovieFrame>,
+    position: usize,
+}
+
+// Pad buttons are written as "RLDUTSBA", any character except '.' and ' ' is a pressed button
+fn parse_pad(field: &str) -> u8 {
+    if field.len() != 8 {
+        // Empty port or a Zapper, which is not supported
+        return 0;
+    }
+    field
+        .chars()
+        .enumerate()
+        .filter(|(_, c)| *c != '.' && *c != ' ')
+        .fold(0, |input, (i, _)| input | 1 << i)
+}
+
+impl Movie {
+    pub fn load(path: &PathBuf) -> Option<Self> {
+        let text = match fs::read_to_string(path) {
+            Err(e) => {
+                println!("Can't read file: '{0}'. Error: {1}", path.display(), e);
+                return None;
+            }
+            Ok(text) => text,
+        };
+        match Movie::parse(&text) {
+            Ok(movie) => Some(movie),
+            Err(e) => {
+                println!("Can't parse movie: '{0}'. Error: {1}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut movie = Movie {
+            region: Region::Ntsc,
+            four_score: false,
+            frames: vec![],
+            position: 0,
+        };
+
+        for (num, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.starts_with('|') {
+                let fields: Vec<&str> = line.split('|').collect();
+                let commands: u8 = fields
+                    .get(1)
+                    .and_then(|c| c.trim().parse().ok())
+                    .ok_or(format!("bad input on line {}", num + 1))?;
+                let mut frame = MovieFrame {
+                    reset: commands & (COMMAND_SOFT_RESET | COMMAND_HARD_RESET) != 0,
+                    pads: [0; 4],
+                };
+                let pad_count = if movie.four_score { 4 } else { 2 };
+                for (pad, field) in fields.iter().skip(2).take(pad_count).enumerate() {
+                    frame.pads[pad] = parse_pad(field);
+                }
+                movie.frames.push(frame);
+            } else {
+                let mut parts = line.splitn(2, ' ');
+                let key = parts.next();
+                let value = parts.next().unwrap_or("").trim();
+                match key {
+                    Some("palFlag") if value == "1" => movie.region = Region::Pal,
+                    Some("fourscore") => movie.four_score = value == "1",
+                    _ => (),
+                }
+            }
+        }
+
+        if movie.frames.is_empty() {
+            return Err("no input frames".to_string());
+        }
+        Ok(movie)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Input for the next frame, `None` after the last one.
+    pub fn next_frame(&mut self) -> Option<MovieFrame> {
+        let frame = self.frames.get(self.position).copied();
+        self.position += 1;
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fm2() {
+        let text = "version 3\n\
+                    palFlag 0\n\
+                    fourscore 0\n\
+                    port0 1\n\
+                    port1 1\n\
+                    port2 0\n\
+                    |0|........|........||\n\
+                    |0|R......A|...U....||\n\
+                    |1|.L..T...|........||\n";
+        let mut movie = Movie::parse(text).unwrap();
+        assert_eq!(movie.frame_count(), 3);
+        assert_eq!(movie.region, Region::Ntsc);
+        assert_eq!(movie.next_frame().unwrap(), MovieFrame::default());
+
+        let frame = movie.next_frame().unwrap();
+        assert_eq!(frame.pads, [0x81, 0x08, 0, 0]);
+        let frame = movie.next_frame().unwrap();
+        assert!(frame.reset);
+        assert_eq!(frame.pads[0], 0x12);
+        assert_eq!(movie.next_frame(), None);
+    }
+}
@@ -0,0 +1,130 @@
+use super::controller::{Controller, ControllerPort};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Adapter wiring extra pads to the controller ports.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Multitap {
+    /// Only pads 1 and 2.
+    None,
+    /// NES Four Score / Satellite, pads are multiplexed on the serial lines.
+    FourScore,
+    /// Famicom pads 3 and 4 plugged into the expansion port, read through D1.
+    FamicomExpansion,
+}
+
+/// One port of a Four Score: reads out two pads followed by the adapter signature.
+pub struct FourScore {
+    pads: [Rc<RefCell<Controller>>; 2],
+    signature: u8,
+    strobe: bool,
+    reads: u8,
+}
+
+impl ControllerPort for FourScore {
+    fn write(&mut self, data: u8) {
+        self.strobe = (data & 0x01) != 0;
+        if self.strobe {
+            self.reads = 0;
+        }
+        for pad in &self.pads {
+            pad.borrow_mut().write(data);
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        let data = match self.reads {
+            0..=7 => self.pads[0].borrow_mut().read(),
+            8..=15 => self.pads[1].borrow_mut().read(),
+            16..=23 => (self.signature >> (23 - self.reads)) & 0x01,
+            _ => 0x01,
+        };
+        if !self.strobe && self.reads < 24 {
+            self.reads += 1;
+        }
+        data
+    }
+
+    fn peek(&self) -> u8 {
+        match self.reads {
+            0..=7 => self.pads[0].borrow().peek(),
+            8..=15 => self.pads[1].borrow().peek(),
+            16..=23 => (self.signature >> (23 - self.reads)) & 0x01,
+            _ => 0x01,
+        }
+    }
+}
+
+impl FourScore {
+    /// `num` is the port number, port 0 hosts pads 1 and 3, port 1 pads 2 and 4.
+    pub fn new(
+        num: usize,
+        first: Rc<RefCell<Controller>>,
+        second: Rc<RefCell<Controller>>,
+    ) -> Self {
+        FourScore {
+            pads: [first, second],
+            signature: if num == 0 { 0x10 } else { 0x20 },
+            strobe: false,
+            reads: 0,
+        }
+    }
+}
+
+/// Famicom pad together with the expansion port pad read from the same register.
+pub struct FamicomPads {
+    pads: [Rc<RefCell<Controller>>; 2],
+}
+
+impl ControllerPort for FamicomPads {
+    fn write(&mut self, data: u8) {
+        for pad in &self.pads {
+            pad.borrow_mut().write(data);
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        self.pads[0].borrow_mut().read() | self.pads[1].borrow_mut().read() << 1
+    }
+
+    fn peek(&self) -> u8 {
+        self.pads[0].borrow().peek() | self.pads[1].borrow().peek() << 1
+    }
+}
+
+impl FamicomPads {
+    pub fn new(pad: Rc<RefCell<Controller>>, expansion: Rc<RefCell<Controller>>) -> Self {
+        FamicomPads {
+            pads: [pad, expansion],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn four_score_signature() {
+        let pads: Vec<_> = (0..2)
+            .map(|_| Rc::new(RefCell::new(Controller::new())))
+            .collect();
+        pads[0].borrow_mut().input = 0x80;
+        pads[1].borrow_mut().input = 0x01;
+
+        let mut port = FourScore::new(1, pads[0].clone(), pads[1].clone());
+        port.write(1);
+        port.write(0);
+        let bits: Vec<u8> = (0..25).map(|_| port.read()).collect();
+        assert_eq!(
+            bits,
+            [
+                1, 0, 0, 0, 0, 0, 0, 0, // first pad
+                0, 0, 0, 0, 0, 0, 0, 1, // second pad
+                0, 0, 1, 0, 0, 0, 0, 0, // signature
+                1
+            ]
+        );
+    }
+}
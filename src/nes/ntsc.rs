@@ -0,0 +1,188 @@
+use super::palette::{ntsc_signal, NtscParams};
+use super::ppu::{Screen, SCREEN_SIZE};
+
+/// Width of the filtered image, about the horizontal resolution a TV resolves.
+pub const NTSC_WIDTH: usize = 602;
+
+// The PPU signal generator runs at 8 samples per dot, 12 samples per subcarrier period
+const SAMPLES_PER_DOT: usize = 8;
+const LINE_SAMPLES: usize = SCREEN_SIZE.0 * SAMPLES_PER_DOT;
+// Averaging a full subcarrier period separates luma from chroma,
+// the wider chroma window makes colors bleed like on a real TV
+const LUMA_WINDOW: usize = 12;
+const CHROMA_WINDOW: usize = 24;
+// Blank signal around the visible part of the line
+const PADDING: usize = CHROMA_WINDOW / 2;
+// Steps of the gamma table between black and white
+const GAMMA_STEPS: usize = 4096;
+
+/// Composite video filter.
+///
+/// Encodes each scanline of color indices into the composite signal the PPU outputs and
+/// decodes it back like a TV would, which gives dot crawl, color bleeding and artifact
+/// colors on sharp luma edges.
+pub struct NtscFilter {
+    pub params: NtscParams,
+    // Params the tables below were built for
+    table_params: NtscParams,
+    // Signal level of every 9-bit color at each of the 12 phases
+    signal: Vec<[f32; 12]>,
+    // Decoder's subcarrier reference (cos, sin) at each phase
+    carrier: [(f32, f32); 12],
+    // Gamma corrected channel values of levels 0-1
+    gamma: Vec<u32>,
+    output: Vec<u32>,
+    // Running sums of the decoded luma and chroma components of a line
+    y_sum: Vec<f32>,
+    i_sum: Vec<f32>,
+    q_sum: Vec<f32>,
+}
+
+impl NtscFilter {
+    pub fn new(params: NtscParams) -> Self {
+        let len = LINE_SAMPLES + 2 * PADDING + 1;
+        let signal = (0..512)
+            .map(|color| {
+                let mut levels = [0.0; 12];
+                for (phase, level) in levels.iter_mut().enumerate() {
+                    *level = ntsc_signal(color, phase);
+                }
+                levels
+            })
+            .collect();
+        let mut filter = NtscFilter {
+            params,
+            table_params: params,
+            signal,
+            carrier: [(0.0, 0.0); 12],
+            gamma: vec![],
+            output: vec![0; NTSC_WIDTH * SCREEN_SIZE.1],
+            y_sum: vec![0.0; len],
+            i_sum: vec![0.0; len],
+            q_sum: vec![0.0; len],
+        };
+        filter.update_tables();
+        filter
+    }
+
+    fn update_tables(&mut self) {
+        self.table_params = self.params;
+        for (phase, carrier) in self.carrier.iter_mut().enumerate() {
+            let angle = self.params.phase_angle(phase as f32);
+            *carrier = (angle.cos(), angle.sin());
+        }
+        let params = self.params;
+        self.gamma = (0..=GAMMA_STEPS)
+            .map(|step| params.gamma_byte(step as f32 / GAMMA_STEPS as f32))
+            .collect();
+    }
+
+    /// Filtered image of `NTSC_WIDTH` x 240 RGB pixels.
+    pub fn output(&self) -> &[u32] {
+        &self.output
+    }
+
+    pub fn apply(&mut self, screen: &Screen) {
+        if self.params != self.table_params {
+            self.update_tables();
+        }
+        for y in 0..SCREEN_SIZE.1 {
+            // A scanline is 341 dots long, which moves the phase by 4 samples each line
+            let line_phase = screen.phase as usize + y * 4;
+            self.encode_line(screen, y, line_phase);
+            self.decode_line(y);
+        }
+    }
+
+    fn encode_line(&mut self, screen: &Screen, y: usize, line_phase: usize) {
+        let (mut y_acc, mut i_acc, mut q_acc) = (0.0, 0.0, 0.0);
+        for s in 0..LINE_SAMPLES + 2 * PADDING {
+            if s >= PADDING && s < LINE_SAMPLES + PADDING {
+                let sample = s - PADDING;
+                let color = screen.get_pixel(sample / SAMPLES_PER_DOT, y);
+                let phase = (line_phase + sample) % 12;
+                let v = self.signal[color as usize & 0x1FF][phase];
+                let (cos, sin) = self.carrier[phase];
+                y_acc += v;
+                i_acc += v * cos;
+                q_acc += v * sin;
+            }
+            self.y_sum[s + 1] = y_acc;
+            self.i_sum[s + 1] = i_acc;
+            self.q_sum[s + 1] = q_acc;
+        }
+    }
+
+    fn decode_line(&mut self, y: usize) {
+        let window_avg = |sum: &[f32], center: usize, width: usize| {
+            (sum[center + width / 2] - sum[center - width / 2]) / width as f32
+        };
+
+        let gamma = &self.gamma;
+        // Levels above white saturate
+        let to_byte = |v: f32| {
+            let step = (v.max(0.0) * GAMMA_STEPS as f32).round() as usize;
+            gamma.get(step).copied().unwrap_or(255)
+        };
+        for x in 0..NTSC_WIDTH {
+            let center = PADDING + (x * 2 + 1) * LINE_SAMPLES / (NTSC_WIDTH * 2);
+            let luma = window_avg(&self.y_sum, center, LUMA_WINDOW);
+            let i = window_avg(&self.i_sum, center, CHROMA_WINDOW);
+            let q = window_avg(&self.q_sum, center, CHROMA_WINDOW);
+            self.output[x + y * NTSC_WIDTH] = self.params.yiq_to_rgb_with(luma, i, q, to_byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::palette::Palette;
+    use super::*;
+
+    // Pixel in the middle of the picture, away from the edges
+    const CENTER: usize = NTSC_WIDTH / 2 + 100 * NTSC_WIDTH;
+
+    #[test]
+    fn flat_color_matches_palette() {
+        let params = NtscParams::default();
+        let palette = Palette::generate(&params);
+        let mut filter = NtscFilter::new(params);
+        let mut screen = Screen::default();
+        for color in [0x16, 0x2A, 0x30, 1 << 6 | 0x21].iter() {
+            for y in 0..SCREEN_SIZE.1 {
+                for x in 0..SCREEN_SIZE.0 {
+                    screen.set_pixel(x, y, *color);
+                }
+            }
+            filter.apply(&screen);
+
+            let expected = palette.get_color(*color);
+            let actual = filter.output()[CENTER];
+            for shift in [0, 8, 16].iter() {
+                let diff = ((expected >> shift) & 0xFF) as i32 - ((actual >> shift) & 0xFF) as i32;
+                assert!(diff.abs() <= 2, "{:06X} != {:06X}", actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn artifact_colors() {
+        // Alternating black and white columns produce color fringes
+        let mut filter = NtscFilter::new(NtscParams::default());
+        let mut screen = Screen::default();
+        for y in 0..SCREEN_SIZE.1 {
+            for x in 0..SCREEN_SIZE.0 {
+                screen.set_pixel(x, y, if x % 2 == 0 { 0x30 } else { 0x0F });
+            }
+        }
+        filter.apply(&screen);
+        let c = filter.output()[CENTER];
+        let (r, g, b) = ((c >> 16) & 0xFF, (c >> 8) & 0xFF, c & 0xFF);
+        assert!(r != g || g != b);
+
+        // Tables follow param changes
+        filter.params.hue = 90.0;
+        filter.apply(&screen);
+        assert_ne!(filter.output()[CENTER], c);
+    }
+}
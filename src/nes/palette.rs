@@ -0,0 +1,266 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::fs;
+use std::path::PathBuf;
+
+/// Number of colors with all emphasis combinations, indexed by `emphasis << 6 | color`.
+pub const PALETTE_SIZE: usize = 512;
+
+const DEFAULT_PALETTE: [u32; 64] = [
+    0x7C7C7C, 0x0000FC, 0x0000BC, 0x4428BC, 0x940084, 0xA80020, 0xA81000, 0x881400, 0x503000,
+    0x007800, 0x006800, 0x005800, 0x004058, 0x000000, 0x000000, 0x000000, 0xBCBCBC, 0x0078F8,
+    0x0058F8, 0x6844FC, 0xD800CC, 0xE40058, 0xF83800, 0xE45C10, 0xAC7C00, 0x00B800, 0x00A800,
+    0x00A844, 0x008888, 0x000000, 0x000000, 0x000000, 0xF8F8F8, 0x3CBCFC, 0x6888FC, 0x9878F8,
+    0xF878F8, 0xF85898, 0xF87858, 0xFCA044, 0xF8B800, 0xB8F818, 0x58D854, 0x58F898, 0x00E8D8,
+    0x787878, 0x000000, 0x000000, 0xFCFCFC, 0xA4E4FC, 0xB8B8F8, 0xD8B8F8, 0xF8B8F8, 0xF8A4C0,
+    0xF0D0B0, 0xFCE0A8, 0xF8D878, 0xD8F878, 0xB8F8B8, 0xB8F8D8, 0x00FCFC, 0xF8D8F8, 0x000000,
+    0x000000,
+];
+
+// Color emphasis darkens the other two channels by about 18%
+const EMPHASIS_ATTENUATION: f32 = 0.816328;
+
+// Composite signal levels (volts) of the 4 luma rows, for the low and high half of a color wave
+const SIGNAL_LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const SIGNAL_HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const SIGNAL_BLACK: f32 = 0.518;
+const SIGNAL_WHITE: f32 = 1.962;
+// Emphasis attenuates the signal during the emphasized color phases
+const SIGNAL_ATTENUATION: f32 = 0.746;
+// Phase of the color burst reference, in 1/12 of the subcarrier period
+const COLOR_BURST_PHASE: f32 = 3.9;
+
+/// NTSC decoder settings used to generate a palette and by the composite video filter.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NtscParams {
+    /// Hue rotation in degrees.
+    pub hue: f32,
+    pub saturation: f32,
+    pub contrast: f32,
+    pub brightness: f32,
+    /// Display gamma the colors are corrected for, 2.2 leaves them as decoded.
+    pub gamma: f32,
+}
+
+impl Default for NtscParams {
+    fn default() -> Self {
+        NtscParams {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 1.0,
+            gamma: 2.2,
+        }
+    }
+}
+
+impl NtscParams {
+    /// Angle of the decoder's subcarrier reference at `phase` (in 1/12 of a period).
+    pub fn phase_angle(&self, phase: f32) -> f32 {
+        PI * (phase + COLOR_BURST_PHASE) / 6.0 + self.hue.to_radians()
+    }
+
+    /// Gamma corrected 0-255 channel value of a decoded level.
+    pub fn gamma_byte(&self, v: f32) -> u32 {
+        let v = v.max(0.0).powf(2.2 / self.gamma);
+        (v * 255.0).round().min(255.0) as u32
+    }
+
+    /// Applies picture settings to a decoded YIQ color and converts it to RGB.
+    pub fn yiq_to_rgb(&self, y: f32, i: f32, q: f32) -> u32 {
+        self.yiq_to_rgb_with(y, i, q, |v| self.gamma_byte(v))
+    }
+
+    /// Same as `yiq_to_rgb` with the gamma curve given by `to_byte`, e.g. from a table.
+    pub fn yiq_to_rgb_with(&self, y: f32, i: f32, q: f32, to_byte: impl Fn(f32) -> u32) -> u32 {
+        let y = y * self.contrast + (self.brightness - 1.0);
+        let i = i * self.saturation;
+        let q = q * self.saturation;
+
+        let r = to_byte(y + 0.946882 * i + 0.623557 * q);
+        let g = to_byte(y - 0.274788 * i - 0.635691 * q);
+        let b = to_byte(y - 1.108545 * i + 1.709007 * q);
+        r << 16 | g << 8 | b
+    }
+}
+
+/// Composite signal level the PPU outputs for a 9-bit color at subcarrier `phase`
+/// (0-11), normalized so that black is 0 and white is 1.
+pub fn ntsc_signal(color: u16, phase: usize) -> f32 {
+    let hue = (color & 0x0F) as usize;
+    let emphasis = color >> 6;
+    // Columns $E and $F are black
+    let row = if hue < 0x0E {
+        (color as usize >> 4) & 0x03
+    } else {
+        1
+    };
+    let low = if hue == 0x00 {
+        SIGNAL_HIGH[row]
+    } else {
+        SIGNAL_LOW[row]
+    };
+    let high = if hue < 0x0D {
+        SIGNAL_HIGH[row]
+    } else {
+        SIGNAL_LOW[row]
+    };
+
+    // The color wave is high during 6 of the 12 subcarrier phases
+    let in_phase = |color: usize| (color + phase) % 12 < 6;
+
+    let mut signal = if in_phase(hue) { high } else { low };
+    if hue < 0x0E
+        && ((emphasis & 1 != 0 && in_phase(0))
+            || (emphasis & 2 != 0 && in_phase(4))
+            || (emphasis & 4 != 0 && in_phase(8)))
+    {
+        signal *= SIGNAL_ATTENUATION;
+    }
+    (signal - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK)
+}
+
+/// Where the palette colors come from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
+pub enum PaletteSource {
+    Default,
+    Ntsc(NtscParams),
+    File(PathBuf),
+}
+
+/// Maps PPU color index and emphasis bits to RGB.
+#[derive(Clone)]
+pub struct Palette {
+    colors: Vec<u32>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::from_64_colors(&DEFAULT_PALETTE)
+    }
+}
+
+impl Palette {
+    /// Builds the palette for `source`, `None` if a palette file can't be loaded.
+    pub fn from_source(source: &PaletteSource) -> Option<Self> {
+        match source {
+            PaletteSource::Default => Some(Palette::default()),
+            PaletteSource::Ntsc(params) => Some(Palette::generate(params)),
+            PaletteSource::File(palfile) => Palette::load_from_file(palfile),
+        }
+    }
+
+    /// Loads a 64 color palette (192 bytes) or a 512 color one (1536 bytes) with all
+    /// emphasis combinations from a `.pal` file.
+    pub fn load_from_file(palfile: &PathBuf) -> Option<Self> {
+        let contents = match fs::read(palfile) {
+            Err(e) => {
+                println!("Can't read file: '{0}'. Error: {1}", palfile.display(), e);
+                return None;
+            }
+            Ok(f) => f,
+        };
+
+        let colors: Vec<u32> = contents
+            .chunks_exact(3)
+            .map(|rgb| (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32)
+            .collect();
+
+        match contents.len() {
+            192 => Some(Palette::from_64_colors(&colors)),
+            1536 => Some(Palette { colors }),
+            len => {
+                println!(
+                    "Wrong palette size in file: '{0}', {1} bytes.",
+                    palfile.display(),
+                    len
+                );
+                None
+            }
+        }
+    }
+
+    /// Derives emphasis colors from 64 base colors by darkening the other channels.
+    pub fn from_64_colors(base: &[u32]) -> Self {
+        let mut colors = vec![0; PALETTE_SIZE];
+        for (i, c) in colors.iter_mut().enumerate() {
+            let emphasis = i >> 6;
+            let mut rgb = [
+                ((base[i & 0x3F] >> 16) & 0xFF) as f32,
+                ((base[i & 0x3F] >> 8) & 0xFF) as f32,
+                (base[i & 0x3F] & 0xFF) as f32,
+            ];
+            // Bit 0 emphasizes red, bit 1 green and bit 2 blue
+            for channel in 0..3 {
+                if emphasis & (1 << channel) != 0 {
+                    for (other, value) in rgb.iter_mut().enumerate() {
+                        if other != channel {
+                            *value *= EMPHASIS_ATTENUATION;
+                        }
+                    }
+                }
+            }
+            *c = (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32;
+        }
+        Palette { colors }
+    }
+
+    /// Generates all 512 colors by decoding the composite signal the PPU outputs.
+    pub fn generate(params: &NtscParams) -> Self {
+        let mut colors = vec![0; PALETTE_SIZE];
+        for (color, c) in colors.iter_mut().enumerate() {
+            // One full subcarrier period averages out to the color the TV shows
+            let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+            for phase in 0..12 {
+                let v = ntsc_signal(color as u16, phase) / 12.0;
+                let angle = params.phase_angle(phase as f32);
+                y += v;
+                i += v * angle.cos();
+                q += v * angle.sin();
+            }
+            *c = params.yiq_to_rgb(y, i, q);
+        }
+        Palette { colors }
+    }
+
+    /// RGB color of a 6-bit PPU color with emphasis bits 6-8.
+    pub fn get_color(&self, index: u16) -> u32 {
+        self.colors[index as usize & (PALETTE_SIZE - 1)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(c: u32) -> (u32, u32, u32) {
+        ((c >> 16) & 0xFF, (c >> 8) & 0xFF, c & 0xFF)
+    }
+
+    #[test]
+    fn generated_palette() {
+        let palette = Palette::generate(&NtscParams::default());
+        let (r, g, b) = rgb(palette.get_color(0x16));
+        assert!(r > g && r > b);
+        let (r, g, b) = rgb(palette.get_color(0x12));
+        assert!(b > r && b > g);
+        assert_eq!(palette.get_color(0x0F), 0);
+        assert_eq!(palette.get_color(0x30), 0xFFFFFF);
+
+        // Red emphasis darkens a white pixel's green and blue
+        let (r, g, b) = rgb(palette.get_color(1 << 6 | 0x30));
+        assert!(r > g && r > b);
+    }
+
+    #[test]
+    fn emphasis_from_64_colors() {
+        let palette = Palette::from_64_colors(&DEFAULT_PALETTE);
+        assert_eq!(palette.get_color(0x30), DEFAULT_PALETTE[0x30]);
+        let (r, g, b) = rgb(palette.get_color(4 << 6 | 0x30));
+        assert_eq!(b, 0xFC);
+        assert!(r < b && g < b);
+        assert_eq!(palette.get_color(7 << 6 | 0x0F), 0);
+    }
+}
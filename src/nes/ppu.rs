@@ -4,6 +4,8 @@ use std::rc::Rc;
 
 use super::bus::CpuBusDevice;
 use super::cartridge::Cartridge;
+use super::palette::Palette;
+use super::region::Region;
 
 #[derive(Default, Clone)]
 pub struct CtrlReg {
@@ -102,17 +104,6 @@ impl LoopyAddr {
     }
 }
 
-const PALETTE: [u32; 64] = [
-    0x7C7C7C, 0x0000FC, 0x0000BC, 0x4428BC, 0x940084, 0xA80020, 0xA81000, 0x881400, 0x503000,
-    0x007800, 0x006800, 0x005800, 0x004058, 0x000000, 0x000000, 0x000000, 0xBCBCBC, 0x0078F8,
-    0x0058F8, 0x6844FC, 0xD800CC, 0xE40058, 0xF83800, 0xE45C10, 0xAC7C00, 0x00B800, 0x00A800,
-    0x00A844, 0x008888, 0x000000, 0x000000, 0x000000, 0xF8F8F8, 0x3CBCFC, 0x6888FC, 0x9878F8,
-    0xF878F8, 0xF85898, 0xF87858, 0xFCA044, 0xF8B800, 0xB8F818, 0x58D854, 0x58F898, 0x00E8D8,
-    0x787878, 0x000000, 0x000000, 0xFCFCFC, 0xA4E4FC, 0xB8B8F8, 0xD8B8F8, 0xF8B8F8, 0xF8A4C0,
-    0xF0D0B0, 0xFCE0A8, 0xF8D878, 0xD8F878, 0xB8F8B8, 0xB8F8D8, 0x00FCFC, 0xF8D8F8, 0x000000,
-    0x000000,
-];
-
 #[derive(Default, Clone, Copy)]
 pub struct ObjectAttributeEntry {
     pub y: u8,
@@ -121,6 +112,38 @@ pub struct ObjectAttributeEntry {
     pub x: u8,
 }
 
+impl ObjectAttributeEntry {
+    pub fn get_byte(&self, i: u8) -> u8 {
+        match i & 0x03 {
+            0 => self.y,
+            1 => self.id,
+            2 => self.attr,
+            _ => self.x,
+        }
+    }
+
+    pub fn set_byte(&mut self, i: u8, data: u8) {
+        match i & 0x03 {
+            0 => self.y = data,
+            1 => self.id = data,
+            // Bits 2-4 of attributes are not implemented in OAM
+            2 => self.attr = data & 0xE3,
+            _ => self.x = data,
+        }
+    }
+}
+
+/// Sprite evaluation progress during dots 65-256.
+#[derive(Default, Clone, Copy)]
+pub struct SpriteEvalState {
+    n: u8, // sprite index in primary OAM
+    m: u8, // byte index within the sprite
+    secondary_addr: u8,
+    found: u8,
+    done: bool,
+    sprite_zero: bool,
+}
+
 #[derive(Default, Clone, Copy)]
 pub struct SpriteRenderState {
     pub scanline: [ObjectAttributeEntry; 8],
@@ -144,8 +167,15 @@ pub struct BgRenderState {
 pub const SCREEN_SIZE: (usize, usize) = (256, 240);
 
 /// Screen buffer.
+///
+/// The PPU writes 9-bit pixels (6-bit color index with emphasis in bits 6-8), RGB is
+/// produced from them with `update_rgb` once a frame is complete.
 pub struct Screen {
-    buffer: Vec<u32>,
+    buffer: Vec<u16>,
+    rgb: Vec<u32>,
+    /// NTSC subcarrier phase (0-11) at dot 0 of the first scanline, it changes from frame
+    /// to frame which makes the dot crawl.
+    pub phase: u8,
     pub complete: bool,
 }
 
@@ -153,19 +183,34 @@ impl Default for Screen {
     fn default() -> Self {
         Screen {
             buffer: vec![0; SCREEN_SIZE.0 * SCREEN_SIZE.1],
+            rgb: vec![0; SCREEN_SIZE.0 * SCREEN_SIZE.1],
+            phase: 0,
             complete: true,
         }
     }
 }
 
 impl Screen {
-    pub fn set_pixel(&mut self, x: usize, y: usize, c: u32) {
+    pub fn set_pixel(&mut self, x: usize, y: usize, c: u16) {
         self.buffer[x + y * SCREEN_SIZE.0] = c;
     }
 
-    pub fn get_pixel(&self, x: usize, y: usize) -> u32 {
+    /// Color index with emphasis bits.
+    pub fn get_pixel(&self, x: usize, y: usize) -> u16 {
         self.buffer[x + y * SCREEN_SIZE.0]
     }
+
+    /// RGB colors as of the last `update_rgb`.
+    pub fn rgb(&self) -> &[u32] {
+        &self.rgb
+    }
+
+    /// Converts the color indices to RGB with `palette`.
+    pub fn update_rgb(&mut self, palette: &Palette) {
+        for (rgb, &c) in self.rgb.iter_mut().zip(self.buffer.iter()) {
+            *rgb = palette.get_color(c);
+        }
+    }
 }
 
 pub struct Ppu {
@@ -174,6 +219,12 @@ pub struct Ppu {
     pub cycle: i16,
     pub scanline: i16,
     pub odd_frame: bool,
+    /// Frames completed since reset.
+    pub frame: u32,
+    // NTSC subcarrier phase at dot 0 of the current scanline
+    signal_phase: u8,
+    pub region: Region,
+    pub palette: Palette,
 
     pub ctrl: CtrlReg,
     pub mask: MaskReg,
@@ -183,6 +234,10 @@ pub struct Ppu {
 
     pub oam_addr: u8,
     pub oam_mem: [ObjectAttributeEntry; 64],
+    pub secondary_oam: [u8; 32],
+    // Value on the internal OAM bus, returned by $2004 reads during rendering
+    oam_latch: u8,
+    sprite_eval: SpriteEvalState,
 
     pub name_table: [[u8; 1024]; 2],
     pub pal_table: [u8; 32],
@@ -224,7 +279,7 @@ impl CpuBusDevice for Ppu {
                 self.oam_addr = data;
             }
             0x0004 => {
-                self.write_oam(self.oam_addr, data);
+                self.write_oam_data(data);
             }
             0x0005 => {
                 // Scroll
@@ -274,16 +329,7 @@ impl CpuBusDevice for Ppu {
             }
             0x0004 => {
                 // OAM Data
-                let index = (self.oam_addr / 4) as usize;
-                data = {
-                    match self.oam_addr % 4 {
-                        0 => self.oam_mem[index].y,
-                        1 => self.oam_mem[index].id,
-                        2 => self.oam_mem[index].attr,
-                        3 => self.oam_mem[index].x,
-                        _ => 0,
-                    }
-                };
+                data = self.peek(addr);
             }
             0x0007 => {
                 // PPU Data
@@ -299,6 +345,29 @@ impl CpuBusDevice for Ppu {
 
         data
     }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr & 0x0007 {
+            0x0002 => (self.status.to_byte() & 0xE0) | (self.ppu_data_buf & 0x1F),
+            0x0004 => {
+                if self.is_rendering() {
+                    self.oam_latch
+                } else {
+                    self.read_oam(self.oam_addr)
+                }
+            }
+            0x0007 => {
+                // Palette is read right away, other memory through the buffer
+                let vram_addr = self.vram_addr.to_data();
+                if vram_addr >= 0x3F00 {
+                    self.ppu_peek(vram_addr)
+                } else {
+                    self.ppu_data_buf
+                }
+            }
+            _ => 0,
+        }
+    }
 }
 
 impl BgRenderState {
@@ -351,12 +420,19 @@ impl Ppu {
             cycle: 0,
             scanline: 0,
             odd_frame: false,
+            frame: 0,
+            signal_phase: 0,
+            region: Region::Ntsc,
+            palette: Palette::default(),
             ctrl: CtrlReg::default(),
             mask: MaskReg::default(),
             status: StatusReg::default(),
             nmi: false,
             oam_addr: 0,
             oam_mem: [ObjectAttributeEntry::default(); 64],
+            secondary_oam: [0xFF; 32],
+            oam_latch: 0xFF,
+            sprite_eval: SpriteEvalState::default(),
             name_table: [[0; 1024]; 2],
             pal_table: [0; 32],
             bg_state: BgRenderState::default(),
@@ -372,7 +448,11 @@ impl Ppu {
     }
 
     pub fn reset(&mut self) {
+        let region = self.region;
+        let palette = self.palette.clone();
         *self = Ppu::new(self.cartridge.clone());
+        self.region = region;
+        self.palette = palette;
     }
 
     pub fn ppu_write(&mut self, addr: u16, data: u8) {
@@ -410,16 +490,25 @@ impl Ppu {
     }
 
     pub fn ppu_read(&mut self, addr: u16) -> u8 {
+        let addr = addr & 0x3FFF;
+        if addr < 0x2000 {
+            return self.cartridge.borrow_mut().ppu_read(addr);
+        }
+        self.ppu_peek(addr)
+    }
+
+    /// Reads PPU memory without changing mapper state, for debugging tools.
+    pub fn ppu_peek(&self, addr: u16) -> u8 {
         let mut data: u8 = 0;
         let addr = addr & 0x3FFF;
 
         if addr < 0x2000 {
-            return self.cartridge.borrow_mut().ppu_read(addr);
+            return self.cartridge.borrow().ppu_peek(addr);
         }
 
         if addr <= 0x3EFF {
             let addr = addr & 0x0FFF;
-            if self.cartridge.borrow_mut().is_vertical_mirror() {
+            if self.cartridge.borrow().is_vertical_mirror() {
                 if addr <= 0x03FF || addr >= 0x0800 && addr <= 0x0BFF {
                     data = self.name_table[0][(addr & 0x03FF) as usize];
                 } else {
@@ -450,19 +539,172 @@ impl Ppu {
     }
 
     pub fn write_oam(&mut self, addr: u8, data: u8) {
-        let index = (addr / 4) as usize;
-        match addr % 4 {
-            0 => {
-                self.oam_mem[index].y = data;
+        self.oam_mem[(addr / 4) as usize].set_byte(addr % 4, data);
+    }
+
+    pub fn read_oam(&self, addr: u8) -> u8 {
+        self.oam_mem[(addr / 4) as usize].get_byte(addr % 4)
+    }
+
+    /// Write to OAMDATA ($2004), also used by OAM DMA.
+    pub fn write_oam_data(&mut self, data: u8) {
+        if self.is_rendering() {
+            // No write during rendering, only a glitchy increment of the high 6 bits
+            self.oam_addr = self.oam_addr.wrapping_add(4);
+        } else {
+            self.write_oam(self.oam_addr, data);
+            self.oam_addr = self.oam_addr.wrapping_add(1);
+        }
+    }
+
+    fn is_rendering(&self) -> bool {
+        (self.mask.show_background || self.mask.show_sprites)
+            && self.scanline >= -1
+            && self.scanline < 240
+    }
+
+    fn sprite_height(&self) -> i16 {
+        if self.ctrl.is_wide_sprite {
+            16
+        } else {
+            8
+        }
+    }
+
+    fn sprite_in_range(&self, y: u8) -> bool {
+        let diff = self.scanline - y as i16;
+        diff >= 0 && diff < self.sprite_height()
+    }
+
+    // Dots 1-64: secondary OAM is filled with $FF, one byte every 2 dots
+    fn clear_secondary_oam(&mut self) {
+        self.oam_latch = 0xFF;
+        if self.cycle % 2 == 0 {
+            self.secondary_oam[((self.cycle - 1) / 2) as usize] = 0xFF;
+        }
+    }
+
+    // Dots 65-256: odd dots read primary OAM, even dots write secondary OAM
+    fn evaluate_sprites(&mut self) {
+        if self.cycle == 65 {
+            self.sprite_eval = SpriteEvalState {
+                n: self.oam_addr >> 2,
+                m: self.oam_addr & 0x03,
+                ..SpriteEvalState::default()
+            };
+        }
+
+        let mut eval = self.sprite_eval;
+        if self.cycle % 2 == 1 {
+            self.oam_latch = self.oam_mem[eval.n as usize].get_byte(eval.m);
+            return;
+        }
+
+        let data = self.oam_latch;
+        if eval.done {
+            // Copy attempts fail, only n keeps being incremented
+            eval.n = (eval.n + 1) & 0x3F;
+        } else if eval.found < 8 {
+            self.secondary_oam[eval.secondary_addr as usize] = data;
+            if eval.m == 0 && !self.sprite_in_range(data) {
+                eval.n = (eval.n + 1) & 0x3F;
+                eval.done = eval.n == 0;
+            } else {
+                if eval.m == 0 && self.cycle == 66 {
+                    eval.sprite_zero = true;
+                }
+                eval.secondary_addr += 1;
+                eval.m = (eval.m + 1) & 0x03;
+                if eval.m == 0 {
+                    eval.found += 1;
+                    eval.n = (eval.n + 1) & 0x3F;
+                    eval.done = eval.n == 0;
+                }
             }
-            1 => {
-                self.oam_mem[index].id = data;
+        } else {
+            // Secondary OAM is full: reads from it instead of writing
+            self.oam_latch = self.secondary_oam[0];
+            if self.sprite_in_range(data) {
+                self.status.sprite_overflow = true;
+                eval.done = true;
+            } else {
+                // Hardware bug: m is incremented together with n, so the following
+                // sprites get their tile, attribute or X byte checked as Y
+                eval.n = (eval.n + 1) & 0x3F;
+                eval.m = (eval.m + 1) & 0x03;
+                eval.done = eval.n == 0;
             }
-            2 => {
-                self.oam_mem[index].attr = data;
+        }
+        self.sprite_eval = eval;
+    }
+
+    fn sprite_pattern_addr(&self, sprite: &ObjectAttributeEntry) -> u16 {
+        let height = self.sprite_height() as u16;
+        let mut row = (self.scanline - sprite.y as i16) as u16 & (height - 1);
+        if sprite.attr & 0x80 != 0 {
+            row = height - 1 - row;
+        }
+
+        if !self.ctrl.is_wide_sprite {
+            ((self.ctrl.pattern_sprite as u16) << 12) | ((sprite.id as u16) << 4) | row
+        } else {
+            let tile = ((sprite.id as u16) & 0xFE) + (row >> 3);
+            (((sprite.id as u16) & 0x01) << 12) | (tile << 4) | (row & 0x07)
+        }
+    }
+
+    // Dots 257-320: 8 dots per sprite slot, patterns are fetched on the last 4
+    fn fetch_sprites(&mut self) {
+        self.oam_addr = 0;
+
+        let slot = ((self.cycle - 257) / 8) as usize;
+        match (self.cycle - 257) % 8 {
+            0 => {
+                if slot == 0 {
+                    self.sprite_state.count = if self.scanline >= 0 {
+                        self.sprite_eval.found
+                    } else {
+                        0
+                    };
+                    self.sprite_zero_hit_possible = self.sprite_eval.sprite_zero;
+                }
+
+                let mut sprite = ObjectAttributeEntry::default();
+                for i in 0..4 {
+                    sprite.set_byte(i, self.secondary_oam[slot * 4 + i as usize]);
+                }
+                // Unused slots fetch tile $FF and stay transparent
+                if slot >= self.sprite_state.count as usize {
+                    sprite = ObjectAttributeEntry {
+                        y: 0xFF,
+                        id: 0xFF,
+                        attr: 0xFF,
+                        x: 0xFF,
+                    };
+                }
+                self.sprite_state.scanline[slot] = sprite;
+                self.oam_latch = sprite.y;
+            }
+            4 => {
+                let addr = self.sprite_pattern_addr(&self.sprite_state.scanline[slot]);
+                let mut bits = self.ppu_read(addr);
+                if self.sprite_state.scanline[slot].attr & 0x40 != 0 {
+                    bits = flipbyte(bits);
+                }
+                self.sprite_state.shifter_pattern_lo[slot] = bits;
             }
-            3 => {
-                self.oam_mem[index].x = data;
+            6 => {
+                let addr = self.sprite_pattern_addr(&self.sprite_state.scanline[slot]) + 8;
+                let mut bits = self.ppu_read(addr);
+                if self.sprite_state.scanline[slot].attr & 0x40 != 0 {
+                    bits = flipbyte(bits);
+                }
+                self.sprite_state.shifter_pattern_hi[slot] = bits;
+
+                if slot >= self.sprite_state.count as usize {
+                    self.sprite_state.shifter_pattern_lo[slot] = 0;
+                    self.sprite_state.shifter_pattern_hi[slot] = 0;
+                }
             }
             _ => {}
         }
@@ -533,14 +775,29 @@ impl Ppu {
             if self.scanline == 0
                 && self.cycle == 0
                 && self.odd_frame
+                && self.region.has_odd_frame_skip()
                 && (self.mask.show_background || self.mask.show_sprites)
             {
                 // odd frame cycle skip
                 self.cycle = 1;
+                // Dots on this line come 8 samples earlier
+                self.signal_phase = (self.signal_phase + 4) % 12;
+            }
+
+            if self.scanline == 0 && self.cycle == 1 {
+                self.screen.phase = self.signal_phase;
             }
 
             if self.scanline == -1 && self.cycle == 1 {
                 // New frame
+                if (self.mask.show_background || self.mask.show_sprites) && self.oam_addr >= 8 {
+                    // OAMADDR hardware bug: 8 bytes at OAMADDR & $F8 are copied over the first 8
+                    let src = self.oam_addr & 0xF8;
+                    for i in 0..8 {
+                        let data = self.read_oam(src + i);
+                        self.write_oam(i, data);
+                    }
+                }
                 self.status.vertical_blank = false;
                 self.status.sprite_overflow = false;
                 self.status.sprite_zero_hit = false;
@@ -616,100 +873,26 @@ impl Ppu {
             }
 
             // Sprite Rendering
-            if self.cycle == 257 && self.scanline >= 0 {
-                self.sprite_state.scanline = [ObjectAttributeEntry::default(); 8];
-                self.sprite_state.count = 0;
-
-                for i in 0..8 {
-                    self.sprite_state.shifter_pattern_lo[i] = 0;
-                    self.sprite_state.shifter_pattern_hi[i] = 0;
+            if self.mask.show_background || self.mask.show_sprites {
+                if self.scanline >= 0 && self.cycle >= 1 && self.cycle <= 64 {
+                    self.clear_secondary_oam();
                 }
 
-                let mut oam_index: u8 = 0;
-                self.sprite_zero_hit_possible = false;
-
-                while oam_index < 64 && self.sprite_state.count < 9 {
-                    let diff = (self.scanline as i16) - (self.oam_mem[oam_index as usize].y as i16);
-
-                    let sprite_size = if self.ctrl.is_wide_sprite { 16 } else { 8 };
-                    if diff >= 0 && diff < sprite_size && self.sprite_state.count < 8 {
-                        if self.sprite_state.count < 8 {
-                            if oam_index == 0 {
-                                self.sprite_zero_hit_possible = true;
-                            }
-
-                            self.sprite_state.scanline[self.sprite_state.count as usize] =
-                                self.oam_mem[oam_index as usize];
-                        }
-                        self.sprite_state.count += 1;
-                    }
-                    oam_index += 1;
+                if self.scanline >= 0 && self.cycle >= 65 && self.cycle <= 256 {
+                    self.evaluate_sprites();
                 }
 
-                self.status.sprite_overflow = self.sprite_state.count >= 8;
-            }
-
-            if self.cycle == 340 {
-                for i in 0..self.sprite_state.count as usize {
-                    let mut sprite_pattern_addr_lo: u16;
-                    let sprite_pattern_addr_hi: u16;
-
-                    let scan_id = self.sprite_state.scanline[i].id as u16;
-                    let scan_y = self.scanline - self.sprite_state.scanline[i].y as i16;
-
-                    if !self.ctrl.is_wide_sprite {
-                        // 8x8 sprite
-                        if self.sprite_state.scanline[i].attr & 0x80 == 0 {
-                            sprite_pattern_addr_lo = ((self.ctrl.pattern_sprite as u16) << 12)
-                                | (scan_id << 4)
-                                | (scan_y as u16);
-                        } else {
-                            sprite_pattern_addr_lo = ((self.ctrl.pattern_sprite as u16) << 12)
-                                | (scan_id << 4)
-                                | ((7 - scan_y) as u16);
-                        }
-                    } else {
-                        // 8x16 sprite
-                        sprite_pattern_addr_lo = ((scan_id & 0x01) << 12) | ((scan_id & 0xFE) << 4);
-
-                        if self.sprite_state.scanline[i].attr & 0x80 != 0 {
-                            if scan_y < 8 {
-                                sprite_pattern_addr_lo |= (scan_y as u16) & 0x07;
-                            } else {
-                                sprite_pattern_addr_lo |= (scan_y as u16) & 0x07;
-                            }
-                        } else {
-                            if scan_y < 8 {
-                                sprite_pattern_addr_lo |= (7 - scan_y) as u16 & 0x07;
-                            } else {
-                                sprite_pattern_addr_lo |= (7 - scan_y) as u16 & 0x07;
-                            }
-                        }
-                    }
-
-                    sprite_pattern_addr_hi = sprite_pattern_addr_lo + 8;
-
-                    let mut sprite_pattern_bits_lo = self.ppu_read(sprite_pattern_addr_lo);
-                    let mut sprite_pattern_bits_hi = self.ppu_read(sprite_pattern_addr_hi);
-
-                    if self.sprite_state.scanline[i].attr & 0x40 != 0 {
-                        sprite_pattern_bits_lo = flipbyte(sprite_pattern_bits_lo);
-                        sprite_pattern_bits_hi = flipbyte(sprite_pattern_bits_hi);
-                    }
-
-                    self.sprite_state.shifter_pattern_lo[i] = sprite_pattern_bits_lo;
-                    self.sprite_state.shifter_pattern_hi[i] = sprite_pattern_bits_hi;
+                if self.cycle >= 257 && self.cycle <= 320 {
+                    self.fetch_sprites();
                 }
             }
         }
 
-        if self.scanline >= 241 && self.scanline < 261 {
-            if self.scanline == 241 && self.cycle == 1 {
-                self.status.vertical_blank = true;
+        if self.scanline == self.region.vblank_scanline() && self.cycle == 1 {
+            self.status.vertical_blank = true;
 
-                if self.ctrl.generate_nmi {
-                    self.nmi = true;
-                }
+            if self.ctrl.generate_nmi {
+                self.nmi = true;
             }
         }
 
@@ -804,7 +987,7 @@ impl Ppu {
         }
 
         // Set pixel
-        let c = self.get_color_from_pal(palette, pixel);
+        let c = self.get_color_index(palette, pixel);
         if self.cycle > 0 && self.cycle <= 256 && self.scanline >= 0 && self.scanline < 240 {
             self.screen
                 .set_pixel((self.cycle - 1) as usize, self.scanline as usize, c);
@@ -816,15 +999,120 @@ impl Ppu {
         if self.cycle >= 341 {
             self.cycle = 0;
             self.scanline += 1;
-            if self.scanline >= 261 {
+            // 341 dots of 8 samples each
+            self.signal_phase = (self.signal_phase + 4) % 12;
+            if self.scanline > self.region.last_scanline() {
                 self.scanline = -1;
+                self.screen.update_rgb(&self.palette);
                 self.screen.complete = true;
                 self.odd_frame = !self.odd_frame;
+                self.frame += 1;
             }
         }
     }
 
-    pub fn get_color_from_pal(&mut self, palette: u8, pixel: u8) -> u32 {
-        return PALETTE[(self.ppu_read(0x3F00 + ((palette << 2) + pixel) as u16) & 0x3F) as usize];
+    /// 9-bit color of a palette entry: 6-bit color index and emphasis in bits 6-8.
+    pub fn get_color_index(&self, palette: u8, pixel: u8) -> u16 {
+        let mut color = self.ppu_peek(0x3F00 + ((palette << 2) + pixel) as u16) as u16;
+        if self.mask.grayscale {
+            color &= 0x30;
+        }
+        color & 0x3F | self.emphasis() << 6
+    }
+
+    /// Emphasis bits from PPUMASK as red, green, blue (bits 0-2).
+    pub fn emphasis(&self) -> u16 {
+        let (red, green) = if self.region == Region::Ntsc {
+            (self.mask.emphasize_red, self.mask.emphasize_green)
+        } else {
+            // PAL and Dendy PPUs have red and green emphasis bits swapped
+            (self.mask.emphasize_green, self.mask.emphasize_red)
+        };
+        red as u16 | (green as u16) << 1 | (self.mask.emphasize_blue as u16) << 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Evaluates sprites on scanline 10 with the given sprite Y (or ID) values
+    fn eval_scanline(sprites: &[(usize, u8, u8)]) -> Ppu {
+        let mut ppu = Ppu::new(Rc::new(RefCell::new(Cartridge::new())));
+        for i in 0..256 {
+            ppu.write_oam(i as u8, 0xF0);
+        }
+        for &(n, y, id) in sprites {
+            ppu.oam_mem[n].y = y;
+            ppu.oam_mem[n].id = id;
+        }
+        ppu.mask.show_sprites = true;
+        ppu.scanline = 10;
+        for _ in 0..=320 {
+            ppu.clock();
+        }
+        ppu
+    }
+
+    #[test]
+    fn sprite_overflow() {
+        let sprites: Vec<_> = (0..9).map(|n| (n, 5, 0)).collect();
+        let ppu = eval_scanline(&sprites);
+        assert!(ppu.status.sprite_overflow);
+        assert_eq!(ppu.sprite_state.count, 8);
+        assert!(ppu.sprite_zero_hit_possible);
+    }
+
+    #[test]
+    fn sprite_overflow_hardware_bug() {
+        // Sprite 9 is not on the scanline but its tile ID is checked as Y
+        let mut sprites: Vec<_> = (0..8).map(|n| (n, 5, 0)).collect();
+        sprites.push((9, 0xF0, 5));
+        assert!(eval_scanline(&sprites).status.sprite_overflow);
+
+        // Sprite 9 is on the scanline but its tile ID is checked instead
+        let mut sprites: Vec<_> = (1..9).map(|n| (n, 5, 0)).collect();
+        sprites.push((10, 5, 0xF0));
+        let ppu = eval_scanline(&sprites);
+        assert!(!ppu.status.sprite_overflow);
+        assert!(!ppu.sprite_zero_hit_possible);
+    }
+
+    // Counts dots in one frame and the scanline on which vblank is set
+    fn frame_timing(region: Region) -> (usize, i16) {
+        let mut ppu = Ppu::new(Rc::new(RefCell::new(Cartridge::new())));
+        ppu.region = region;
+        ppu.scanline = -1;
+        ppu.screen.complete = false;
+        let mut dots = 0;
+        let mut vblank_scanline = 0;
+        while !ppu.screen.complete {
+            ppu.clock();
+            dots += 1;
+            if ppu.status.vertical_blank && vblank_scanline == 0 {
+                vblank_scanline = ppu.scanline;
+            }
+        }
+        (dots, vblank_scanline)
+    }
+
+    #[test]
+    fn region_frame_timing() {
+        assert_eq!(frame_timing(Region::Ntsc), (341 * 262, 241));
+        assert_eq!(frame_timing(Region::Pal), (341 * 312, 241));
+        assert_eq!(frame_timing(Region::Dendy), (341 * 312, 291));
+    }
+
+    #[test]
+    fn screen_rgb_conversion() {
+        let palette = Palette::default();
+        let mut screen = Screen::default();
+        screen.set_pixel(0, 0, 0x16);
+        screen.set_pixel(1, 0, 4 << 6 | 0x30);
+        screen.update_rgb(&palette);
+        assert_eq!(screen.get_pixel(1, 0), 0x130);
+        assert_eq!(screen.rgb()[0], palette.get_color(0x16));
+        assert_eq!(screen.rgb()[1], palette.get_color(0x130));
+        assert_ne!(screen.rgb()[1], palette.get_color(0x30));
     }
 }
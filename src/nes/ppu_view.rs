@@ -0,0 +1,302 @@
+use super::ppu::Ppu;
+
+/// Four nametables side by side, 2x2 screens.
+pub const NAMETABLES_SIZE: (usize, usize) = (512, 480);
+const TILES_WIDE: usize = NAMETABLES_SIZE.0 / 8;
+
+/// Row of 8 pixels (0-3) of the tile in pattern table at `base`.
+pub fn tile_row(ppu: &Ppu, base: u16, tile: u8, row: u16) -> [u8; 8] {
+    let addr = base + tile as u16 * 16 + row;
+    let lsb = ppu.ppu_peek(addr);
+    let msb = ppu.ppu_peek(addr + 8);
+    let mut pixels = [0; 8];
+    for (col, pixel) in pixels.iter_mut().enumerate() {
+        let bit = 7 - col;
+        *pixel = (msb >> bit & 0x01) << 1 | (lsb >> bit & 0x01);
+    }
+    pixels
+}
+
+/// Color index of the pixel with the given palette, 0 pixels show the backdrop.
+pub fn pixel_color(ppu: &Ppu, palette: u8, pixel: u8) -> u16 {
+    if pixel == 0 {
+        ppu.get_color_index(0, 0)
+    } else {
+        ppu.get_color_index(palette, pixel)
+    }
+}
+
+/// One pattern table, 16x16 tiles.
+pub const PATTERN_TABLE_SIZE: (usize, usize) = (128, 128);
+
+/// Color indices of the pattern table at `base` drawn with `palette` (0-7).
+pub fn pattern_table(ppu: &Ppu, base: u16, palette: u8) -> Vec<u16> {
+    let (width, height) = PATTERN_TABLE_SIZE;
+    let mut pixels = vec![0; width * height];
+    for tile in 0..=255u8 {
+        let (tile_x, tile_y) = (tile as usize % 16, tile as usize / 16);
+        for row in 0..8 {
+            let start = (tile_y * 8 + row) * width + tile_x * 8;
+            for (col, &pixel) in tile_row(ppu, base, tile, row as u16).iter().enumerate() {
+                pixels[start + col] = pixel_color(ppu, palette, pixel);
+            }
+        }
+    }
+    pixels
+}
+
+/// Nametable entry of a background tile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NametableTile {
+    /// PPU address of the entry.
+    pub addr: u16,
+    pub tile: u8,
+    /// PPU address and value of the attribute byte.
+    pub attr_addr: u16,
+    pub attr: u8,
+    /// Palette selected by the attribute byte for this tile.
+    pub palette: u8,
+}
+
+/// The four logical nametables as they are mapped with the current mirroring.
+pub struct NametableView {
+    /// Color indices, `NAMETABLES_SIZE` pixels.
+    pub pixels: Vec<u16>,
+    tiles: Vec<NametableTile>,
+    /// Top left corner of the screen from the temporary VRAM address and fine X.
+    pub scroll: (usize, usize),
+    /// Scanline the view was captured at.
+    pub scanline: i16,
+}
+
+impl NametableView {
+    pub fn capture(ppu: &Ppu) -> Self {
+        let (width, height) = NAMETABLES_SIZE;
+        let base = if ppu.ctrl.pattern_background {
+            0x1000
+        } else {
+            0x0000
+        };
+        let mut pixels = vec![0; width * height];
+        let mut tiles = Vec::with_capacity(TILES_WIDE * height / 8);
+        for tile_y in 0..height / 8 {
+            for tile_x in 0..TILES_WIDE {
+                // Tables 1 and 3 are on the right, 2 and 3 at the bottom
+                let table = (tile_y / 30) * 2 + tile_x / 32;
+                let (x, y) = (tile_x % 32, tile_y % 30);
+                let table_addr = 0x2000 + table as u16 * 0x400;
+                let addr = table_addr + (y * 32 + x) as u16;
+                let attr_addr = table_addr + 0x3C0 + (y / 4 * 8 + x / 4) as u16;
+                let attr = ppu.ppu_peek(attr_addr);
+                // Each attribute byte covers 4x4 tiles, 2 bits per 2x2 tiles
+                let palette = attr >> ((y & 0x02) * 2 + (x & 0x02)) & 0x03;
+                let tile = ppu.ppu_peek(addr);
+                tiles.push(NametableTile {
+                    addr,
+                    tile,
+                    attr_addr,
+                    attr,
+                    palette,
+                });
+
+                for row in 0..8 {
+                    let line = tile_row(ppu, base, tile, row as u16);
+                    let start = (tile_y * 8 + row) * width + tile_x * 8;
+                    for (col, &pixel) in line.iter().enumerate() {
+                        pixels[start + col] = pixel_color(ppu, palette, pixel);
+                    }
+                }
+            }
+        }
+
+        let t = &ppu.tram_addr;
+        let scroll = (
+            t.nametable_x as usize * 256 + t.coarse_x as usize * 8 + ppu.fine_x as usize,
+            t.nametable_y as usize * 240 + t.coarse_y as usize * 8 + t.fine_y as usize,
+        );
+        NametableView {
+            pixels,
+            tiles,
+            scroll,
+            scanline: ppu.scanline,
+        }
+    }
+
+    /// Tile at pixel `x`, `y` of the view.
+    pub fn tile_at(&self, x: usize, y: usize) -> Option<&NametableTile> {
+        if x >= NAMETABLES_SIZE.0 || y >= NAMETABLES_SIZE.1 {
+            return None;
+        }
+        self.tiles.get(y / 8 * TILES_WIDE + x / 8)
+    }
+}
+
+/// OAM entry with the details of how it's drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpriteInfo {
+    pub index: usize,
+    pub x: u8,
+    /// OAM Y, the sprite is drawn from the next scanline.
+    pub y: u8,
+    pub tile: u8,
+    pub attr: u8,
+    /// Palette 4-7.
+    pub palette: u8,
+    pub flip_h: bool,
+    pub flip_v: bool,
+    pub behind_background: bool,
+    /// 8 or 16.
+    pub height: usize,
+    /// Pattern table address of the top tile, 8x16 sprites use the next tile too.
+    pub pattern_addr: u16,
+}
+
+impl SpriteInfo {
+    /// All 64 sprites with the current sprite size and pattern table.
+    pub fn all(ppu: &Ppu) -> Vec<SpriteInfo> {
+        ppu.oam_mem
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                let (height, pattern_addr) = if ppu.ctrl.is_wide_sprite {
+                    // Bit 0 selects the pattern table
+                    let table = (entry.id & 0x01) as u16 * 0x1000;
+                    (16, table + (entry.id & 0xFE) as u16 * 16)
+                } else {
+                    let table = if ppu.ctrl.pattern_sprite { 0x1000 } else { 0 };
+                    (8, table + entry.id as u16 * 16)
+                };
+                SpriteInfo {
+                    index,
+                    x: entry.x,
+                    y: entry.y,
+                    tile: entry.id,
+                    attr: entry.attr,
+                    palette: 4 + (entry.attr & 0x03),
+                    flip_h: entry.attr & 0x40 != 0,
+                    flip_v: entry.attr & 0x80 != 0,
+                    behind_background: entry.attr & 0x20 != 0,
+                    height,
+                    pattern_addr,
+                }
+            })
+            .collect()
+    }
+
+    /// Pattern table addresses of the sprite tiles.
+    pub fn tile_addrs(&self) -> Vec<u16> {
+        (0..self.height as u16 / 8)
+            .map(|i| self.pattern_addr + i * 16)
+            .collect()
+    }
+
+    /// Color indices of 8 x `height` pixels, transparent ones show the backdrop.
+    pub fn pixels(&self, ppu: &Ppu) -> Vec<u16> {
+        let mut pixels = Vec::with_capacity(8 * self.height);
+        for row in 0..self.height {
+            let row = if self.flip_v {
+                self.height - 1 - row
+            } else {
+                row
+            };
+            let tile_addr = self.pattern_addr + (row / 8) as u16 * 16;
+            let mut line = tile_row(ppu, tile_addr, 0, (row % 8) as u16);
+            if self.flip_h {
+                line.reverse();
+            }
+            pixels.extend(
+                line.iter()
+                    .map(|&pixel| pixel_color(ppu, self.palette, pixel)),
+            );
+        }
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cartridge::Cartridge;
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn nametables() {
+        let mut ppu = Ppu::new(Rc::new(RefCell::new(Cartridge::new())));
+        // Horizontal mirroring: tables 0 and 1 are the first 1K
+        ppu.name_table[0][33] = 0x42;
+        ppu.name_table[0][0x3C0] = 0b11_10_01_00;
+        ppu.name_table[1][0] = 0x24;
+        ppu.pal_table[0] = 0x0F;
+        ppu.tram_addr.set_data(0x0C22);
+        ppu.fine_x = 3;
+
+        let view = NametableView::capture(&ppu);
+        let tile = view.tile_at(8, 8).unwrap();
+        assert_eq!((tile.addr, tile.tile), (0x2021, 0x42));
+        assert_eq!((tile.attr_addr, tile.palette), (0x23C0, 0));
+        let tile = view.tile_at(256 + 8, 8).unwrap();
+        assert_eq!((tile.addr, tile.tile), (0x2421, 0x42));
+        assert_eq!(view.tile_at(16, 0).unwrap().palette, 1);
+        assert_eq!(view.tile_at(0, 16).unwrap().palette, 2);
+        assert_eq!(view.tile_at(24, 24).unwrap().palette, 3);
+        assert_eq!(view.tile_at(0, 240).unwrap().tile, 0x24);
+        assert_eq!(view.tile_at(512, 0), None);
+
+        // Empty CHR shows backdrop
+        assert!(view.pixels.iter().all(|&color| color == 0x0F));
+        assert_eq!(view.scroll, (256 + 2 * 8 + 3, 240 + 8));
+    }
+
+    #[test]
+    fn pattern_tables() {
+        let mut rom = vec![0; 16 + 0x4000 + 0x2000];
+        rom[0..6].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1]);
+        // Tile 1, row 0: pixel 1 at the left, pixel 3 at the right
+        rom[16 + 0x4000 + 16] = 0x81;
+        rom[16 + 0x4000 + 24] = 0x01;
+        let romfile = std::env::temp_dir().join("nes-rust-pattern-tables.nes");
+        std::fs::write(&romfile, &rom).unwrap();
+        let mut cartridge = Cartridge::new();
+        cartridge.load_from_file(&romfile).unwrap();
+        let _ = std::fs::remove_file(&romfile);
+        let mut ppu = Ppu::new(Rc::new(RefCell::new(cartridge)));
+        ppu.pal_table[0] = 0x0F;
+        ppu.pal_table[9] = 0x16;
+        ppu.pal_table[11] = 0x30;
+
+        let pixels = pattern_table(&ppu, 0x0000, 2);
+        assert_eq!(pixels.len(), 128 * 128);
+        assert_eq!(
+            &pixels[8..16],
+            &[0x16, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x30]
+        );
+        assert_eq!(pixels[128 + 8], 0x0F);
+        assert!(pattern_table(&ppu, 0x1000, 2)
+            .iter()
+            .all(|&color| color == 0x0F));
+    }
+
+    #[test]
+    fn sprites() {
+        let mut ppu = Ppu::new(Rc::new(RefCell::new(Cartridge::new())));
+        ppu.pal_table[0] = 0x0F;
+        for (i, &byte) in [0x20, 0x43, 0xE1, 0x80].iter().enumerate() {
+            ppu.write_oam(8 + i as u8, byte);
+        }
+        ppu.ctrl.pattern_sprite = true;
+
+        let sprite = SpriteInfo::all(&ppu)[2];
+        assert_eq!((sprite.x, sprite.y, sprite.tile), (0x80, 0x20, 0x43));
+        assert_eq!(sprite.palette, 5);
+        assert!(sprite.flip_h && sprite.flip_v && sprite.behind_background);
+        assert_eq!(sprite.tile_addrs(), vec![0x1430]);
+        assert_eq!(sprite.pixels(&ppu), vec![0x0F; 64]);
+
+        // 8x16 sprites take the table from the tile number
+        ppu.ctrl.is_wide_sprite = true;
+        let sprite = SpriteInfo::all(&ppu)[2];
+        assert_eq!(sprite.height, 16);
+        assert_eq!(sprite.tile_addrs(), vec![0x1420, 0x1430]);
+    }
+}
@@ -20,6 +20,10 @@ impl CpuBusDevice for Ram {
     fn cpu_read(&mut self, addr: u16) -> u8 {
         return self.bytes[(addr & (RAM_SIZE - 1)) as usize];
     }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.bytes[(addr & (RAM_SIZE - 1)) as usize]
+    }
 }
 
 impl Ram {
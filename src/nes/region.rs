@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Console region, defines CPU/PPU timing and the frame rate.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    /// Famiclone with PAL frame rate but NTSC-like CPU:PPU ratio and a late vblank.
+    Dendy,
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Region::Ntsc
+    }
+}
+
+impl Region {
+    pub const ALL: [Region; 3] = [Region::Ntsc, Region::Pal, Region::Dendy];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Region::Ntsc => "NTSC",
+            Region::Pal => "PAL",
+            Region::Dendy => "Dendy",
+        }
+    }
+
+    /// Region by its name, case insensitive.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Region::ALL
+            .iter()
+            .find(|region| region.name().eq_ignore_ascii_case(name))
+            .copied()
+    }
+
+    /// Region from NES 2.0 header byte 0Ch (CPU/PPU timing).
+    /// Multi-region games (2) run as NTSC.
+    pub fn from_nes2_timing(timing: u8) -> Self {
+        match timing & 0x03 {
+            1 => Region::Pal,
+            3 => Region::Dendy,
+            _ => Region::Ntsc,
+        }
+    }
+
+    /// Guesses region from GoodNES/No-Intro style tags in the ROM file name.
+    pub fn from_file_name(romfile: &PathBuf) -> Option<Self> {
+        let name = romfile.file_name()?.to_str()?.to_lowercase();
+        const PAL_TAGS: [&str; 6] = ["(e)", "(europe)", "(pal)", "(g)", "(f)", "(a)"];
+        if PAL_TAGS.iter().any(|tag| name.contains(tag)) {
+            return Some(Region::Pal);
+        }
+        if name.contains("(dendy)") {
+            return Some(Region::Dendy);
+        }
+        None
+    }
+
+    /// PPU dots per CPU cycle as a fraction (numerator, denominator).
+    pub fn ppu_dots_per_cpu_cycle(&self) -> (u32, u32) {
+        match self {
+            Region::Pal => (16, 5),
+            Region::Ntsc | Region::Dendy => (3, 1),
+        }
+    }
+
+    pub fn frame_rate(&self) -> f32 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+
+    /// Last scanline of a frame, the pre-render line is numbered -1 and follows it.
+    pub fn last_scanline(&self) -> i16 {
+        match self {
+            Region::Ntsc => 260,
+            Region::Pal | Region::Dendy => 310,
+        }
+    }
+
+    /// Scanline on which vblank starts and NMI fires.
+    pub fn vblank_scanline(&self) -> i16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            // Dendy has 51 idle post-render lines, vblank lasts 20 lines like on NTSC
+            Region::Dendy => 291,
+        }
+    }
+
+    /// Only NTSC PPU skips a dot on odd frames when rendering is enabled.
+    pub fn has_odd_frame_skip(&self) -> bool {
+        *self == Region::Ntsc
+    }
+}
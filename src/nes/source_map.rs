@@ -0,0 +1,190 @@
+use super::cartridge::PrgMapping;
+use super::symbols::{DbgRecord, DbgSegment, Location};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// ld65 line type of macro bodies, their code is shown at the line using the macro
+const LINE_TYPE_MACRO: usize = 2;
+
+/// Line in one of the `SourceMap` files, numbered from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceLine {
+    pub file: usize,
+    pub line: usize,
+}
+
+struct SourceFile {
+    name: String,
+    // Empty when the file can't be read
+    lines: Vec<String>,
+}
+
+/// Addresses of assembly source lines from ld65 debug info.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+    // Line of every code byte
+    prg_lines: HashMap<usize, SourceLine>,
+    cpu_lines: HashMap<u16, SourceLine>,
+    // Where code of the line starts, a line can be assembled several times
+    locations: HashMap<SourceLine, Vec<Location>>,
+}
+
+impl SourceMap {
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    /// Adds lines from records of a .dbg file, source files are looked up relative to `dir`.
+    pub fn add_dbg(
+        &mut self,
+        records: &[DbgRecord],
+        segments: &HashMap<usize, DbgSegment>,
+        dir: &Path,
+    ) {
+        let by_kind = |kind: &'static str| records.iter().filter(move |r| r.kind == kind);
+
+        let mut files = HashMap::new();
+        for record in by_kind("file") {
+            if let (Some(id), Some(name)) = (record.number("id"), record.get("name")) {
+                files.insert(id, self.files.len());
+                let lines = fs::read_to_string(dir.join(name))
+                    .map(|text| text.lines().map(str::to_string).collect())
+                    .unwrap_or_default();
+                self.files.push(SourceFile {
+                    name: name.to_string(),
+                    lines,
+                });
+            }
+        }
+
+        // Span start is relative to its segment
+        let spans: HashMap<usize, (&DbgSegment, usize, usize)> = by_kind("span")
+            .filter_map(|record| {
+                let segment = segments.get(&record.number("seg")?)?;
+                let start = segment.start + record.number("start")?;
+                Some((
+                    record.number("id")?,
+                    (segment, start, record.number("size")?),
+                ))
+            })
+            .collect();
+
+        let mut line_spans = vec![];
+        for record in by_kind("line") {
+            if record.number("type") == Some(LINE_TYPE_MACRO) {
+                continue;
+            }
+            let line = match (record.number("file"), record.number("line")) {
+                (Some(file), Some(line)) => match files.get(&file) {
+                    Some(&file) => SourceLine { file, line },
+                    None => continue,
+                },
+                _ => continue,
+            };
+            let ids = record.get("span").unwrap_or("").split('+');
+            for span in ids.filter_map(|id| spans.get(&id.parse().ok()?)) {
+                line_spans.push((line, *span));
+            }
+        }
+
+        // Bytes of a line can be in a span of an enclosing line too, smaller spans win
+        line_spans.sort_by_key(|(_, (_, _, size))| std::cmp::Reverse(*size));
+        for (line, (segment, start, size)) in line_spans {
+            if size == 0 {
+                continue;
+            }
+            for addr in start..start + size {
+                match segment.location(addr) {
+                    Some(Location::Prg { offset, .. }) => self.prg_lines.insert(offset, line),
+                    Some(Location::Cpu(addr)) => self.cpu_lines.insert(addr, line),
+                    None => None,
+                };
+            }
+            if let Some(location) = segment.location(start) {
+                self.locations.entry(line).or_default().push(location);
+            }
+        }
+    }
+
+    /// Source line of code at CPU address `addr`.
+    pub fn line_at(&self, addr: u16, mapping: &PrgMapping) -> Option<SourceLine> {
+        match mapping.offset(addr) {
+            Some(offset) => self.prg_lines.get(&offset).copied(),
+            None => self.cpu_lines.get(&addr).copied(),
+        }
+    }
+
+    pub fn file_name(&self, line: SourceLine) -> &str {
+        &self.files[line.file].name
+    }
+
+    /// Text of the line, `None` if the file wasn't found.
+    pub fn text(&self, line: SourceLine) -> Option<&str> {
+        let lines = &self.files[line.file].lines;
+        lines.get(line.line.checked_sub(1)?).map(String::as_str)
+    }
+
+    /// Finds line by `file:line`, the file name can be given without directories.
+    pub fn find(&self, location: &str) -> Option<SourceLine> {
+        let mut parts = location.rsplitn(2, ':');
+        let line = parts.next()?.trim().parse().ok()?;
+        let name = parts.next()?.trim().replace('\\', "/");
+        let file = self.files.iter().position(|file| {
+            let file_name = file.name.replace('\\', "/");
+            file_name == name || file_name.ends_with(&format!("/{}", name))
+        })?;
+        Some(SourceLine { file, line })
+    }
+
+    /// CPU address of the line's code in currently mapped PRG banks.
+    pub fn addr(&self, line: SourceLine, mapping: &PrgMapping) -> Option<u16> {
+        self.locations
+            .get(&line)?
+            .iter()
+            .find_map(|location| match *location {
+                Location::Cpu(addr) => Some(addr),
+                Location::Prg { offset, addr } => mapping.addr(offset, addr),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ld65_lines() {
+        let text = "file\tid=0,name=\"src/main.s\",size=100,mtime=0x5E000000,mod=0\n\
+                    seg\tid=0,name=\"CODE\",start=0x00C000,size=0x0010,ooffs=16\n\
+                    span\tid=0,seg=0,start=0,size=2\n\
+                    span\tid=1,seg=0,start=2,size=3\n\
+                    span\tid=2,seg=0,start=2,size=8\n\
+                    span\tid=3,seg=0,start=5,size=5\n\
+                    line\tid=0,file=0,line=3,span=0\n\
+                    line\tid=1,file=0,line=4,span=1\n\
+                    line\tid=2,file=0,line=5,span=2\n\
+                    line\tid=3,file=0,line=20,type=2,span=3\n";
+        let records: Vec<DbgRecord> = text.lines().filter_map(DbgRecord::parse).collect();
+        let segments = DbgSegment::from_records(&records);
+        let mut source_map = SourceMap::default();
+        source_map.add_dbg(&records, &segments, Path::new("."));
+
+        let mapping = PrgMapping {
+            windows: [0, 0x2000, 0, 0x2000],
+        };
+        let line = |line| SourceLine { file: 0, line };
+        assert_eq!(source_map.line_at(0xC001, &mapping), Some(line(3)));
+        // Line 5 is a macro call, its body is the line 20
+        assert_eq!(source_map.line_at(0xC002, &mapping), Some(line(4)));
+        assert_eq!(source_map.line_at(0xC006, &mapping), Some(line(5)));
+        assert_eq!(source_map.line_at(0xC010, &mapping), None);
+
+        assert_eq!(source_map.find("main.s:4"), Some(line(4)));
+        assert_eq!(source_map.find("other.s:4"), None);
+        assert_eq!(source_map.addr(line(4), &mapping), Some(0xC002));
+        assert_eq!(source_map.file_name(line(4)), "src/main.s");
+        assert_eq!(source_map.text(line(4)), None);
+    }
+}
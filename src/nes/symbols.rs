@@ -0,0 +1,419 @@
+use super::cartridge::{PrgMapping, HEADER_SIZE};
+use super::disasm::Labels;
+use super::source_map::SourceMap;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Label and comment attached to an address, either can be empty.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub comment: String,
+}
+
+/// Where a symbol is: fixed CPU address (RAM, registers) or PRG-ROM offset, which is
+/// visible to CPU only while its bank is mapped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Location {
+    Cpu(u16),
+    /// `addr` is the CPU address the code was linked for, if known.
+    Prg {
+        offset: usize,
+        addr: Option<u16>,
+    },
+}
+
+/// Labels and comments from symbol files: ca65 debug info (.dbg), FCEUX name lists
+/// (.nl) and Mesen label files (.mlb).
+#[derive(Default)]
+pub struct Symbols {
+    cpu: HashMap<u16, Symbol>,
+    prg: HashMap<usize, Symbol>,
+    locations: HashMap<String, Location>,
+    /// Source lines from ca65 debug info.
+    pub source_map: SourceMap,
+}
+
+/// Fields of one ld65 debug info line, like `sym id=0,name="reset",val=0x8000`.
+pub struct DbgRecord {
+    pub kind: String,
+    fields: HashMap<String, String>,
+}
+
+impl DbgRecord {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.trim().splitn(2, char::is_whitespace);
+        let kind = parts.next()?.to_string();
+        let mut fields = HashMap::new();
+
+        // Values are separated by commas, except inside quoted strings
+        let mut quoted = false;
+        let mut field = String::new();
+        for c in parts.next().unwrap_or("").chars().chain(Some(',')) {
+            match c {
+                '"' => quoted = !quoted,
+                ',' if !quoted => {
+                    let mut kv = field.splitn(2, '=');
+                    if let (Some(key), Some(value)) = (kv.next(), kv.next()) {
+                        fields.insert(key.trim().to_string(), value.to_string());
+                    }
+                    field.clear();
+                }
+                _ => field.push(c),
+            }
+        }
+        Some(DbgRecord { kind, fields })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(String::as_str)
+    }
+
+    /// Number field, decimal or `0x` hex.
+    pub fn number(&self, key: &str) -> Option<usize> {
+        let value = self.get(key)?;
+        if value.starts_with("0x") {
+            usize::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+        } else {
+            value.parse().ok()
+        }
+    }
+}
+
+/// ld65 segment, `rom_offset` is `None` for segments not in the ROM file (RAM).
+pub struct DbgSegment {
+    pub start: usize,
+    pub rom_offset: Option<usize>,
+}
+
+impl DbgSegment {
+    /// Segments by id.
+    pub fn from_records(records: &[DbgRecord]) -> HashMap<usize, DbgSegment> {
+        records
+            .iter()
+            .filter(|record| record.kind == "seg")
+            .filter_map(|record| {
+                let segment = DbgSegment {
+                    start: record.number("start")?,
+                    // Offset in the .nes file, which starts with the header
+                    rom_offset: record
+                        .number("ooffs")
+                        .filter(|offset| *offset >= HEADER_SIZE)
+                        .map(|offset| offset - HEADER_SIZE),
+                };
+                Some((record.number("id")?, segment))
+            })
+            .collect()
+    }
+
+    /// Location of CPU address `addr` in the segment, `None` if it's before the segment.
+    pub fn location(&self, addr: usize) -> Option<Location> {
+        let relative = addr.checked_sub(self.start)?;
+        Some(match self.rom_offset {
+            Some(offset) => Location::Prg {
+                offset: offset + relative,
+                addr: Some(addr as u16),
+            },
+            None => Location::Cpu(addr as u16),
+        })
+    }
+}
+
+fn parse_hex(s: &str) -> Option<usize> {
+    usize::from_str_radix(s.trim().trim_start_matches('$'), 16).ok()
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cpu.len() + self.prg.len()
+    }
+
+    pub fn add(&mut self, location: Location, name: &str, comment: &str) {
+        let symbol = match location {
+            Location::Cpu(addr) => self.cpu.entry(addr).or_default(),
+            Location::Prg { offset, .. } => self.prg.entry(offset).or_default(),
+        };
+        if !name.is_empty() {
+            symbol.name = name.to_string();
+            self.locations.insert(name.to_string(), location);
+        }
+        if !comment.is_empty() {
+            symbol.comment = comment.to_string();
+        }
+    }
+
+    /// Loads symbol file by its extension, returns false on error.
+    pub fn load(&mut self, path: &Path) -> bool {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                println!("Can't read file: '{0}'. Error: {1}", path.display(), e);
+                return false;
+            }
+        };
+
+        let count = self.len();
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        match extension.to_lowercase().as_str() {
+            "dbg" => self.parse_dbg(&text, path.parent().unwrap_or(Path::new(""))),
+            "mlb" => self.parse_mlb(&text),
+            "nl" => {
+                // FCEUX names them "game.nes.ram.nl" and "game.nes.<bank>.nl"
+                let stem = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or("");
+                let bank = stem.rfind('.').and_then(|dot| parse_hex(&stem[dot + 1..]));
+                self.parse_nl(&text, bank);
+            }
+            _ => {
+                println!("Unknown symbol file: '{0}'.", path.display());
+                return false;
+            }
+        }
+        println!(
+            "Loaded {} symbols from '{}'.",
+            self.len() - count,
+            path.display()
+        );
+        true
+    }
+
+    /// Loads symbol files next to `rom_file` with the same name.
+    pub fn load_for_rom(&mut self, rom_file: &PathBuf) {
+        let candidates = [
+            rom_file.with_extension("dbg"),
+            rom_file.with_extension("mlb"),
+            PathBuf::from(format!("{}.ram.nl", rom_file.display())),
+        ];
+        for path in candidates.iter().filter(|path| path.is_file()) {
+            self.load(path);
+        }
+        for bank in 0..256 {
+            let path = PathBuf::from(format!("{}.{:X}.nl", rom_file.display(), bank));
+            if path.is_file() {
+                self.load(&path);
+            }
+        }
+    }
+
+    /// FCEUX name list, `$C000#Name#Comment` lines. `bank` is the 16K PRG bank for
+    /// "game.nes.<bank>.nl" files, `None` for RAM.
+    pub fn parse_nl(&mut self, text: &str, bank: Option<usize>) {
+        for line in text.lines().filter(|line| line.starts_with('$')) {
+            let mut parts = line.splitn(3, '#');
+            // Arrays are written as "$0300/10"
+            let addr = parts.next().and_then(|addr| addr.split('/').next());
+            let addr = match addr.and_then(parse_hex) {
+                Some(addr) if addr <= 0xFFFF => addr,
+                _ => continue,
+            };
+            let name = parts.next().unwrap_or("").trim();
+            let comment = parts.next().unwrap_or("").trim();
+
+            let location = match bank {
+                Some(bank) if addr >= 0x8000 => Location::Prg {
+                    offset: bank * 0x4000 + (addr & 0x3FFF),
+                    addr: Some(addr as u16),
+                },
+                _ => Location::Cpu(addr as u16),
+            };
+            self.add(location, name, comment);
+        }
+    }
+
+    /// Mesen label file, `TYPE:ADDR[-END]:Name[:Comment]` lines.
+    pub fn parse_mlb(&mut self, text: &str) {
+        for line in text.lines() {
+            let mut parts = line.splitn(4, ':');
+            let kind = parts.next().unwrap_or("");
+            let addr = parts.next().and_then(|addr| addr.split('-').next());
+            let addr = match addr.and_then(parse_hex) {
+                Some(addr) => addr,
+                None => continue,
+            };
+            let name = parts.next().unwrap_or("").trim();
+            let comment = parts.next().unwrap_or("").replace("\\n", "\n");
+
+            // Mesen 2 uses long memory type names, offsets past the memory type are skipped
+            let location = match kind {
+                "P" | "NesPrgRom" => Location::Prg {
+                    offset: addr,
+                    addr: None,
+                },
+                "R" | "G" | "NesInternalRam" | "NesMemory" if addr <= 0xFFFF => {
+                    Location::Cpu(addr as u16)
+                }
+                "W" | "S" | "NesWorkRam" | "NesSaveRam" if addr < 0x2000 => {
+                    Location::Cpu(0x6000 + addr as u16)
+                }
+                _ => continue,
+            };
+            self.add(location, name, comment.trim());
+        }
+    }
+
+    /// ld65 debug info, labels, absolute equates like `PPUCTRL = $2000` and source
+    /// lines, with source files relative to `dir`.
+    pub fn parse_dbg(&mut self, text: &str, dir: &Path) {
+        let records: Vec<DbgRecord> = text.lines().filter_map(DbgRecord::parse).collect();
+        let segments = DbgSegment::from_records(&records);
+        self.source_map.add_dbg(&records, &segments, dir);
+
+        for record in records.iter().filter(|record| record.kind == "sym") {
+            let (name, addr) = match (record.get("name"), record.number("val")) {
+                (Some(name), Some(addr)) if addr <= 0xFFFF => (name, addr),
+                _ => continue,
+            };
+            let location = match record.get("type") {
+                Some("lab") => match record.number("seg").and_then(|id| segments.get(&id)) {
+                    Some(segment) => match segment.location(addr) {
+                        Some(location) => location,
+                        None => continue,
+                    },
+                    None => Location::Cpu(addr as u16),
+                },
+                Some("equ") if record.get("addrsize") == Some("absolute") => {
+                    Location::Cpu(addr as u16)
+                }
+                _ => continue,
+            };
+            self.add(location, name, "");
+        }
+    }
+
+    /// Symbols as seen by CPU with `mapping` of PRG banks.
+    pub fn lookup(&self, mapping: PrgMapping) -> SymbolLookup<'_> {
+        SymbolLookup {
+            symbols: self,
+            mapping,
+        }
+    }
+}
+
+pub struct SymbolLookup<'a> {
+    symbols: &'a Symbols,
+    mapping: PrgMapping,
+}
+
+impl<'a> SymbolLookup<'a> {
+    pub fn symbol(&self, addr: u16) -> Option<&'a Symbol> {
+        match self.mapping.offset(addr) {
+            Some(offset) => self.symbols.prg.get(&offset),
+            None => self.symbols.cpu.get(&addr),
+        }
+    }
+
+    pub fn comment(&self, addr: u16) -> Option<&'a str> {
+        self.symbol(addr)
+            .map(|symbol| symbol.comment.as_str())
+            .filter(|comment| !comment.is_empty())
+    }
+}
+
+impl<'a> Labels for SymbolLookup<'a> {
+    fn label(&self, addr: u16) -> Option<&str> {
+        self.symbol(addr)
+            .map(|symbol| symbol.name.as_str())
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Address of a label or a source line given as `file:line`.
+    fn address(&self, name: &str) -> Option<u16> {
+        match self.symbols.locations.get(name) {
+            Some(Location::Cpu(addr)) => Some(*addr),
+            Some(Location::Prg { offset, addr }) => self.mapping.addr(*offset, *addr),
+            None => {
+                let source_map = &self.symbols.source_map;
+                let line = source_map.find(name)?;
+                source_map.addr(line, &self.mapping)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 32K ROM mapped as is
+    const MAPPING: PrgMapping = PrgMapping {
+        windows: [0, 0x2000, 0x4000, 0x6000],
+    };
+
+    #[test]
+    fn fceux_name_list() {
+        let mut symbols = Symbols::new();
+        symbols.parse_nl("$0010#counter#Frame counter\n$0300/10#buffer#\n", None);
+        symbols.parse_nl(
+            "$C000#Reset#\n\\ continued comment\n$C010##Wait vblank\n",
+            Some(1),
+        );
+
+        let lookup = symbols.lookup(MAPPING);
+        assert_eq!(lookup.label(0x10), Some("counter"));
+        assert_eq!(lookup.comment(0x10), Some("Frame counter"));
+        assert_eq!(lookup.label(0x300), Some("buffer"));
+        assert_eq!(lookup.label(0xC000), Some("Reset"));
+        assert_eq!(lookup.label(0xC010), None);
+        assert_eq!(lookup.comment(0xC010), Some("Wait vblank"));
+        assert_eq!(lookup.address("Reset"), Some(0xC000));
+        assert_eq!(symbols.len(), 4);
+    }
+
+    #[test]
+    fn mesen_labels() {
+        let mut symbols = Symbols::new();
+        symbols.parse_mlb(
+            "P:7FFA:nmi_vector\nR:0020-002F:sprites:OAM copy\nG:2000:PPUCTRL\nW:0010:save\n\
+             W:A000:past_ram\nR:10000:too_far\n",
+        );
+
+        let lookup = symbols.lookup(MAPPING);
+        assert_eq!(lookup.label(0xFFFA), Some("nmi_vector"));
+        assert_eq!(lookup.comment(0x20), Some("OAM copy"));
+        assert_eq!(lookup.label(0x2000), Some("PPUCTRL"));
+        assert_eq!(lookup.label(0x6010), Some("save"));
+        assert_eq!(lookup.address("past_ram"), None);
+        assert_eq!(lookup.address("too_far"), None);
+
+        // Last 16K bank of a 128K ROM fixed at $C000
+        symbols.parse_mlb("P:1C000:fixed_bank\n");
+        let lookup = symbols.lookup(PrgMapping {
+            windows: [0, 0x2000, 0x1C000, 0x1E000],
+        });
+        assert_eq!(lookup.label(0xC000), Some("fixed_bank"));
+    }
+
+    #[test]
+    fn ca65_debug_info() {
+        let text = "version\tmajor=2,minor=0\n\
+                    seg\tid=0,name=\"HEADER\",start=0x000000,size=0x0010,ooffs=0\n\
+                    seg\tid=1,name=\"CODE\",start=0x00C000,size=0x0100,oname=\"a,b.nes\",ooffs=16\n\
+                    seg\tid=2,name=\"BSS\",start=0x000300,size=0x0010\n\
+                    sym\tid=0,name=\"reset\",addrsize=absolute,val=0xC004,seg=1,type=lab\n\
+                    sym\tid=1,name=\"buffer\",addrsize=absolute,val=0x300,seg=2,type=lab\n\
+                    sym\tid=2,name=\"PPUCTRL\",addrsize=absolute,val=0x2000,type=equ\n\
+                    sym\tid=3,name=\"SPEED\",addrsize=zeropage,val=0x5,type=equ\n\
+                    sym\tid=4,name=\"before\",addrsize=absolute,val=0x8000,seg=1,type=lab\n";
+        let mut symbols = Symbols::new();
+        symbols.parse_dbg(text, Path::new("."));
+
+        // 16K ROM mirrored at $8000 and $C000
+        let lookup = symbols.lookup(PrgMapping {
+            windows: [0, 0x2000, 0, 0x2000],
+        });
+        assert_eq!(lookup.label(0xC004), Some("reset"));
+        assert_eq!(lookup.label(0x8004), Some("reset"));
+        assert_eq!(lookup.address("reset"), Some(0xC004));
+        assert_eq!(lookup.label(0x300), Some("buffer"));
+        assert_eq!(lookup.label(0x2000), Some("PPUCTRL"));
+        assert_eq!(lookup.label(0x5), None);
+        assert_eq!(lookup.address("before"), None);
+    }
+}
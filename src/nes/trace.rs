@@ -0,0 +1,291 @@
+use super::debugger::{Condition, Context};
+use super::disasm::DisasmInstruction;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Large buffer, a frame of trace is about a megabyte
+const BUFFER_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    /// Layout of nestest.log, bytes, disassembly, registers, PPU position and cycles.
+    Nestest,
+    /// Layout of Mesen trace logger with flag letters, scanline, dot and frame.
+    Mesen,
+}
+
+impl TraceFormat {
+    pub const ALL: [TraceFormat; 2] = [TraceFormat::Nestest, TraceFormat::Mesen];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TraceFormat::Nestest => "nestest",
+            TraceFormat::Mesen => "Mesen",
+        }
+    }
+
+    /// Format by its name, case insensitive.
+    pub fn from_name(name: &str) -> Option<Self> {
+        TraceFormat::ALL
+            .iter()
+            .find(|format| format.name().eq_ignore_ascii_case(name))
+            .copied()
+    }
+}
+
+impl Default for TraceFormat {
+    fn default() -> Self {
+        TraceFormat::Nestest
+    }
+}
+
+/// Parses inclusive range `FIRST-LAST` or a single value, empty text means no range.
+/// Addresses are hex with optional `$` and fit in 16 bits, frames are decimal.
+pub fn parse_range(text: &str, hex: bool) -> Result<Option<(u32, u32)>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    let parse = |s: &str| {
+        let s = s.trim();
+        if hex {
+            u16::from_str_radix(s.trim_start_matches('$'), 16)
+                .map(u32::from)
+                .map_err(|_| format!("'{}' is not a CPU address", s))
+        } else {
+            s.parse()
+                .map_err(|_| format!("'{}' is not a valid number", s))
+        }
+    };
+    let mut parts = text.splitn(2, '-');
+    let first = parse(parts.next().unwrap_or(""))?;
+    let last = match parts.next() {
+        Some(last) => parse(last)?,
+        None => first,
+    };
+    if last < first {
+        return Err(format!("range '{}' ends before it starts", text));
+    }
+    Ok(Some((first, last)))
+}
+
+/// What and how to trace.
+#[derive(Debug, Clone, Default)]
+pub struct TraceOptions {
+    pub format: TraceFormat,
+    /// Prefix PC with its 16K PRG-ROM bank.
+    pub show_bank: bool,
+    /// Only instructions with PC in this inclusive range.
+    pub addr_range: Option<(u16, u16)>,
+    /// Only instructions in these frames, inclusive.
+    pub frame_range: Option<(u32, u32)>,
+    /// Only instructions executed when the condition holds.
+    pub condition: Option<Condition>,
+}
+
+impl TraceOptions {
+    /// Whether the instruction about to run at `context.pc` passes the filters.
+    pub fn accepts(&self, context: &Context, frame: u32) -> bool {
+        self.addr_range
+            .map_or(true, |(first, last)| (first..=last).contains(&context.pc))
+            && self
+                .frame_range
+                .map_or(true, |(first, last)| (first..=last).contains(&frame))
+            && self
+                .condition
+                .as_ref()
+                .map_or(true, |condition| condition.eval(context))
+    }
+}
+
+/// State before one executed instruction.
+pub struct TraceLine<'a> {
+    pub ins: &'a DisasmInstruction,
+    pub context: &'a Context,
+    pub cycles: usize,
+    pub frame: u32,
+    /// 16K PRG-ROM bank of PC, `None` when not shown or not in PRG-ROM.
+    pub bank: Option<usize>,
+}
+
+// Flags as letters, upper case when set
+fn flag_letters(p: u8) -> String {
+    "NVUBDIZC"
+        .chars()
+        .enumerate()
+        .map(|(i, letter)| {
+            if p & (0x80 >> i) != 0 {
+                letter
+            } else {
+                letter.to_ascii_lowercase()
+            }
+        })
+        .collect()
+}
+
+impl<'a> TraceLine<'a> {
+    pub fn write(&self, f: &mut impl Write, format: TraceFormat) -> io::Result<()> {
+        let context = self.context;
+        if let Some(bank) = self.bank {
+            write!(f, "{:02X}:", bank)?;
+        }
+        match format {
+            TraceFormat::Nestest => {
+                write!(f, "{:04X} ", context.pc)?;
+                for i in 0..4 {
+                    match self.ins.bytes().get(i) {
+                        Some(byte) => write!(f, "{:02X} ", byte)?,
+                        None => write!(f, "   ")?,
+                    }
+                }
+                let unofficial = if self.ins.official { "" } else { "*" };
+                write!(f, "{}{} ", unofficial, self.ins.mnemonic)?;
+                let operand = format!("{} {}", self.ins.operand, self.ins.annotation);
+                write!(f, "{:<28}", format!("{} ", operand.trim_end()))?;
+                write!(
+                    f,
+                    "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} ",
+                    context.a, context.x, context.y, context.p, context.sp
+                )?;
+                write!(f, "PPU:{:>3},{:>3} ", context.scanline, context.cycle)?;
+                writeln!(f, "CYC:{} ", self.cycles)
+            }
+            TraceFormat::Mesen => {
+                let bytes: Vec<String> = self
+                    .ins
+                    .bytes()
+                    .iter()
+                    .map(|b| format!("${:02X}", b))
+                    .collect();
+                write!(f, "{:04X}  {:<12} ", context.pc, bytes.join(" "))?;
+                write!(f, "{:<24} ", self.ins.to_string())?;
+                write!(
+                    f,
+                    "A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{} ",
+                    context.a,
+                    context.x,
+                    context.y,
+                    context.sp,
+                    flag_letters(context.p)
+                )?;
+                writeln!(
+                    f,
+                    "V:{:<3} H:{:<3} Fr:{} Cyc:{}",
+                    context.scanline, context.cycle, self.frame, self.cycles
+                )
+            }
+        }
+    }
+}
+
+/// Writes traced instructions to a file.
+pub struct TraceLogger {
+    pub options: TraceOptions,
+    path: PathBuf,
+    writer: BufWriter<File>,
+    /// Number of lines written.
+    pub lines: u64,
+}
+
+impl TraceLogger {
+    pub fn create(path: &PathBuf, options: TraceOptions) -> Option<Self> {
+        match File::create(path) {
+            Ok(file) => Some(TraceLogger {
+                options,
+                path: path.clone(),
+                writer: BufWriter::with_capacity(BUFFER_SIZE, file),
+                lines: 0,
+            }),
+            Err(e) => {
+                println!("Can't create file: '{0}'. Error: {1}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes the line, returns false if the file can't be written anymore.
+    pub fn log(&mut self, line: &TraceLine) -> bool {
+        match line.write(&mut self.writer, self.options.format) {
+            Ok(()) => {
+                self.lines += 1;
+                true
+            }
+            Err(e) => {
+                println!("Can't write trace to '{}': {}", self.path.display(), e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::disasm;
+
+    #[test]
+    fn filters_and_formats() {
+        assert_eq!(parse_range("", true), Ok(None));
+        assert_eq!(parse_range("$C000-C0FF", true), Ok(Some((0xC000, 0xC0FF))));
+        assert_eq!(parse_range("10", false), Ok(Some((10, 10))));
+        assert!(parse_range("20-10", false).is_err());
+        assert!(parse_range("x", true).is_err());
+        assert!(parse_range("C000-10000", true).is_err());
+        assert_eq!(parse_range("100000", false), Ok(Some((100000, 100000))));
+
+        let options = TraceOptions {
+            addr_range: Some((0xC000, 0xC0FF)),
+            frame_range: Some((2, 3)),
+            condition: Some(Condition::parse("X == 5").unwrap()),
+            ..TraceOptions::default()
+        };
+        let context = Context {
+            pc: 0xC010,
+            x: 5,
+            sp: 0xFD,
+            p: 0x24,
+            cycle: 21,
+            ..Context::default()
+        };
+        assert!(options.accepts(&context, 2));
+        assert!(!options.accepts(&context, 4));
+        assert!(!options.accepts(&Context { x: 4, ..context }, 2));
+        assert!(!options.accepts(
+            &Context {
+                pc: 0xD000,
+                ..context
+            },
+            2
+        ));
+
+        let code = [0xB5, 0x10];
+        let mut ins = disasm::decode(&mut |addr| code[(addr - 0xC010) as usize], 0xC010, None);
+        ins.annotate(&mut |addr| addr as u8 * 2, context.x, context.y);
+        let line = TraceLine {
+            ins: &ins,
+            context: &context,
+            cycles: 7,
+            frame: 2,
+            bank: Some(1),
+        };
+        let text = |format| {
+            let mut out = vec![];
+            line.write(&mut out, format).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            text(TraceFormat::Nestest),
+            "01:C010 B5 10       LDA $10,X @ 15 = 2A             A:00 X:05 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7 \n"
+        );
+        assert_eq!(
+            text(TraceFormat::Mesen),
+            "01:C010  $B5 $10      LDA $10,X                \
+             A:00 X:05 Y:00 S:FD P:nvUbdIzc V:0   H:21  Fr:2 Cyc:7\n"
+        );
+    }
+}
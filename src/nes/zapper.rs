@@ -0,0 +1,81 @@
+use super::controller::ControllerPort;
+use super::ppu::{Ppu, SCREEN_SIZE};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// The photodiode keeps reacting for a bit more than 20 scanlines after the beam passed
+const LIGHT_SENSE_SCANLINES: i32 = 26;
+// Radius in pixels around the aimed point that is seen by the photodiode
+const LIGHT_SENSE_RADIUS: i32 = 2;
+const LIGHT_SENSE_BRIGHTNESS: u32 = 0x80;
+
+/// Zapper light gun.
+pub struct Zapper {
+    ppu: Rc<RefCell<Ppu>>,
+    /// Aimed screen pixel, `None` when pointing away from the screen.
+    pub aim: Option<(i32, i32)>,
+    pub trigger: bool,
+}
+
+impl ControllerPort for Zapper {
+    fn write(&mut self, _: u8) {}
+
+    fn read(&mut self) -> u8 {
+        self.peek()
+    }
+
+    fn peek(&self) -> u8 {
+        // D3: 0 when light is detected, D4: 1 when trigger is pulled
+        (!self.light_sensed() as u8) << 3 | (self.trigger as u8) << 4
+    }
+}
+
+fn brightness(c: u32) -> u32 {
+    let r = (c >> 16) & 0xFF;
+    let g = (c >> 8) & 0xFF;
+    let b = c & 0xFF;
+    (r * 299 + g * 587 + b * 114) / 1000
+}
+
+impl Zapper {
+    pub fn new(ppu: Rc<RefCell<Ppu>>) -> Self {
+        Zapper {
+            ppu,
+            aim: None,
+            trigger: false,
+        }
+    }
+
+    fn light_sensed(&self) -> bool {
+        let (x, y) = match self.aim {
+            Some(aim) => aim,
+            None => return false,
+        };
+
+        let ppu = self.ppu.borrow();
+        let scanline = ppu.scanline as i32;
+        let cycle = ppu.cycle as i32;
+
+        // Only pixels drawn during the current frame shortly before the beam position count
+        if scanline < y - LIGHT_SENSE_RADIUS || scanline > y + LIGHT_SENSE_SCANLINES {
+            return false;
+        }
+
+        for py in y - LIGHT_SENSE_RADIUS..=y + LIGHT_SENSE_RADIUS {
+            for px in x - LIGHT_SENSE_RADIUS..=x + LIGHT_SENSE_RADIUS {
+                if px < 0 || py < 0 || px >= SCREEN_SIZE.0 as i32 || py >= SCREEN_SIZE.1 as i32 {
+                    continue;
+                }
+                if py > scanline || (py == scanline && px >= cycle - 1) {
+                    continue;
+                }
+                let c = ppu.screen.get_pixel(px as usize, py as usize);
+                if brightness(ppu.palette.get_color(c)) >= LIGHT_SENSE_BRIGHTNESS {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
@@ -0,0 +1,130 @@
+use crate::app::UiTexture;
+use crate::imgui_wgpu::Renderer;
+use crate::nes::ppu_view::SpriteInfo;
+use crate::nes::Emulator;
+use imgui::*;
+use wgpu::{Device, Queue};
+
+// Previews are kept in one texture, 8 sprites per row in 8x16 cells
+const CELLS_WIDE: usize = 8;
+const CELL_SIZE: (usize, usize) = (8, 16);
+const PREVIEW_SCALE: f32 = 2.0;
+
+/// List of OAM entries with previews, and sprite boxes for the screen.
+pub struct OamWindow {
+    texture: UiTexture,
+    sprites: Vec<SpriteInfo>,
+    show_boxes: bool,
+    /// Pattern table addresses of the hovered sprite tiles.
+    pub hovered_tiles: Vec<u16>,
+}
+
+impl OamWindow {
+    pub fn new(renderer: &mut Renderer, device: &Device) -> Self {
+        let size = (CELLS_WIDE * CELL_SIZE.0, 64 / CELLS_WIDE * CELL_SIZE.1);
+        OamWindow {
+            texture: UiTexture::new(renderer, device, size),
+            sprites: vec![],
+            show_boxes: false,
+            hovered_tiles: vec![],
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        emulator: &Emulator,
+        renderer: &mut Renderer,
+        device: &Device,
+        queue: &mut Queue,
+    ) {
+        let ppu = emulator.ppu.borrow();
+        self.sprites = SpriteInfo::all(&ppu);
+        for sprite in &self.sprites {
+            let cell_x = sprite.index % CELLS_WIDE * CELL_SIZE.0;
+            let cell_y = sprite.index / CELLS_WIDE * CELL_SIZE.1;
+            let pixels = sprite.pixels(&ppu);
+            for y in 0..CELL_SIZE.1 {
+                for x in 0..CELL_SIZE.0 {
+                    let color = pixels
+                        .get(y * 8 + x)
+                        .map_or(0, |&c| ppu.palette.get_color(c));
+                    self.texture.set_pixel(cell_x + x, cell_y + y, color);
+                }
+            }
+        }
+        self.texture.update(renderer, device, queue);
+    }
+
+    /// Sprite bounding boxes in screen pixels as left, top, right, bottom.
+    pub fn screen_boxes(&self) -> Vec<[f32; 4]> {
+        if !self.show_boxes {
+            return vec![];
+        }
+        // Sprites at Y $EF and below are off screen
+        self.sprites
+            .iter()
+            .filter(|sprite| sprite.y < 0xEF)
+            .map(|sprite| {
+                let (x, y) = (sprite.x as f32, sprite.y as f32 + 1.0);
+                [x, y, x + 8.0, y + sprite.height as f32]
+            })
+            .collect()
+    }
+
+    pub fn draw_ui(&mut self, ui: &imgui::Ui) {
+        let window = imgui::Window::new(im_str!("Sprites"));
+        window
+            .size([360.0, 500.0], Condition::FirstUseEver)
+            .position([1440.0, 610.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                ui.checkbox(im_str!("Boxes on screen"), &mut self.show_boxes);
+                self.hovered_tiles.clear();
+                ChildWindow::new(im_str!("OAM"))
+                    .border(true)
+                    .build(&ui, || self.draw_list(ui));
+            });
+    }
+
+    fn draw_list(&mut self, ui: &imgui::Ui) {
+        let [width, height] = self.texture.get_size(1.0);
+        let cell_uv = [CELL_SIZE.0 as f32 / width, CELL_SIZE.1 as f32 / height];
+        for sprite in &self.sprites {
+            let uv0 = [
+                (sprite.index % CELLS_WIDE) as f32 * cell_uv[0],
+                (sprite.index / CELLS_WIDE) as f32 * cell_uv[1],
+            ];
+            // Only the top half of the cell is used by 8x8 sprites
+            let rows = sprite.height as f32 / CELL_SIZE.1 as f32;
+            let uv1 = [uv0[0] + cell_uv[0], uv0[1] + cell_uv[1] * rows];
+            Image::new(
+                self.texture.texture_id,
+                [8.0 * PREVIEW_SCALE, sprite.height as f32 * PREVIEW_SCALE],
+            )
+            .uv0(uv0)
+            .uv1(uv1)
+            .build(&ui);
+            let mut hovered = ui.is_item_hovered();
+
+            ui.same_line(0.0);
+            let flip = match (sprite.flip_h, sprite.flip_v) {
+                (false, false) => "",
+                (true, false) => "H",
+                (false, true) => "V",
+                (true, true) => "HV",
+            };
+            let priority = if sprite.behind_background {
+                "back"
+            } else {
+                "front"
+            };
+            ui.text(format!(
+                "{:2} X:{:3} Y:{:3} Tile:${:02X} Pal:{} {:5} {}",
+                sprite.index, sprite.x, sprite.y, sprite.tile, sprite.palette, priority, flip
+            ));
+            hovered |= ui.is_item_hovered();
+            if hovered {
+                self.hovered_tiles = sprite.tile_addrs();
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use crate::nes::ppu_view::{pattern_table, PATTERN_TABLE_SIZE};
+use crate::nes::Emulator;
+use crate::video::cpu_filters;
+use imgui::*;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SWATCH_SIZE: f32 = 24.0;
+
+fn to_color(rgb: u32) -> [f32; 4] {
+    [
+        (rgb >> 16 & 0xFF) as f32 / 255.0,
+        (rgb >> 8 & 0xFF) as f32 / 255.0,
+        (rgb & 0xFF) as f32 / 255.0,
+        1.0,
+    ]
+}
+
+/// Palette RAM entries, clicking one selects the palette for the pattern tables.
+pub struct PaletteWindow {
+    /// Palette 0-7 the pattern tables are drawn with.
+    pub selected: u8,
+}
+
+impl PaletteWindow {
+    pub fn new() -> Self {
+        PaletteWindow { selected: 0 }
+    }
+
+    pub fn draw_ui(&mut self, ui: &imgui::Ui, emulator: &Emulator) {
+        let window = imgui::Window::new(im_str!("Palettes"));
+        window
+            .size([300.0, 330.0], Condition::FirstUseEver)
+            .position([1170.0, 610.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                let ppu = emulator.ppu.borrow();
+                for palette in 0..8u8 {
+                    let name = if palette < 4 { "BG" } else { "Sprite" };
+                    let label = im_str!("{} {}", name, palette % 4);
+                    if Selectable::new(&label)
+                        .selected(self.selected == palette)
+                        .size([60.0, SWATCH_SIZE])
+                        .build(&ui)
+                    {
+                        self.selected = palette;
+                    }
+
+                    for pixel in 0..4u8 {
+                        let index = palette * 4 + pixel;
+                        let addr = 0x3F00 + index as u16;
+                        let value = ppu.ppu_peek(addr) & 0x3F;
+                        let rgb = ppu.palette.get_color(value as u16);
+                        ui.same_line(0.0);
+                        if ColorButton::new(&im_str!("##entry{}", index), to_color(rgb))
+                            .size([SWATCH_SIZE, SWATCH_SIZE])
+                            .tooltip(false)
+                            .build(&ui)
+                        {
+                            self.selected = palette;
+                        }
+                        if ui.is_item_hovered() {
+                            ui.tooltip_text(format!(
+                                "{:2}: ${:04X} = ${:02X}\nRGB: #{:06X}",
+                                index, addr, value, rgb
+                            ));
+                        }
+                    }
+                }
+
+                ui.separator();
+                if ui.button(im_str!("Export pattern tables"), [0.0, 0.0]) {
+                    self.export_pattern_tables(emulator);
+                }
+            });
+    }
+
+    // Both pattern tables side by side as a PNG, drawn with the selected palette
+    fn export_pattern_tables(&self, emulator: &Emulator) {
+        let ppu = emulator.ppu.borrow();
+        let (width, height) = PATTERN_TABLE_SIZE;
+        let tables = [
+            pattern_table(&ppu, 0x0000, self.selected),
+            pattern_table(&ppu, 0x1000, self.selected),
+        ];
+        let mut pixels = Vec::with_capacity(width * 2 * height);
+        for y in 0..height {
+            for table in tables.iter() {
+                let row = &table[y * width..(y + 1) * width];
+                pixels.extend(row.iter().map(|&color| ppu.palette.get_color(color)));
+            }
+        }
+
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let path = PathBuf::from(format!("patterns-{}.png", time.as_secs()));
+        cpu_filters::Image::new(width * 2, height, pixels).save_png(&path);
+    }
+}
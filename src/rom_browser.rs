@@ -0,0 +1,327 @@
+use crate::app::UiTexture;
+use crate::config::config_path;
+use crate::imgui_wgpu::Renderer;
+use crate::nes::cartridge::RomHeader;
+use crate::nes::mappers;
+use crate::nes::ppu::SCREEN_SIZE;
+use crate::video::cpu_filters;
+use imgui::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use wgpu::{Device, Queue};
+
+/// Number of recently played ROMs to remember.
+pub const MAX_RECENT_ROMS: usize = 10;
+
+const UNSUPPORTED_COLOR: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+
+// FNV-1a, unlike the std hasher it stays the same across Rust versions
+fn path_hash(path: &PathBuf) -> u64 {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+    path.to_string_lossy()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+// Same-named ROMs in different directories get their own thumbnails
+fn thumbnail_path(rom_file: &PathBuf) -> Option<PathBuf> {
+    let name = rom_file.file_stem()?.to_str()?;
+    config_path(&format!(
+        "thumbnails/{}-{:016x}.png",
+        name,
+        path_hash(rom_file)
+    ))
+}
+
+/// Saves the screen as thumbnail shown for `rom_file` in the browser.
+pub fn save_thumbnail(rom_file: &PathBuf, screen: &[u32]) {
+    let path = match thumbnail_path(rom_file) {
+        Some(path) => path,
+        None => return,
+    };
+    let (width, height) = SCREEN_SIZE;
+    let image = cpu_filters::Image::new(width, height, screen.to_vec());
+    let result = fs::create_dir_all(path.parent().unwrap())
+        .map_err(|e| e.to_string())
+        .and_then(|_| {
+            image::save_buffer(
+                &path,
+                &image.to_rgba(),
+                width as u32,
+                height as u32,
+                image::ColorType::Rgba8,
+            )
+            .map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        println!("Can't save thumbnail: '{0}'. Error: {1}", path.display(), e);
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+enum Entry {
+    Dir(PathBuf),
+    // Header is `None` for files which are not iNES ROMs
+    Rom(PathBuf, Option<RomHeader>),
+}
+
+/// Window to browse directories for ROMs, showing header info and thumbnails from
+/// previous sessions.
+pub struct RomBrowser {
+    dir: PathBuf,
+    path_input: ImString,
+    filter: ImString,
+    entries: Vec<Entry>,
+    selected: Option<(PathBuf, Option<RomHeader>)>,
+    thumbnail: UiTexture,
+    // ROM the thumbnail texture has been loaded for, and if it had a thumbnail
+    thumbnail_rom: Option<PathBuf>,
+    has_thumbnail: bool,
+}
+
+impl RomBrowser {
+    pub fn new(renderer: &mut Renderer, device: &Device, dir: PathBuf) -> Self {
+        let mut browser = RomBrowser {
+            dir: PathBuf::new(),
+            path_input: ImString::with_capacity(256),
+            filter: ImString::with_capacity(64),
+            entries: vec![],
+            selected: None,
+            thumbnail: UiTexture::new(renderer, device, SCREEN_SIZE),
+            thumbnail_rom: None,
+            has_thumbnail: false,
+        };
+        browser.open_dir(dir);
+        browser
+    }
+
+    fn open_dir(&mut self, dir: PathBuf) {
+        let read_dir = match fs::read_dir(&dir) {
+            Ok(read_dir) => read_dir,
+            Err(e) => {
+                println!("Can't open directory: '{0}'. Error: {1}", dir.display(), e);
+                return;
+            }
+        };
+
+        let mut dirs = vec![];
+        let mut roms = vec![];
+        for path in read_dir.filter_map(Result::ok).map(|entry| entry.path()) {
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path
+                .extension()
+                .map_or(false, |ext| ext.eq_ignore_ascii_case("nes"))
+            {
+                roms.push(path);
+            }
+        }
+        dirs.sort();
+        roms.sort();
+
+        self.entries = dirs.into_iter().map(Entry::Dir).collect();
+        self.entries.extend(roms.into_iter().map(|rom| {
+            let header = RomHeader::read(&rom);
+            Entry::Rom(rom, header)
+        }));
+        self.path_input = ImString::new(dir.to_string_lossy());
+        self.dir = dir;
+    }
+
+    /// Draws the browser window, returns ROM the user wants to play.
+    pub fn draw_ui(
+        &mut self,
+        ui: &imgui::Ui,
+        rom_dirs: &[PathBuf],
+        recent: &[PathBuf],
+    ) -> Option<PathBuf> {
+        let mut open = None;
+        let mut load = None;
+
+        let window = imgui::Window::new(im_str!("ROMs"));
+        window
+            .size([350.0, 600.0], Condition::FirstUseEver)
+            .position([5.0, 5.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                if ui.small_button(im_str!("Up")) {
+                    open = self.dir.parent().map(Path::to_path_buf);
+                }
+                for (i, rom_dir) in rom_dirs.iter().enumerate() {
+                    ui.same_line(0.0);
+                    if ui.small_button(&im_str!("ROMs {}", i + 1)) {
+                        open = Some(rom_dir.clone());
+                    }
+                }
+                if ui
+                    .input_text(im_str!("Path"), &mut self.path_input)
+                    .enter_returns_true(true)
+                    .build()
+                {
+                    open = Some(PathBuf::from(self.path_input.to_str()));
+                }
+                ui.input_text(im_str!("Filter"), &mut self.filter).build();
+
+                ChildWindow::new(im_str!("Files"))
+                    .size([0.0, -150.0])
+                    .border(true)
+                    .build(&ui, || self.draw_entries(ui, &mut open, &mut load));
+
+                if self.draw_selected(ui) {
+                    load = self.selected.as_ref().map(|(rom, _)| rom.clone());
+                }
+
+                if !recent.is_empty() && CollapsingHeader::new(im_str!("Recent")).build(&ui) {
+                    for (i, rom) in recent.iter().enumerate() {
+                        let label = im_str!("{}##recent{}", file_name(rom), i);
+                        if Selectable::new(&label).build(&ui) {
+                            load = Some(rom.clone());
+                        }
+                    }
+                }
+            });
+
+        if let Some(dir) = open {
+            self.open_dir(dir);
+        }
+        load
+    }
+
+    // Directories and ROMs matching the filter, unsupported mappers are grayed out
+    fn draw_entries(
+        &mut self,
+        ui: &imgui::Ui,
+        open: &mut Option<PathBuf>,
+        load: &mut Option<PathBuf>,
+    ) {
+        let filter = self.filter.to_str().to_lowercase();
+        for entry in &self.entries {
+            let (rom, header) = match entry {
+                Entry::Dir(dir) => {
+                    if Selectable::new(&im_str!("[{}]", file_name(dir))).build(&ui) {
+                        *open = Some(dir.clone());
+                    }
+                    continue;
+                }
+                Entry::Rom(rom, header) => (rom, header),
+            };
+
+            let name = file_name(rom);
+            if !name.to_lowercase().contains(&filter) {
+                continue;
+            }
+            let supported = header
+                .as_ref()
+                .map_or(false, |h| mappers::is_supported(h.mapper));
+            let label = match header {
+                Some(h) if !supported => im_str!("{} (mapper {})", name, h.mapper),
+                None => im_str!("{} (bad header)", name),
+                _ => im_str!("{}", name),
+            };
+
+            let color = if supported {
+                None
+            } else {
+                Some(ui.push_style_color(StyleColor::Text, UNSUPPORTED_COLOR))
+            };
+            let is_selected = self.selected.as_ref().map(|(path, _)| path) == Some(rom);
+            if Selectable::new(&label).selected(is_selected).build(&ui) {
+                self.selected = Some((rom.clone(), header.clone()));
+            }
+            if ui.is_item_hovered() && ui.is_mouse_double_clicked(MouseButton::Left) {
+                *load = Some(rom.clone());
+            }
+            if let Some(color) = color {
+                color.pop(&ui);
+            }
+        }
+    }
+
+    // Header info and thumbnail of the selected ROM, returns true when Play is clicked
+    fn draw_selected(&self, ui: &imgui::Ui) -> bool {
+        let (rom, header) = match &self.selected {
+            Some(selected) => selected,
+            None => return false,
+        };
+
+        if self.has_thumbnail && self.thumbnail_rom.as_ref() == Some(rom) {
+            Image::new(self.thumbnail.texture_id, self.thumbnail.get_size(0.5)).build(&ui);
+            ui.same_line(0.0);
+        }
+
+        let mut play = false;
+        ui.group(|| {
+            ui.text(file_name(rom));
+            match header {
+                Some(header) => {
+                    let supported = mappers::is_supported(header.mapper);
+                    ui.text(format!(
+                        "Mapper {}{}",
+                        header.mapper,
+                        if supported { "" } else { " (unsupported)" }
+                    ));
+                    let chr = if header.chr_pages == 0 {
+                        "RAM".to_string()
+                    } else {
+                        format!("{}K", header.chr_rom_size() / 1024)
+                    };
+                    ui.text(format!(
+                        "PRG {}K, CHR {}",
+                        header.prg_rom_size() / 1024,
+                        chr
+                    ));
+                    let mirroring = if header.four_screen {
+                        "four-screen"
+                    } else if header.vertical_mirror {
+                        "vertical"
+                    } else {
+                        "horizontal"
+                    };
+                    ui.text(format!("Mirroring: {}", mirroring));
+                    if header.battery {
+                        ui.text(im_str!("Battery"));
+                    }
+                    let region = header.region.map_or("unknown", |r| r.name());
+                    ui.text(format!("Region: {}", region));
+                }
+                None => ui.text(im_str!("Not an iNES file")),
+            }
+            play = ui.small_button(im_str!("Play"));
+        });
+        play
+    }
+
+    /// Loads the thumbnail of the selected ROM into the texture.
+    pub fn update(&mut self, renderer: &mut Renderer, device: &Device, queue: &mut Queue) {
+        let rom = match &self.selected {
+            Some((rom, _)) => rom,
+            None => return,
+        };
+        if self.thumbnail_rom.as_ref() == Some(rom) {
+            return;
+        }
+        self.thumbnail_rom = Some(rom.clone());
+        self.has_thumbnail = false;
+
+        let image = match thumbnail_path(rom).and_then(|path| image::open(path).ok()) {
+            Some(image) => image.to_rgba8(),
+            None => return,
+        };
+        if image.dimensions() != (SCREEN_SIZE.0 as u32, SCREEN_SIZE.1 as u32) {
+            return;
+        }
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let [r, g, b, _] = pixel.0;
+            let color = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+            self.thumbnail.set_pixel(x as usize, y as usize, color);
+        }
+        self.thumbnail.update(renderer, device, queue);
+        self.has_thumbnail = true;
+    }
+}
@@ -0,0 +1,293 @@
+// CPU versions of the screen pipeline for when there is no GPU, e.g. headless screenshots.
+// Cropping, upscaling and scaling to the output size follow the shaders in `screen.frag`,
+// but the hq2x here is a simplified one and xBR and the effects are only done by shaders.
+
+use super::{ScaleFilter, Upscaler, VideoOptions};
+use crate::nes::ppu::SCREEN_SIZE;
+use std::path::PathBuf;
+
+/// RGB image with 0xRRGGBB pixels.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<u32>) -> Self {
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    // Pixel at (x + dx, y + dy), clamped to the image edges
+    fn get(&self, x: usize, y: usize, dx: i32, dy: i32) -> u32 {
+        let x = (x as i32 + dx).max(0).min(self.width as i32 - 1) as usize;
+        let y = (y as i32 + dy).max(0).min(self.height as i32 - 1) as usize;
+        self.pixels[x + y * self.width]
+    }
+
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Image {
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in y..y + height {
+            let start = x + row * self.width;
+            pixels.extend_from_slice(&self.pixels[start..start + width]);
+        }
+        Image::new(width, height, pixels)
+    }
+
+    /// RGBA bytes as expected by the `image` crate.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.pixels.len() * 4);
+        for c in &self.pixels {
+            data.extend_from_slice(&[(c >> 16) as u8, (c >> 8) as u8, *c as u8, 0xFF]);
+        }
+        data
+    }
+
+    pub fn save_png(&self, path: &PathBuf) {
+        match image::save_buffer(
+            path,
+            &self.to_rgba(),
+            self.width as u32,
+            self.height as u32,
+            image::ColorType::Rgba8,
+        ) {
+            Ok(_) => println!("Saved screenshot: '{0}'.", path.display()),
+            Err(e) => println!(
+                "Can't save screenshot: '{0}'. Error: {1}",
+                path.display(),
+                e
+            ),
+        }
+    }
+}
+
+/// Crops the overscan, runs the selected upscaler and scales to the output size with the
+/// pixel aspect ratio, like the window shows the screen without effects.
+pub fn apply(options: &VideoOptions, screen: &Image) -> Image {
+    // The screen may come wider from the NTSC filter
+    let (x, y, w, h) = options.crop_rect();
+    let image = screen.crop(
+        x * screen.width / SCREEN_SIZE.0,
+        y,
+        w * screen.width / SCREEN_SIZE.0,
+        h,
+    );
+
+    let image = match options.upscaler {
+        Upscaler::Scale2x => scale2x(&image),
+        Upscaler::Hq2x => hq2x(&image),
+        Upscaler::None | Upscaler::Xbr => image,
+    };
+    let (width, height) = options.output_size();
+    resize(&image, width as usize, height as usize, options.filter)
+}
+
+/// Scales `src` to `width` x `height`, sampling at pixel centers like the GPU does.
+pub fn resize(src: &Image, width: usize, height: usize, filter: ScaleFilter) -> Image {
+    let scale_x = src.width as f32 / width as f32;
+    let scale_y = src.height as f32 / height as f32;
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let sy = (y as f32 + 0.5) * scale_y;
+        for x in 0..width {
+            let sx = (x as f32 + 0.5) * scale_x;
+            pixels.push(match filter {
+                ScaleFilter::Nearest => src.get(sx as usize, sy as usize, 0, 0),
+                ScaleFilter::Bilinear => bilinear(src, sx - 0.5, sy - 0.5),
+            });
+        }
+    }
+    Image::new(width, height, pixels)
+}
+
+// Source pixels around (x, y) in pixel center coordinates, weighted by distance
+fn bilinear(src: &Image, x: f32, y: f32) -> u32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (ix, iy) = (x0.max(0.0) as usize, y0.max(0.0) as usize);
+    let (dx, dy) = (if x0 < 0.0 { 0 } else { 1 }, if y0 < 0.0 { 0 } else { 1 });
+    let weight = |f: f32| (f * 256.0).round() as u32;
+    let (wx, wy) = (weight(fx), weight(fy));
+    blend(&[
+        (src.get(ix, iy, 0, 0), (256 - wx) * (256 - wy)),
+        (src.get(ix, iy, dx, 0), wx * (256 - wy)),
+        (src.get(ix, iy, 0, dy), (256 - wx) * wy),
+        (src.get(ix, iy, dx, dy), wx * wy),
+    ])
+}
+
+// Runs `quadrant` for the 4 output pixels of every source pixel, passing the
+// direction (dx, dy) the output pixel faces
+fn upscale_2x(src: &Image, quadrant: impl Fn(&Image, usize, usize, i32, i32) -> u32) -> Image {
+    let width = src.width * 2;
+    let mut pixels = vec![0; width * src.height * 2];
+    for y in 0..src.height {
+        for x in 0..src.width {
+            for &(qx, qy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let dx = qx as i32 * 2 - 1;
+                let dy = qy as i32 * 2 - 1;
+                pixels[x * 2 + qx + (y * 2 + qy) * width] = quadrant(src, x, y, dx, dy);
+            }
+        }
+    }
+    Image::new(width, src.height * 2, pixels)
+}
+
+/// Scale2x (EPX) pixel art upscaler.
+pub fn scale2x(src: &Image) -> Image {
+    upscale_2x(src, |src, x, y, dx, dy| {
+        let e = src.get(x, y, 0, 0);
+        let side_x = src.get(x, y, dx, 0);
+        let side_y = src.get(x, y, 0, dy);
+        let opposite_x = src.get(x, y, -dx, 0);
+        let opposite_y = src.get(x, y, 0, -dy);
+        if side_x == side_y && side_y != opposite_x && side_x != opposite_y {
+            side_x
+        } else {
+            e
+        }
+    })
+}
+
+fn to_yuv(c: u32) -> (f32, f32, f32) {
+    let r = ((c >> 16) & 0xFF) as f32;
+    let g = ((c >> 8) & 0xFF) as f32;
+    let b = (c & 0xFF) as f32;
+    (
+        0.299 * r + 0.587 * g + 0.114 * b,
+        -0.169 * r - 0.331 * g + 0.5 * b,
+        0.5 * r - 0.419 * g - 0.081 * b,
+    )
+}
+
+// Colors are similar when under the hqx YUV thresholds
+fn similar(a: u32, b: u32) -> bool {
+    let (ya, ua, va) = to_yuv(a);
+    let (yb, ub, vb) = to_yuv(b);
+    (ya - yb).abs() <= 48.0 && (ua - ub).abs() <= 7.0 && (va - vb).abs() <= 6.0
+}
+
+// Weighted average of colors
+fn blend(colors: &[(u32, u32)]) -> u32 {
+    let total: u32 = colors.iter().map(|&(_, w)| w).sum();
+    let channel = |shift: u32| {
+        let sum: u32 = colors.iter().map(|&(c, w)| ((c >> shift) & 0xFF) * w).sum();
+        ((sum + total / 2) / total) << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
+
+/// hq2x style upscaler. Neighbours are compared in YUV with the hqx thresholds, but
+/// instead of the full 256 case table only corners cut by an edge get blended.
+pub fn hq2x(src: &Image) -> Image {
+    upscale_2x(src, |src, x, y, dx, dy| {
+        let e = src.get(x, y, 0, 0);
+        let side_x = src.get(x, y, dx, 0);
+        let side_y = src.get(x, y, 0, dy);
+        let diagonal = src.get(x, y, dx, dy);
+        let ex = similar(e, side_x);
+        let ey = similar(e, side_y);
+        if !ex && !ey && similar(side_x, side_y) {
+            // An edge cuts the corner
+            if similar(diagonal, side_x) {
+                return blend(&[(e, 2), (side_x, 3), (side_y, 3)]);
+            }
+            return blend(&[(e, 2), (side_x, 1), (side_y, 1)]);
+        }
+        if !ex && !ey && !similar(e, diagonal) {
+            return blend(&[(e, 3), (diagonal, 1)]);
+        }
+        e
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: u32 = 0xFFFFFF;
+    const B: u32 = 0x000000;
+
+    #[test]
+    fn scale2x_diagonal() {
+        #[rustfmt::skip]
+        let src = Image::new(3, 3, vec![
+            W, B, B,
+            B, B, B,
+            B, B, W,
+        ]);
+        let out = scale2x(&src);
+        assert_eq!((out.width, out.height), (6, 6));
+        // Flat areas stay as they are
+        assert_eq!(out.get(2, 2, 0, 0), B);
+        assert_eq!(out.get(5, 5, 0, 0), W);
+
+        #[rustfmt::skip]
+        let src = Image::new(2, 2, vec![
+            W, B,
+            B, W,
+        ]);
+        let out = scale2x(&src);
+        // Black pixels between the white diagonal get their inner corner filled
+        assert_eq!(out.get(2, 1, 0, 0), W);
+        assert_eq!(out.get(3, 0, 0, 0), B);
+    }
+
+    #[test]
+    fn hq2x_blends_edges() {
+        #[rustfmt::skip]
+        let src = Image::new(3, 3, vec![
+            B, B, B,
+            B, W, W,
+            B, W, W,
+        ]);
+        let out = hq2x(&src);
+        // Top left corner of the white block is cut by the black edge
+        assert_ne!(out.get(2, 2, 0, 0), W);
+        assert_ne!(out.get(2, 2, 0, 0), B);
+        assert_eq!(out.get(3, 3, 0, 0), W);
+        assert_eq!(out.get(0, 0, 0, 0), B);
+    }
+
+    #[test]
+    fn apply_keeps_ntsc_width() {
+        // NTSC filter output is wider than the NES screen, not a multiple of it
+        let (width, height) = (602, SCREEN_SIZE.1);
+        let mut pixels = vec![B; width * height];
+        pixels[width - 3..width].copy_from_slice(&[W; 3]);
+        let screen = Image::new(width, height, pixels);
+
+        let mut options = VideoOptions::default();
+        options.scale = 1.0;
+        let out = apply(&options, &screen);
+        assert_eq!((out.width, out.height), (256, 240));
+        assert_eq!(out.get(255, 0, 0, 0), W);
+        assert_eq!(out.get(254, 0, 0, 0), B);
+
+        options.crop_overscan = true;
+        let out = apply(&options, &screen);
+        assert_eq!((out.width, out.height), (256, 240 - 16));
+    }
+
+    #[test]
+    fn apply_scales_to_output_size() {
+        let (width, height) = SCREEN_SIZE;
+        let screen = Image::new(width, height, vec![W; width * height]);
+
+        let mut options = VideoOptions::default();
+        options.scale = 2.0;
+        options.pixel_aspect = true;
+        let out = apply(&options, &screen);
+        assert_eq!((out.width as u32, out.height as u32), options.output_size());
+        assert_eq!((out.width, out.height), (585, 480));
+
+        // Flat areas stay flat when filtered
+        options.filter = ScaleFilter::Bilinear;
+        let out = apply(&options, &screen);
+        assert!(out.pixels.iter().all(|&c| c == W));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::nes::ppu::SCREEN_SIZE;
+use imgui::*;
+use serde::{Deserialize, Serialize};
+
+pub mod cpu_filters;
+pub mod pipeline;
+
+// Lines hidden by the TV bezel on top and bottom of the picture
+const OVERSCAN_LINES: usize = 8;
+// NES pixels are 8:7 wide on a 4:3 TV
+const PIXEL_ASPECT: f32 = 8.0 / 7.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ScaleFilter {
+    Nearest,
+    Bilinear,
+}
+
+/// Pixel art upscaler run before scaling to the output size.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Upscaler {
+    None,
+    Scale2x,
+    Hq2x,
+    Xbr,
+}
+
+/// Post-process effect applied to the scaled picture.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Effect {
+    None,
+    Scanlines,
+    Crt,
+}
+
+/// How the game screen is scaled and filtered for display.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VideoOptions {
+    pub scale: f32,
+    pub integer_scale: bool,
+    /// Stretch to the 8:7 pixel aspect ratio of a TV.
+    pub pixel_aspect: bool,
+    pub filter: ScaleFilter,
+    pub crop_overscan: bool,
+    pub upscaler: Upscaler,
+    pub effect: Effect,
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        VideoOptions {
+            scale: 3.0,
+            integer_scale: true,
+            pixel_aspect: false,
+            filter: ScaleFilter::Nearest,
+            crop_overscan: false,
+            upscaler: Upscaler::None,
+            effect: Effect::None,
+        }
+    }
+}
+
+impl VideoOptions {
+    /// Visible part of the NES screen as x, y, width, height.
+    pub fn crop_rect(&self) -> (usize, usize, usize, usize) {
+        if self.crop_overscan {
+            (
+                0,
+                OVERSCAN_LINES,
+                SCREEN_SIZE.0,
+                SCREEN_SIZE.1 - 2 * OVERSCAN_LINES,
+            )
+        } else {
+            (0, 0, SCREEN_SIZE.0, SCREEN_SIZE.1)
+        }
+    }
+
+    pub fn scale_factor(&self) -> f32 {
+        if self.integer_scale {
+            self.scale.round().max(1.0)
+        } else {
+            self.scale.max(0.25)
+        }
+    }
+
+    /// Size of one NES pixel on screen.
+    pub fn pixel_size(&self) -> (f32, f32) {
+        let scale = self.scale_factor();
+        if self.pixel_aspect {
+            (scale * PIXEL_ASPECT, scale)
+        } else {
+            (scale, scale)
+        }
+    }
+
+    pub fn output_size(&self) -> (u32, u32) {
+        let (_, _, w, h) = self.crop_rect();
+        let (px, py) = self.pixel_size();
+        (
+            (w as f32 * px).round() as u32,
+            (h as f32 * py).round() as u32,
+        )
+    }
+
+    /// Maps a position relative to the top left of the displayed picture to NES pixels.
+    pub fn to_nes_pixel(&self, pos: [f32; 2]) -> (i32, i32) {
+        let (x, y, _, _) = self.crop_rect();
+        let (px, py) = self.pixel_size();
+        (
+            (pos[0] / px) as i32 + x as i32,
+            (pos[1] / py) as i32 + y as i32,
+        )
+    }
+
+    /// Position of NES pixel `x`, `y` relative to the top left of the displayed picture.
+    pub fn from_nes_pixel(&self, x: f32, y: f32) -> [f32; 2] {
+        let (crop_x, crop_y, _, _) = self.crop_rect();
+        let (px, py) = self.pixel_size();
+        [(x - crop_x as f32) * px, (y - crop_y as f32) * py]
+    }
+
+    /// Same options with the largest scale at which the picture fits into `area`.
+    pub fn fit(&self, area: [f32; 2]) -> VideoOptions {
+        let (_, _, w, h) = self.crop_rect();
+        let aspect = if self.pixel_aspect { PIXEL_ASPECT } else { 1.0 };
+        let scale = (area[0] / (w as f32 * aspect)).min(area[1] / h as f32);
+
+        let mut options = *self;
+        options.scale = if self.integer_scale {
+            scale.floor().max(1.0)
+        } else {
+            scale.max(0.25)
+        };
+        options
+    }
+
+    /// Draws option widgets, returns true if anything changed.
+    pub fn draw_ui(&mut self, ui: &imgui::Ui) -> bool {
+        let old = *self;
+
+        ui.drag_float(im_str!("Scale"), &mut self.scale)
+            .min(1.0)
+            .max(8.0)
+            .speed(0.05)
+            .build();
+        ui.checkbox(im_str!("Integer scale"), &mut self.integer_scale);
+        ui.same_line(0.0);
+        ui.checkbox(im_str!("8:7 aspect"), &mut self.pixel_aspect);
+        ui.same_line(0.0);
+        ui.checkbox(im_str!("Crop overscan"), &mut self.crop_overscan);
+
+        ui.text(im_str!("Filter:"));
+        ui.same_line(0.0);
+        ui.radio_button(im_str!("Nearest"), &mut self.filter, ScaleFilter::Nearest);
+        ui.same_line(0.0);
+        ui.radio_button(im_str!("Bilinear"), &mut self.filter, ScaleFilter::Bilinear);
+
+        ui.text(im_str!("Upscaler:"));
+        ui.same_line(0.0);
+        ui.radio_button(im_str!("None"), &mut self.upscaler, Upscaler::None);
+        ui.same_line(0.0);
+        ui.radio_button(im_str!("Scale2x"), &mut self.upscaler, Upscaler::Scale2x);
+        ui.same_line(0.0);
+        ui.radio_button(im_str!("hq2x"), &mut self.upscaler, Upscaler::Hq2x);
+        ui.same_line(0.0);
+        ui.radio_button(im_str!("xBR"), &mut self.upscaler, Upscaler::Xbr);
+
+        ui.text(im_str!("Effect:"));
+        ui.same_line(0.0);
+        ui.radio_button(im_str!("Off"), &mut self.effect, Effect::None);
+        ui.same_line(0.0);
+        ui.radio_button(im_str!("Scanlines"), &mut self.effect, Effect::Scanlines);
+        ui.same_line(0.0);
+        ui.radio_button(im_str!("CRT"), &mut self.effect, Effect::Crt);
+
+        *self != old
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_size() {
+        let mut options = VideoOptions::default();
+        options.scale = 2.6;
+        assert_eq!(options.output_size(), (768, 720));
+
+        options.crop_overscan = true;
+        options.pixel_aspect = true;
+        assert_eq!(options.output_size(), (878, 672));
+        assert_eq!(options.to_nes_pixel([0.0, 0.0]), (0, 8));
+        assert_eq!(options.from_nes_pixel(0.0, 9.0), [0.0, 3.0]);
+
+        options.integer_scale = false;
+        assert_eq!(options.output_size(), (761, 582));
+    }
+
+    #[test]
+    fn fit_to_window() {
+        let mut options = VideoOptions::default();
+        assert_eq!(options.fit([1600.0, 900.0]).output_size(), (768, 720));
+
+        options.integer_scale = false;
+        options.pixel_aspect = true;
+        options.crop_overscan = true;
+        let (width, height) = options.fit([1600.0, 900.0]).output_size();
+        assert_eq!(height, 900);
+        assert!(width < 1600);
+    }
+}
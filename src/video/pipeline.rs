@@ -0,0 +1,315 @@
+use super::{Effect, ScaleFilter, Upscaler, VideoOptions};
+use crate::imgui_wgpu::Renderer;
+use crate::nes::ppu::SCREEN_SIZE;
+use imgui::TextureId;
+use wgpu::*;
+
+// Texture rows have to be aligned to this for buffer to texture copies
+const ROW_ALIGNMENT: usize = 256;
+// Source size, crop rect and modes, each as vec4
+const UNIFORM_SIZE: u64 = 48;
+
+fn compile(shader: &[u8]) -> Vec<u32> {
+    shader
+        .chunks(4)
+        .map(|bytes4| u32::from_le_bytes([bytes4[0], bytes4[1], bytes4[2], bytes4[3]]))
+        .collect()
+}
+
+fn create_sampler(device: &Device, filter: FilterMode) -> Sampler {
+    device.create_sampler(&SamplerDescriptor {
+        address_mode_u: AddressMode::ClampToEdge,
+        address_mode_v: AddressMode::ClampToEdge,
+        address_mode_w: AddressMode::ClampToEdge,
+        mag_filter: filter,
+        min_filter: filter,
+        mipmap_filter: FilterMode::Nearest,
+        lod_min_clamp: -100.0,
+        lod_max_clamp: 100.0,
+        compare: CompareFunction::Always,
+    })
+}
+
+/// Renders the game screen into a texture shown by imgui. The picture is cropped and
+/// scaled to the size from `VideoOptions` in one pass which also runs the selected
+/// upscaler and effect (see `screen.frag`).
+pub struct ScreenPipeline {
+    pipeline: RenderPipeline,
+    bind_group_layout: BindGroupLayout,
+    uniform_buffer: Buffer,
+    nearest_sampler: Sampler,
+    linear_sampler: Sampler,
+    source: Option<(wgpu::Texture, (u32, u32))>,
+    output: Option<(TextureId, (u32, u32))>,
+}
+
+impl ScreenPipeline {
+    pub fn new(device: &Device) -> Self {
+        let vs_module = device.create_shader_module(&compile(include_bytes!("screen.vert.spv")));
+        let fs_module = device.create_shader_module(&compile(include_bytes!("screen.frag.spv")));
+
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: UNIFORM_SIZE,
+            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            bindings: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: BindingType::UniformBuffer { dynamic: false },
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: BindingType::SampledTexture {
+                        multisampled: false,
+                        component_type: TextureComponentType::Float,
+                        dimension: TextureViewDimension::D2,
+                    },
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: BindingType::Sampler { comparison: false },
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            bind_group_layouts: &[&bind_group_layout],
+        });
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(RasterizationStateDescriptor {
+                front_face: FrontFace::Ccw,
+                cull_mode: CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: PrimitiveTopology::TriangleList,
+            color_states: &[ColorStateDescriptor {
+                format: TextureFormat::Rgba8Unorm,
+                color_blend: BlendDescriptor::REPLACE,
+                alpha_blend: BlendDescriptor::REPLACE,
+                write_mask: ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            // Fullscreen triangle is generated in the vertex shader
+            vertex_state: VertexStateDescriptor {
+                index_format: IndexFormat::Uint16,
+                vertex_buffers: &[],
+            },
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+
+        ScreenPipeline {
+            pipeline,
+            bind_group_layout,
+            uniform_buffer,
+            nearest_sampler: create_sampler(device, FilterMode::Nearest),
+            linear_sampler: create_sampler(device, FilterMode::Linear),
+            source: None,
+            output: None,
+        }
+    }
+
+    /// Texture with the last rendered picture.
+    pub fn output(&self) -> Option<TextureId> {
+        self.output.map(|(id, _)| id)
+    }
+
+    /// Uploads a frame of 0xRRGGBB pixels.
+    pub fn upload(
+        &mut self,
+        device: &Device,
+        queue: &mut Queue,
+        pixels: &[u32],
+        width: usize,
+        height: usize,
+    ) {
+        let size = (width as u32, height as u32);
+        if self.source.as_ref().map(|(_, s)| *s) != Some(size) {
+            let texture = device.create_texture(&TextureDescriptor {
+                label: None,
+                size: Extent3d {
+                    width: size.0,
+                    height: size.1,
+                    depth: 1,
+                },
+                array_layer_count: 1,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                usage: TextureUsage::SAMPLED | TextureUsage::COPY_DST,
+            });
+            self.source = Some((texture, size));
+        }
+
+        let row_bytes = (width * 4 + ROW_ALIGNMENT - 1) / ROW_ALIGNMENT * ROW_ALIGNMENT;
+        let mut data = vec![0; row_bytes * height];
+        for y in 0..height {
+            for x in 0..width {
+                let c = pixels[x + y * width];
+                let pos = y * row_bytes + x * 4;
+                data[pos..pos + 4].copy_from_slice(&[
+                    (c >> 16) as u8,
+                    (c >> 8) as u8,
+                    c as u8,
+                    0xFF,
+                ]);
+            }
+        }
+
+        let buffer = device.create_buffer_with_data(&data, BufferUsage::COPY_SRC);
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_texture(
+            BufferCopyView {
+                buffer: &buffer,
+                offset: 0,
+                bytes_per_row: row_bytes as u32,
+                rows_per_image: size.1,
+            },
+            TextureCopyView {
+                texture: &self.source.as_ref().unwrap().0,
+                mip_level: 0,
+                array_layer: 0,
+                origin: Origin3d { x: 0, y: 0, z: 0 },
+            },
+            Extent3d {
+                width: size.0,
+                height: size.1,
+                depth: 1,
+            },
+        );
+        queue.submit(&[encoder.finish()]);
+    }
+
+    /// Renders the last uploaded frame with `options` and returns the texture to draw.
+    pub fn render(
+        &mut self,
+        renderer: &mut Renderer,
+        device: &Device,
+        queue: &mut Queue,
+        options: &VideoOptions,
+    ) -> Option<TextureId> {
+        let (source, source_size) = self.source.as_ref()?;
+
+        // Output texture is recreated whenever the size changes
+        let size = options.output_size();
+        if self.output.map(|(_, s)| s) != Some(size) {
+            if let Some((id, _)) = self.output.take() {
+                renderer.remove_texture(id);
+            }
+            let id = renderer.create_render_target(device, size.0, size.1);
+            self.output = Some((id, size));
+        }
+        let output_id = self.output?.0;
+
+        // The source can be wider than the NES screen (NTSC filter), crop in UV
+        let (x, y, w, h) = options.crop_rect();
+        let (sw, sh) = (source_size.0 as f32, source_size.1 as f32);
+        let (nes_w, nes_h) = (SCREEN_SIZE.0 as f32, SCREEN_SIZE.1 as f32);
+        let upscaler = match options.upscaler {
+            Upscaler::None => 0.0,
+            Upscaler::Scale2x => 1.0,
+            Upscaler::Hq2x => 2.0,
+            Upscaler::Xbr => 3.0,
+        };
+        let effect = match options.effect {
+            Effect::None => 0.0,
+            Effect::Scanlines => 1.0,
+            Effect::Crt => 2.0,
+        };
+        let params: [f32; 12] = [
+            sw,
+            sh,
+            1.0 / sw,
+            1.0 / sh,
+            x as f32 / nes_w,
+            y as f32 / nes_h,
+            (x + w) as f32 / nes_w,
+            (y + h) as f32 / nes_h,
+            upscaler,
+            effect,
+            0.0,
+            0.0,
+        ];
+        let data: Vec<u8> = params
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect();
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        let uniforms = device.create_buffer_with_data(&data, BufferUsage::COPY_SRC);
+        encoder.copy_buffer_to_buffer(&uniforms, 0, &self.uniform_buffer, 0, UNIFORM_SIZE);
+
+        let source_view = source.create_default_view();
+        let sampler = match options.filter {
+            ScaleFilter::Nearest => &self.nearest_sampler,
+            ScaleFilter::Bilinear => &self.linear_sampler,
+        };
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.bind_group_layout,
+            bindings: &[
+                Binding {
+                    binding: 0,
+                    resource: BindingResource::Buffer {
+                        buffer: &self.uniform_buffer,
+                        range: 0..UNIFORM_SIZE,
+                    },
+                },
+                Binding {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&source_view),
+                },
+                Binding {
+                    binding: 2,
+                    resource: BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+
+        let target = renderer.texture_view(output_id)?;
+        {
+            let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+                color_attachments: &[RenderPassColorAttachmentDescriptor {
+                    attachment: &target,
+                    resolve_target: None,
+                    load_op: LoadOp::Clear,
+                    store_op: StoreOp::Store,
+                    clear_color: Color {
+                        r: 0.0,
+                        g: 0.0,
+                        b: 0.0,
+                        a: 1.0,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, &bind_group, &[]);
+            rpass.draw(0..3, 0..1);
+        }
+        queue.submit(&[encoder.finish()]);
+
+        Some(output_id)
+    }
+}
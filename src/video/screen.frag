@@ -0,0 +1,178 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform Params {
+  vec4 u_SourceSize; // width, height, 1/width, 1/height
+  vec4 u_Crop;       // visible source rect in UV: left, top, right, bottom
+  vec4 u_Mode;       // upscaler, effect
+};
+layout(set = 0, binding = 1) uniform texture2D u_Texture;
+layout(set = 0, binding = 2) uniform sampler u_Sampler;
+
+layout(location = 0) in vec2 v_UV;
+
+layout(location = 0) out vec4 o_Target;
+
+const float PI = 3.14159265;
+
+const int UPSCALER_SCALE2X = 1;
+const int UPSCALER_HQ2X = 2;
+const int UPSCALER_XBR = 3;
+
+const int EFFECT_SCANLINES = 1;
+const int EFFECT_CRT = 2;
+
+vec3 fetch(vec2 cell) {
+  return textureLod(sampler2D(u_Texture, u_Sampler), (cell + 0.5) * u_SourceSize.zw, 0.0).rgb;
+}
+
+bool same(vec3 a, vec3 b) {
+  return distance(a, b) < 0.001;
+}
+
+vec3 to_yuv(vec3 c) {
+  return vec3(
+    dot(c, vec3(0.299, 0.587, 0.114)),
+    dot(c, vec3(-0.169, -0.331, 0.5)),
+    dot(c, vec3(0.5, -0.419, -0.081)));
+}
+
+// hqx color similarity thresholds
+bool similar(vec3 a, vec3 b) {
+  vec3 d = abs(to_yuv(a) - to_yuv(b)) * 255.0;
+  return d.x <= 48.0 && d.y <= 7.0 && d.z <= 6.0;
+}
+
+// xBR weighted color distance
+float dist(vec3 a, vec3 b) {
+  vec3 d = abs(to_yuv(a) - to_yuv(b));
+  return 48.0 * d.x + 7.0 * d.y + 6.0 * d.z;
+}
+
+// Upscalers work on the quadrant of source pixel `cell` facing `dir`,
+// X and Y are the horizontal and vertical neighbours on that side.
+
+vec3 scale2x(vec2 cell, vec2 dir) {
+  vec3 E = fetch(cell);
+  vec3 X = fetch(cell + vec2(dir.x, 0.0));
+  vec3 Y = fetch(cell + vec2(0.0, dir.y));
+  vec3 X1 = fetch(cell - vec2(dir.x, 0.0));
+  vec3 Y1 = fetch(cell - vec2(0.0, dir.y));
+  if (same(X, Y) && !same(Y, X1) && !same(X, Y1)) {
+    return X;
+  }
+  return E;
+}
+
+vec3 hq2x(vec2 cell, vec2 dir) {
+  vec3 E = fetch(cell);
+  vec3 X = fetch(cell + vec2(dir.x, 0.0));
+  vec3 Y = fetch(cell + vec2(0.0, dir.y));
+  vec3 I = fetch(cell + dir);
+  bool ex = similar(E, X);
+  bool ey = similar(E, Y);
+  if (!ex && !ey && similar(X, Y)) {
+    // An edge cuts the corner
+    if (similar(I, X)) {
+      return (E * 2.0 + X * 3.0 + Y * 3.0) / 8.0;
+    }
+    return (E * 2.0 + X + Y) / 4.0;
+  }
+  if (!ex && !ey && !similar(E, I)) {
+    return (E * 3.0 + I) / 4.0;
+  }
+  return E;
+}
+
+vec3 xbr(vec2 cell, vec2 dir, vec2 fp) {
+  // Neighbourhood rotated so the interpolated corner is at the bottom right:
+  //    B  C
+  // D  E  F  F4
+  // G  H  I  I4
+  //    H5 I5
+  vec2 dx = vec2(dir.x, 0.0);
+  vec2 dy = vec2(0.0, dir.y);
+  vec3 E = fetch(cell);
+  vec3 B = fetch(cell - dy);
+  vec3 C = fetch(cell + dx - dy);
+  vec3 D = fetch(cell - dx);
+  vec3 F = fetch(cell + dx);
+  vec3 G = fetch(cell - dx + dy);
+  vec3 H = fetch(cell + dy);
+  vec3 I = fetch(cell + dx + dy);
+  vec3 F4 = fetch(cell + dx * 2.0);
+  vec3 I4 = fetch(cell + dx * 2.0 + dy);
+  vec3 H5 = fetch(cell + dy * 2.0);
+  vec3 I5 = fetch(cell + dx + dy * 2.0);
+
+  float edge_e = dist(E, C) + dist(E, G) + dist(I, F4) + dist(I, H5) + 4.0 * dist(H, F);
+  float edge_i = dist(H, D) + dist(H, I5) + dist(F, I4) + dist(F, B) + 4.0 * dist(E, I);
+  if (edge_e >= edge_i || same(E, F) || same(E, H)) {
+    return E;
+  }
+
+  vec3 px = dist(E, F) <= dist(E, H) ? F : H;
+  // Position inside the source pixel measured towards the corner
+  vec2 p = mix(vec2(1.0) - fp, fp, step(0.0, dir));
+  float alpha = clamp((p.x + p.y - 1.25) * 2.0, 0.0, 1.0);
+  return mix(E, px, alpha);
+}
+
+vec2 crt_warp(vec2 uv) {
+  vec2 cc = uv * 2.0 - 1.0;
+  cc *= 1.0 + (cc.yx * cc.yx) * vec2(0.031, 0.041);
+  return cc * 0.5 + 0.5;
+}
+
+void main() {
+  int upscaler = int(u_Mode.x);
+  int effect = int(u_Mode.y);
+
+  vec2 uv = v_UV;
+  if (effect == EFFECT_CRT) {
+    uv = crt_warp(uv);
+    if (uv.x < 0.0 || uv.y < 0.0 || uv.x > 1.0 || uv.y > 1.0) {
+      o_Target = vec4(0.0, 0.0, 0.0, 1.0);
+      return;
+    }
+  }
+
+  vec2 src_uv = mix(u_Crop.xy, u_Crop.zw, uv);
+  vec2 pos = src_uv * u_SourceSize.xy;
+  vec2 cell = floor(pos);
+  vec2 fp = pos - cell;
+  vec2 dir = vec2(fp.x < 0.5 ? -1.0 : 1.0, fp.y < 0.5 ? -1.0 : 1.0);
+
+  vec3 color;
+  if (upscaler == UPSCALER_SCALE2X) {
+    color = scale2x(cell, dir);
+  } else if (upscaler == UPSCALER_HQ2X) {
+    color = hq2x(cell, dir);
+  } else if (upscaler == UPSCALER_XBR) {
+    color = xbr(cell, dir, fp);
+  } else {
+    color = textureLod(sampler2D(u_Texture, u_Sampler), src_uv, 0.0).rgb;
+  }
+
+  if (effect == EFFECT_SCANLINES || effect == EFFECT_CRT) {
+    // Darken the gaps between source lines
+    color *= 0.65 + 0.35 * sin(fp.y * PI);
+  }
+  if (effect == EFFECT_CRT) {
+    // Aperture grille
+    float column = mod(gl_FragCoord.x, 3.0);
+    vec3 mask = vec3(0.85);
+    if (column < 1.0) {
+      mask.r = 1.1;
+    } else if (column < 2.0) {
+      mask.g = 1.1;
+    } else {
+      mask.b = 1.1;
+    }
+    color *= mask;
+    // Vignette
+    vec2 cc = uv * 2.0 - 1.0;
+    color *= 1.0 - 0.25 * dot(cc * cc, cc * cc);
+  }
+
+  o_Target = vec4(color, 1.0);
+}